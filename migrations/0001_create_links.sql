@@ -0,0 +1,28 @@
+-- Mirrors the SQLite schema; times are seconds since the Unix epoch.
+CREATE TABLE links (
+    token TEXT PRIMARY KEY,
+    url TEXT NOT NULL,
+    created_at BIGINT NOT NULL,
+    expires_at BIGINT,
+    hits BIGINT NOT NULL DEFAULT 0,
+    password_hash TEXT,
+    deleted BOOLEAN NOT NULL DEFAULT FALSE,
+    owner TEXT,
+    max_hits BIGINT,
+    -- JSON array of [url, weight] pairs, NULL for a plain link
+    destinations TEXT
+);
+CREATE INDEX links_url ON links (url);
+CREATE INDEX links_owner ON links (owner);
+
+CREATE TABLE clicks (
+    id BIGSERIAL PRIMARY KEY,
+    token TEXT NOT NULL,
+    at BIGINT NOT NULL,
+    referer TEXT,
+    user_agent TEXT
+);
+CREATE INDEX clicks_token ON clicks (token, id);
+
+-- Counter behind sequential tokens
+CREATE SEQUENCE link_sequence MINVALUE 0 START WITH 0;
@@ -0,0 +1,2 @@
+-- JSON array of [name, value] pairs added to the target's query, NULL for an untagged link
+ALTER TABLE links ADD COLUMN utm_params TEXT;
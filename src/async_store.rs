@@ -0,0 +1,244 @@
+//! Async face of the stores, which the handlers await.
+//!
+//! Every `StoreAccess` gets this trait for free through a blanket impl that
+//! calls straight through. That is exact for the in-memory `Store`; the SQLite
+//! and Redis stores still block the worker for the duration of a call until
+//! they implement `AsyncStoreAccess` natively instead, as the Postgres store does.
+
+use crate::store::{ClickInfo, LinkOptions, LinkPredicate, LinkRecord, StoreAccess, StoreStats};
+use crate::token::Token;
+use async_trait::async_trait;
+use color_eyre::eyre::Result;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+use url::Url;
+
+/// Same operations and contracts as `StoreAccess`, awaited.
+#[async_trait]
+pub trait AsyncStoreAccess: Send + Sync {
+    // Handlers register through `register_with_options`, these remain for tests
+    #[allow(dead_code)]
+    async fn register_url(&mut self, url: Url) -> Result<Token>;
+    #[allow(dead_code)]
+    async fn register_url_with_alias(&mut self, url: Url, alias: &str) -> Result<Token>;
+    #[allow(dead_code)]
+    async fn register_url_with_ttl(&mut self, url: Url, ttl: Duration) -> Result<Token>;
+    async fn register_with_options(
+        &mut self,
+        url: Url,
+        alias: Option<&str>,
+        ttl: Option<Duration>,
+        options: LinkOptions,
+    ) -> Result<Token>;
+    async fn register_or_get(&mut self, url: Url) -> Result<(Token, bool)>;
+    async fn register_batch(&mut self, urls: Vec<Url>, options: LinkOptions) -> Vec<Result<Token>>;
+    async fn resolve_token(&self, token: &str) -> Result<Url>;
+    async fn link_info(&self, token: &str) -> Result<LinkRecord>;
+    async fn get_record(&self, token: &str) -> Result<LinkRecord> {
+        self.link_info(token).await
+    }
+    async fn owner(&self, token: &str) -> Result<Option<String>>;
+    // Only tests read a bare counter since `link_stats` needs the whole record
+    #[allow(dead_code)]
+    async fn hit_count(&self, token: &str) -> Result<u64>;
+    async fn delete_token(&mut self, token: &str) -> Result<()>;
+    async fn restore_token(&mut self, token: &str) -> Result<()>;
+    async fn update_url(&mut self, token: &str, new_url: Url) -> Result<()>;
+    #[allow(dead_code)]
+    async fn set_password_hash(&mut self, token: &str, password_hash: Option<String>)
+        -> Result<()>;
+    #[allow(dead_code)]
+    async fn set_owner(&mut self, token: &str, owner: Option<String>) -> Result<()>;
+    #[allow(dead_code)]
+    async fn set_max_hits(&mut self, token: &str, max_hits: Option<u64>) -> Result<()>;
+    #[allow(dead_code)]
+    async fn set_destinations(&mut self, token: &str, destinations: Vec<(Url, u32)>) -> Result<()>;
+    #[allow(dead_code)]
+    async fn set_utm_params(
+        &mut self,
+        token: &str,
+        utm_params: Vec<(String, String)>,
+    ) -> Result<()>;
+    async fn purge_expired(&mut self, now: SystemTime) -> Result<usize>;
+    async fn clear(&mut self) -> Result<usize>;
+    async fn delete_matching(&mut self, matches: &LinkPredicate<'_>) -> Result<usize>;
+    async fn list(&self, offset: usize, limit: usize) -> Result<Vec<(Token, LinkRecord)>>;
+    async fn record_click(&self, token: &str, click: ClickInfo) -> Result<()>;
+    async fn clicks(&self, token: &str) -> Result<Vec<ClickInfo>>;
+    async fn export(&self) -> Result<Vec<(Token, LinkRecord)>>;
+    async fn to_json(&self) -> Result<Vec<u8>>;
+    async fn list_by_owner(&self, owner: &str) -> Result<Vec<(Token, LinkRecord)>>;
+    async fn check_alias(&self, alias: &str) -> Result<()>;
+    async fn top_by_hits(&self, n: usize) -> Result<Vec<(Token, LinkRecord)>>;
+    async fn import(&mut self, links: Vec<(String, Url)>) -> Vec<Result<Token>>;
+    async fn usage(&self, now: SystemTime) -> Result<HashMap<String, usize>>;
+    async fn stats(&self, now: SystemTime) -> Result<StoreStats>;
+}
+
+#[async_trait]
+impl<T: StoreAccess> AsyncStoreAccess for T {
+    async fn register_url(&mut self, url: Url) -> Result<Token> {
+        StoreAccess::register_url(self, url)
+    }
+
+    async fn register_url_with_alias(&mut self, url: Url, alias: &str) -> Result<Token> {
+        StoreAccess::register_url_with_alias(self, url, alias)
+    }
+
+    async fn register_url_with_ttl(&mut self, url: Url, ttl: Duration) -> Result<Token> {
+        StoreAccess::register_url_with_ttl(self, url, ttl)
+    }
+
+    async fn register_with_options(
+        &mut self,
+        url: Url,
+        alias: Option<&str>,
+        ttl: Option<Duration>,
+        options: LinkOptions,
+    ) -> Result<Token> {
+        StoreAccess::register_with_options(self, url, alias, ttl, options)
+    }
+
+    async fn register_or_get(&mut self, url: Url) -> Result<(Token, bool)> {
+        StoreAccess::register_or_get(self, url)
+    }
+
+    async fn register_batch(&mut self, urls: Vec<Url>, options: LinkOptions) -> Vec<Result<Token>> {
+        StoreAccess::register_batch(self, urls, options)
+    }
+
+    async fn resolve_token(&self, token: &str) -> Result<Url> {
+        StoreAccess::resolve_token(self, token)
+    }
+
+    async fn link_info(&self, token: &str) -> Result<LinkRecord> {
+        StoreAccess::link_info(self, token)
+    }
+
+    async fn get_record(&self, token: &str) -> Result<LinkRecord> {
+        StoreAccess::get_record(self, token)
+    }
+
+    async fn owner(&self, token: &str) -> Result<Option<String>> {
+        StoreAccess::owner(self, token)
+    }
+
+    async fn hit_count(&self, token: &str) -> Result<u64> {
+        StoreAccess::hit_count(self, token)
+    }
+
+    async fn delete_token(&mut self, token: &str) -> Result<()> {
+        StoreAccess::delete_token(self, token)
+    }
+
+    async fn restore_token(&mut self, token: &str) -> Result<()> {
+        StoreAccess::restore_token(self, token)
+    }
+
+    async fn update_url(&mut self, token: &str, new_url: Url) -> Result<()> {
+        StoreAccess::update_url(self, token, new_url)
+    }
+
+    async fn set_password_hash(
+        &mut self,
+        token: &str,
+        password_hash: Option<String>,
+    ) -> Result<()> {
+        StoreAccess::set_password_hash(self, token, password_hash)
+    }
+
+    async fn set_owner(&mut self, token: &str, owner: Option<String>) -> Result<()> {
+        StoreAccess::set_owner(self, token, owner)
+    }
+
+    async fn set_max_hits(&mut self, token: &str, max_hits: Option<u64>) -> Result<()> {
+        StoreAccess::set_max_hits(self, token, max_hits)
+    }
+
+    async fn set_destinations(&mut self, token: &str, destinations: Vec<(Url, u32)>) -> Result<()> {
+        StoreAccess::set_destinations(self, token, destinations)
+    }
+
+    async fn set_utm_params(
+        &mut self,
+        token: &str,
+        utm_params: Vec<(String, String)>,
+    ) -> Result<()> {
+        StoreAccess::set_utm_params(self, token, utm_params)
+    }
+
+    async fn purge_expired(&mut self, now: SystemTime) -> Result<usize> {
+        StoreAccess::purge_expired(self, now)
+    }
+
+    async fn clear(&mut self) -> Result<usize> {
+        StoreAccess::clear(self)
+    }
+
+    async fn delete_matching(&mut self, matches: &LinkPredicate<'_>) -> Result<usize> {
+        StoreAccess::delete_matching(self, matches)
+    }
+
+    async fn list(&self, offset: usize, limit: usize) -> Result<Vec<(Token, LinkRecord)>> {
+        StoreAccess::list(self, offset, limit)
+    }
+
+    async fn record_click(&self, token: &str, click: ClickInfo) -> Result<()> {
+        StoreAccess::record_click(self, token, click)
+    }
+
+    async fn clicks(&self, token: &str) -> Result<Vec<ClickInfo>> {
+        StoreAccess::clicks(self, token)
+    }
+
+    async fn export(&self) -> Result<Vec<(Token, LinkRecord)>> {
+        StoreAccess::export(self)
+    }
+
+    async fn to_json(&self) -> Result<Vec<u8>> {
+        StoreAccess::to_json(self)
+    }
+
+    async fn list_by_owner(&self, owner: &str) -> Result<Vec<(Token, LinkRecord)>> {
+        StoreAccess::list_by_owner(self, owner)
+    }
+
+    async fn check_alias(&self, alias: &str) -> Result<()> {
+        StoreAccess::check_alias(self, alias)
+    }
+
+    async fn top_by_hits(&self, n: usize) -> Result<Vec<(Token, LinkRecord)>> {
+        StoreAccess::top_by_hits(self, n)
+    }
+
+    async fn import(&mut self, links: Vec<(String, Url)>) -> Vec<Result<Token>> {
+        StoreAccess::import(self, links)
+    }
+
+    async fn usage(&self, now: SystemTime) -> Result<HashMap<String, usize>> {
+        StoreAccess::usage(self, now)
+    }
+
+    async fn stats(&self, now: SystemTime) -> Result<StoreStats> {
+        StoreAccess::stats(self, now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::Store;
+
+    #[tokio::test]
+    async fn test_store_through_async_trait() -> Result<()> {
+        let mut store: Box<dyn AsyncStoreAccess> = Box::new(Store::default());
+        let url = Url::parse("https://example.com")?;
+
+        let token = store.register_url(url.clone()).await?;
+        assert_eq!(store.resolve_token(token.as_str()).await?, url);
+        assert_eq!(store.hit_count(token.as_str()).await?, 1);
+        store.delete_token(token.as_str()).await?;
+        assert!(store.resolve_token(token.as_str()).await.is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,57 @@
+use std::time::SystemTime;
+
+/// Source of the current time, injectable so expiry can be tested.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> SystemTime;
+}
+
+#[derive(Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+#[cfg(test)]
+pub use mock::MockClock;
+
+#[cfg(test)]
+mod mock {
+    use super::*;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    /// Clock that only moves when told to.
+    pub struct MockClock(Mutex<SystemTime>);
+
+    impl MockClock {
+        pub fn new(now: SystemTime) -> Self {
+            Self(Mutex::new(now))
+        }
+
+        pub fn advance(&self, by: Duration) {
+            *self.0.lock().unwrap() += by;
+        }
+    }
+
+    impl Clock for MockClock {
+        fn now(&self) -> SystemTime {
+            *self.0.lock().unwrap()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_mock_clock_advances() {
+        let clock = MockClock::new(SystemTime::UNIX_EPOCH);
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(clock.now(), SystemTime::UNIX_EPOCH + Duration::from_secs(5));
+    }
+}
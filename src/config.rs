@@ -0,0 +1,789 @@
+use color_eyre::{eyre::eyre, eyre::WrapErr, Result};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use url::{Host, Url};
+
+use crate::normalize::Normalization;
+use crate::token::{Charset, Token, TokenConfig, TokenStrategy};
+
+/// Status code `resolve_url` redirects with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RedirectKind {
+    /// `303 See Other`, the historical behaviour.
+    #[default]
+    SeeOther,
+    /// `302 Found`; clients keep asking the shortener, so hits stay counted.
+    Temporary,
+    /// `301 Moved Permanently`; browsers and crawlers may cache it and skip the shortener.
+    Permanent,
+    /// `307 Temporary Redirect`, which keeps the request method and body.
+    TemporaryPreserveMethod,
+}
+
+impl FromStr for RedirectKind {
+    type Err = color_eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "see_other" | "303" => Ok(RedirectKind::SeeOther),
+            "temporary" | "302" => Ok(RedirectKind::Temporary),
+            "permanent" | "301" => Ok(RedirectKind::Permanent),
+            "temporary_preserve_method" | "307" => Ok(RedirectKind::TemporaryPreserveMethod),
+            _ => Err(eyre!("Unknown redirect kind: {s}")),
+        }
+    }
+}
+
+impl RedirectKind {
+    /// Whether clients may remember the redirect, see `Config::redirect_cache_control`.
+    pub fn is_permanent(self) -> bool {
+        self == RedirectKind::Permanent
+    }
+}
+
+/// A `Cache-Control` header value, checked to be something a header can carry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheControl(String);
+
+impl CacheControl {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for CacheControl {
+    type Err = color_eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if !s.chars().all(|c| c == ' ' || c.is_ascii_graphic()) {
+            return Err(eyre!("Cache-Control must be printable ASCII, got {s:?}"));
+        }
+        Ok(Self(s.to_string()))
+    }
+}
+
+/// What following an unknown token answers.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum NotFoundBehavior {
+    /// `404` with the usual error body.
+    #[default]
+    Status,
+    /// `404` with this HTML page.
+    Page(String),
+    /// `302 Found` to this URL, e.g. the homepage; never permanent, the token may be taken later.
+    Redirect(Url),
+}
+
+/// What following an expired link answers.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum ExpiredBehavior {
+    /// `410 Gone`, telling clients the link existed.
+    #[default]
+    Gone,
+    /// Whatever an unknown token gets, see `NotFoundBehavior`.
+    NotFound,
+    /// `302 Found` to this URL, e.g. a page explaining that the link expired.
+    Redirect(Url),
+}
+
+impl FromStr for ExpiredBehavior {
+    type Err = color_eyre::Report;
+
+    /// `gone` (or `410`), `not_found` (or `404`), or an absolute URL to redirect to.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "gone" | "410" => Ok(ExpiredBehavior::Gone),
+            "not_found" | "404" => Ok(ExpiredBehavior::NotFound),
+            _ => Ok(ExpiredBehavior::Redirect(Url::parse(s).wrap_err_with(
+                || format!("Expected gone, not_found or a URL, got {s:?}"),
+            )?)),
+        }
+    }
+}
+
+/// Settings read once at startup, see `Config::from_env` for the variables.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    /// Length of freshly generated tokens.
+    pub token_length: usize,
+    /// Whether tokens are random or a counter.
+    pub token_strategy: TokenStrategy,
+    /// Characters generated tokens are made of and parsed tokens are checked against.
+    pub token_charset: Charset,
+    /// Public URL short links are built on; `None` derives it from request headers.
+    pub base_url: Option<Url>,
+    /// URL schemes accepted on registration.
+    pub allowed_schemes: Vec<String>,
+    /// Longest target URL accepted on registration, in bytes.
+    pub max_url_length: usize,
+    /// Drop a trailing `/` from target paths before storing them, see `Normalization`.
+    pub strip_trailing_slash: bool,
+    /// Sort target query parameters by name before storing them, see `Normalization`.
+    pub sort_query: bool,
+    /// Keep the time, referer and user agent of each redirect for `GET /{token}/clicks`.
+    pub record_clicks: bool,
+    /// Keys accepted as `Authorization: Bearer <key>`; empty leaves registration open.
+    pub api_keys: HashSet<String>,
+    /// Reject targets on loopback, private or link-local addresses.
+    pub block_private_hosts: bool,
+    /// Origins allowed to call the API from a browser; empty allows any origin.
+    pub cors_origins: Vec<String>,
+    /// Status code used when following a short link.
+    pub redirect_kind: RedirectKind,
+    /// Words never used as tokens or aliases, on top of `TokenConfig::DEFAULT_RESERVED`.
+    pub reserved_words: HashSet<String>,
+    /// Most live links a single API key may own; `None` is unlimited.
+    pub link_quota: Option<usize>,
+    /// How often expired links are purged from the store; `None` never purges them.
+    pub sweep_interval: Option<Duration>,
+    /// Seconds a countdown page is shown before following a link; `None` redirects right away.
+    pub interstitial_seconds: Option<u64>,
+    /// Refuse to shorten a URL that already has a live link, with `409`.
+    pub unique_urls: bool,
+    /// Answer a plain registration of an already shortened URL with its existing link,
+    /// see `StoreAccess::register_or_get`.
+    pub dedup: bool,
+    /// How long an `Idempotency-Key` keeps answering with the link it first registered.
+    pub idempotency_window: Duration,
+    /// Namespaces whose links live at `/{namespace}/{token}`, each with tokens of its own.
+    pub namespaces: HashSet<String>,
+    /// File the in-memory store is saved to on shutdown and loaded from on startup.
+    pub snapshot_path: Option<PathBuf>,
+    /// Answer to unknown tokens.
+    pub not_found_behavior: NotFoundBehavior,
+    /// Shortest alias accepted on registration; generated tokens keep `token_length`.
+    pub min_alias_length: usize,
+    /// Believe `x-forwarded-*` headers; only turn on behind a proxy that overwrites them,
+    /// or clients can pick the host of returned short links and dodge rate limits. Left
+    /// off behind a proxy, every client shares the proxy's rate limit.
+    pub trusted_proxy: bool,
+    /// Store tokens lowercased and look them up ignoring case.
+    pub case_insensitive_tokens: bool,
+    /// Hosts targets must be on, e.g. `example.com` or `*.example.com` for any of its
+    /// subdomains; empty allows any host.
+    pub domain_allowlist: Vec<String>,
+    /// Where `GET /` redirects to instead of showing the landing page.
+    pub root_redirect: Option<Url>,
+    /// Lowercase substrings generated tokens must not contain, see `TokenConfig::blocklist`.
+    pub token_blocklist: Vec<String>,
+    /// Answer to links whose expiry has passed.
+    pub expired_behavior: ExpiredBehavior,
+    /// `Cache-Control` of `RedirectKind::Permanent` link redirects, letting CDNs cache them.
+    pub permanent_cache_control: CacheControl,
+    /// `Cache-Control` of the other link redirects, which must reach the shortener to count.
+    pub temporary_cache_control: CacheControl,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            token_length: TokenConfig::default().length,
+            token_strategy: TokenStrategy::default(),
+            token_charset: Charset::default(),
+            base_url: None,
+            allowed_schemes: Self::DEFAULT_ALLOWED_SCHEMES
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+            max_url_length: Self::DEFAULT_MAX_URL_LENGTH,
+            strip_trailing_slash: false,
+            sort_query: false,
+            record_clicks: false,
+            api_keys: HashSet::new(),
+            block_private_hosts: false,
+            cors_origins: Vec::new(),
+            redirect_kind: RedirectKind::default(),
+            reserved_words: HashSet::new(),
+            link_quota: None,
+            sweep_interval: Some(Self::DEFAULT_SWEEP_INTERVAL),
+            interstitial_seconds: None,
+            unique_urls: false,
+            dedup: false,
+            idempotency_window: Self::DEFAULT_IDEMPOTENCY_WINDOW,
+            namespaces: HashSet::new(),
+            snapshot_path: None,
+            not_found_behavior: NotFoundBehavior::default(),
+            min_alias_length: Self::DEFAULT_MIN_ALIAS_LENGTH,
+            trusted_proxy: false,
+            case_insensitive_tokens: false,
+            domain_allowlist: Vec::new(),
+            root_redirect: None,
+            token_blocklist: Vec::new(),
+            expired_behavior: ExpiredBehavior::default(),
+            permanent_cache_control: CacheControl("public, max-age=86400".to_string()),
+            temporary_cache_control: CacheControl("no-store".to_string()),
+        }
+    }
+}
+
+impl Config {
+    const DEFAULT_ALLOWED_SCHEMES: [&str; 2] = ["http", "https"];
+    const DEFAULT_MAX_URL_LENGTH: usize = 2048;
+    const DEFAULT_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+    const DEFAULT_IDEMPOTENCY_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+    const DEFAULT_MIN_ALIAS_LENGTH: usize = 3;
+    /// Second path segments already routed under `/{token}`, so no namespace can use them.
+    const NAMESPACE_RESERVED: [&str; 7] = [
+        "clicks", "info", "preview", "qr", "redirect", "restore", "stats",
+    ];
+
+    /// Reads the `SHORTENER_*` environment variables, falling back to defaults for unset ones.
+    ///
+    /// - `SHORTENER_TOKEN_LENGTH`
+    /// - `SHORTENER_TOKEN_STRATEGY`, `random` or `sequential`
+    /// - `SHORTENER_TOKEN_CHARSET`, `alphanumeric` or `unambiguous`
+    /// - `SHORTENER_BASE_URL`, keep a trailing slash to preserve a path prefix
+    /// - `SHORTENER_ALLOWED_SCHEMES`, comma-separated
+    /// - `SHORTENER_MAX_URL_LENGTH`
+    /// - `SHORTENER_STRIP_TRAILING_SLASH`, `true` or `false`
+    /// - `SHORTENER_SORT_QUERY`, `true` or `false`
+    /// - `SHORTENER_RECORD_CLICKS`, `true` or `false`
+    /// - `SHORTENER_API_KEYS`, comma-separated
+    /// - `SHORTENER_BLOCK_PRIVATE_HOSTS`, `true` or `false`
+    /// - `SHORTENER_CORS_ORIGINS`, comma-separated, e.g. `https://app.example.com`
+    /// - `SHORTENER_REDIRECT_KIND`, `see_other`, `temporary`, `permanent` or
+    ///   `temporary_preserve_method` (or the status code itself)
+    /// - `SHORTENER_RESERVED_WORDS`, comma-separated, matched ignoring case
+    /// - `SHORTENER_LINK_QUOTA`, live links allowed per API key
+    /// - `SHORTENER_SWEEP_INTERVAL_SECS`, `0` turns sweeping off
+    /// - `SHORTENER_INTERSTITIAL_SECONDS`
+    /// - `SHORTENER_UNIQUE_URLS`, `true` or `false`; not supported by the Redis store
+    /// - `SHORTENER_DEDUP`, `true` or `false`; registrations with an API key, password,
+    ///   hit limit, destinations or UTM parameters always get a link of their own
+    /// - `SHORTENER_IDEMPOTENCY_WINDOW_SECS`
+    /// - `SHORTENER_NAMESPACES`, comma-separated, following the alias rules; not
+    ///   supported by the Redis store
+    /// - `SHORTENER_SNAPSHOT_PATH`, only used by the in-memory store
+    /// - `SHORTENER_NOT_FOUND_REDIRECT`, a URL unknown tokens redirect to
+    /// - `SHORTENER_NOT_FOUND_PAGE`, an HTML file served with unknown tokens' `404`;
+    ///   read once here, and exclusive with `SHORTENER_NOT_FOUND_REDIRECT`
+    /// - `SHORTENER_MIN_ALIAS_LENGTH`
+    /// - `SHORTENER_TRUSTED_PROXY`, `true` or `false`; set it to `true` behind Shuttle,
+    ///   which terminates TLS in front of the app and sets the `x-forwarded-*` headers
+    /// - `SHORTENER_CASE_INSENSITIVE_TOKENS`, `true` or `false`; only supported by the
+    ///   in-memory and JSON stores
+    /// - `SHORTENER_DOMAIN_ALLOWLIST`, comma-separated hosts, `*.` in front allowing subdomains
+    /// - `SHORTENER_ROOT_REDIRECT`, a URL `GET /` redirects to
+    /// - `SHORTENER_TOKEN_BLOCKLIST`, a file of substrings generated tokens avoid, one per
+    ///   line, matched ignoring case; blank lines and lines starting with `#` are skipped.
+    ///   Read once here
+    /// - `SHORTENER_EXPIRED_BEHAVIOR`, `gone`, `not_found` or a URL expired links redirect to
+    /// - `SHORTENER_PERMANENT_CACHE_CONTROL`, e.g. `public, max-age=86400`, the default
+    /// - `SHORTENER_TEMPORARY_CACHE_CONTROL`, `no-store` by default
+    pub fn from_env() -> Result<Self> {
+        Self::from_vars(std::env::vars())
+    }
+
+    /// Like `from_env`, but reading from the given name/value pairs.
+    pub fn from_vars(vars: impl IntoIterator<Item = (String, String)>) -> Result<Self> {
+        let vars: HashMap<String, String> = vars.into_iter().collect();
+        let defaults = Self::default();
+
+        let token_length =
+            parse_var(&vars, "SHORTENER_TOKEN_LENGTH")?.unwrap_or(defaults.token_length);
+        if !(1..=Token::MAX_TOKEN_LENGTH).contains(&token_length) {
+            return Err(eyre!(
+                "SHORTENER_TOKEN_LENGTH must be between 1 and {}",
+                Token::MAX_TOKEN_LENGTH
+            ));
+        }
+
+        let token_strategy =
+            parse_eyre_var(&vars, "SHORTENER_TOKEN_STRATEGY")?.unwrap_or(defaults.token_strategy);
+        let token_charset =
+            parse_eyre_var(&vars, "SHORTENER_TOKEN_CHARSET")?.unwrap_or(defaults.token_charset);
+
+        let allowed_schemes = match list_var(&vars, "SHORTENER_ALLOWED_SCHEMES") {
+            Some(schemes) if schemes.is_empty() => {
+                return Err(eyre!("SHORTENER_ALLOWED_SCHEMES must not be empty"))
+            }
+            Some(schemes) => schemes.into_iter().map(|s| s.to_lowercase()).collect(),
+            None => defaults.allowed_schemes,
+        };
+
+        let cors_origins = list_var(&vars, "SHORTENER_CORS_ORIGINS")
+            .unwrap_or_default()
+            .into_iter()
+            .map(|origin| {
+                let url = Url::parse(&origin).wrap_err_with(|| {
+                    format!("Invalid SHORTENER_CORS_ORIGINS entry: {origin:?}")
+                })?;
+                // Browsers send the origin without a trailing slash
+                Ok(url.origin().ascii_serialization())
+            })
+            .collect::<Result<_>>()?;
+
+        let min_alias_length =
+            parse_var(&vars, "SHORTENER_MIN_ALIAS_LENGTH")?.unwrap_or(defaults.min_alias_length);
+        if !(1..=Token::ALIAS_MAX_LENGTH).contains(&min_alias_length) {
+            return Err(eyre!(
+                "SHORTENER_MIN_ALIAS_LENGTH must be between 1 and {}",
+                Token::ALIAS_MAX_LENGTH
+            ));
+        }
+
+        let domain_allowlist = list_var(&vars, "SHORTENER_DOMAIN_ALLOWLIST")
+            .unwrap_or_default()
+            .iter()
+            .map(|entry| {
+                parse_allowed_domain(entry).wrap_err_with(|| {
+                    format!("Invalid SHORTENER_DOMAIN_ALLOWLIST entry: {entry:?}")
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        let token_blocklist = match parse_var::<PathBuf>(&vars, "SHORTENER_TOKEN_BLOCKLIST")? {
+            Some(path) => read_blocklist(&path)?,
+            None => defaults.token_blocklist,
+        };
+
+        let namespaces: HashSet<String> = list_var(&vars, "SHORTENER_NAMESPACES")
+            .unwrap_or_default()
+            .into_iter()
+            .collect();
+
+        let not_found_behavior = match (
+            parse_var(&vars, "SHORTENER_NOT_FOUND_REDIRECT")?,
+            parse_var::<PathBuf>(&vars, "SHORTENER_NOT_FOUND_PAGE")?,
+        ) {
+            (Some(_), Some(_)) => {
+                return Err(eyre!(
+                    "SHORTENER_NOT_FOUND_REDIRECT and SHORTENER_NOT_FOUND_PAGE can't both be set"
+                ))
+            }
+            (Some(url), None) => NotFoundBehavior::Redirect(url),
+            (None, Some(path)) => {
+                NotFoundBehavior::Page(fs::read_to_string(&path).wrap_err_with(|| {
+                    format!("Failed to read SHORTENER_NOT_FOUND_PAGE {}", path.display())
+                })?)
+            }
+            (None, None) => defaults.not_found_behavior,
+        };
+
+        let config = Self {
+            token_length,
+            token_strategy,
+            token_charset,
+            base_url: parse_var(&vars, "SHORTENER_BASE_URL")?,
+            allowed_schemes,
+            max_url_length: parse_var(&vars, "SHORTENER_MAX_URL_LENGTH")?
+                .unwrap_or(defaults.max_url_length),
+            strip_trailing_slash: parse_var(&vars, "SHORTENER_STRIP_TRAILING_SLASH")?
+                .unwrap_or(defaults.strip_trailing_slash),
+            sort_query: parse_var(&vars, "SHORTENER_SORT_QUERY")?.unwrap_or(defaults.sort_query),
+            record_clicks: parse_var(&vars, "SHORTENER_RECORD_CLICKS")?
+                .unwrap_or(defaults.record_clicks),
+            api_keys: list_var(&vars, "SHORTENER_API_KEYS")
+                .unwrap_or_default()
+                .into_iter()
+                .collect(),
+            block_private_hosts: parse_var(&vars, "SHORTENER_BLOCK_PRIVATE_HOSTS")?
+                .unwrap_or(defaults.block_private_hosts),
+            cors_origins,
+            redirect_kind: parse_eyre_var(&vars, "SHORTENER_REDIRECT_KIND")?
+                .unwrap_or(defaults.redirect_kind),
+            reserved_words: list_var(&vars, "SHORTENER_RESERVED_WORDS")
+                .unwrap_or_default()
+                .into_iter()
+                .map(|word| word.to_ascii_lowercase())
+                .collect(),
+            link_quota: parse_var(&vars, "SHORTENER_LINK_QUOTA")?,
+            sweep_interval: match parse_var(&vars, "SHORTENER_SWEEP_INTERVAL_SECS")? {
+                Some(0) => None,
+                Some(secs) => Some(Duration::from_secs(secs)),
+                None => defaults.sweep_interval,
+            },
+            interstitial_seconds: parse_var(&vars, "SHORTENER_INTERSTITIAL_SECONDS")?,
+            unique_urls: parse_var(&vars, "SHORTENER_UNIQUE_URLS")?.unwrap_or(defaults.unique_urls),
+            dedup: parse_var(&vars, "SHORTENER_DEDUP")?.unwrap_or(defaults.dedup),
+            idempotency_window: parse_var(&vars, "SHORTENER_IDEMPOTENCY_WINDOW_SECS")?
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.idempotency_window),
+            namespaces,
+            snapshot_path: parse_var(&vars, "SHORTENER_SNAPSHOT_PATH")?,
+            not_found_behavior,
+            min_alias_length,
+            trusted_proxy: parse_var(&vars, "SHORTENER_TRUSTED_PROXY")?
+                .unwrap_or(defaults.trusted_proxy),
+            case_insensitive_tokens: parse_var(&vars, "SHORTENER_CASE_INSENSITIVE_TOKENS")?
+                .unwrap_or(defaults.case_insensitive_tokens),
+            domain_allowlist,
+            root_redirect: parse_var(&vars, "SHORTENER_ROOT_REDIRECT")?,
+            token_blocklist,
+            expired_behavior: parse_eyre_var(&vars, "SHORTENER_EXPIRED_BEHAVIOR")?
+                .unwrap_or(defaults.expired_behavior),
+            permanent_cache_control: parse_eyre_var(&vars, "SHORTENER_PERMANENT_CACHE_CONTROL")?
+                .unwrap_or(defaults.permanent_cache_control),
+            temporary_cache_control: parse_eyre_var(&vars, "SHORTENER_TEMPORARY_CACHE_CONTROL")?
+                .unwrap_or(defaults.temporary_cache_control),
+        };
+        let token_config = config.token_config();
+        for namespace in &config.namespaces {
+            Token::from_alias(namespace)
+                .wrap_err_with(|| format!("Invalid SHORTENER_NAMESPACES entry: {namespace:?}"))?;
+            // `/admin/...` and friends would shadow the namespace's links
+            if token_config.is_reserved(namespace) {
+                return Err(eyre!(
+                    "SHORTENER_NAMESPACES entry {namespace:?} is a reserved word"
+                ));
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn token_config(&self) -> TokenConfig {
+        let mut config = TokenConfig {
+            length: self.token_length,
+            strategy: self.token_strategy,
+            charset: self.token_charset,
+            ..Default::default()
+        };
+        config.reserved.extend(self.reserved_words.iter().cloned());
+        config.blocklist = self.token_blocklist.clone();
+        config
+    }
+
+    /// How target URLs are rewritten before they are stored.
+    pub fn normalization(&self) -> Normalization {
+        Normalization {
+            strip_trailing_slash: self.strip_trailing_slash,
+            sort_query: self.sort_query,
+        }
+    }
+
+    /// `Cache-Control` sent along with link redirects of `redirect_kind`.
+    pub fn redirect_cache_control(&self) -> &CacheControl {
+        if self.redirect_kind.is_permanent() {
+            &self.permanent_cache_control
+        } else {
+            &self.temporary_cache_control
+        }
+    }
+
+    /// `token_config` for the stores behind `namespaces`.
+    pub fn namespace_token_config(&self) -> TokenConfig {
+        let mut config = self.token_config();
+        config
+            .reserved
+            .extend(Self::NAMESPACE_RESERVED.iter().map(|word| word.to_string()));
+        config
+    }
+}
+
+/// Parses `name` if it is set to a non-blank value.
+fn parse_var<T>(vars: &HashMap<String, String>, name: &str) -> Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    match vars.get(name).map(|value| value.trim()) {
+        Some(value) if !value.is_empty() => value
+            .parse()
+            .map(Some)
+            .wrap_err_with(|| format!("Invalid {name}: {value:?}")),
+        _ => Ok(None),
+    }
+}
+
+/// `parse_var` for the types of this crate, whose `FromStr` errors are eyre reports.
+fn parse_eyre_var<T>(vars: &HashMap<String, String>, name: &str) -> Result<Option<T>>
+where
+    T: FromStr<Err = color_eyre::Report>,
+{
+    match vars.get(name).map(|value| value.trim()) {
+        Some(value) if !value.is_empty() => value
+            .parse()
+            .map(Some)
+            .wrap_err_with(|| format!("Invalid {name}")),
+        _ => Ok(None),
+    }
+}
+
+/// The substrings listed in `path`, see `SHORTENER_TOKEN_BLOCKLIST`.
+fn read_blocklist(path: &Path) -> Result<Vec<String>> {
+    let list = fs::read_to_string(path).wrap_err_with(|| {
+        format!(
+            "Failed to read SHORTENER_TOKEN_BLOCKLIST {}",
+            path.display()
+        )
+    })?;
+    Ok(list
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_ascii_lowercase)
+        .collect())
+}
+
+/// Normalizes a host as `Url` would, keeping a leading `*.`; `münchen.de` becomes
+/// `xn--mnchen-3ya.de`, so entries compare equal to the hosts of parsed targets.
+fn parse_allowed_domain(entry: &str) -> Result<String> {
+    let (wildcard, host) = match entry.strip_prefix("*.") {
+        Some(parent) => ("*.", parent),
+        None => ("", entry),
+    };
+    if host.contains('*') {
+        return Err(eyre!("Only a leading `*.` is supported"));
+    }
+    let host = Host::parse(host.trim_end_matches('.'))?;
+    Ok(format!("{wildcard}{host}"))
+}
+
+/// Splits a comma-separated variable, dropping blank entries.
+fn list_var(vars: &HashMap<String, String>, name: &str) -> Option<Vec<String>> {
+    vars.get(name).map(|value| {
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(String::from)
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_pairs(pairs: &[(&str, &str)]) -> Result<Config> {
+        Config::from_vars(
+            pairs
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string())),
+        )
+    }
+
+    #[test]
+    fn test_from_vars() {
+        let config = from_pairs(&[
+            ("SHORTENER_TOKEN_LENGTH", "8"),
+            ("SHORTENER_TOKEN_STRATEGY", "sequential"),
+            ("SHORTENER_TOKEN_CHARSET", "unambiguous"),
+            ("SHORTENER_BASE_URL", "https://sho.rt/s/"),
+            ("SHORTENER_ALLOWED_SCHEMES", "https, FTP"),
+            ("SHORTENER_MAX_URL_LENGTH", "512"),
+            ("SHORTENER_STRIP_TRAILING_SLASH", "true"),
+            ("SHORTENER_SORT_QUERY", "true"),
+            ("SHORTENER_RECORD_CLICKS", "true"),
+            ("SHORTENER_API_KEYS", "alpha,,beta "),
+            ("SHORTENER_BLOCK_PRIVATE_HOSTS", "true"),
+            (
+                "SHORTENER_CORS_ORIGINS",
+                "https://app.example.com/, http://localhost:5173",
+            ),
+            ("SHORTENER_REDIRECT_KIND", "Permanent"),
+            ("SHORTENER_RESERVED_WORDS", "Login, api"),
+            ("SHORTENER_LINK_QUOTA", "100"),
+            ("SHORTENER_SWEEP_INTERVAL_SECS", "300"),
+            ("SHORTENER_INTERSTITIAL_SECONDS", "5"),
+            ("SHORTENER_UNIQUE_URLS", "true"),
+            ("SHORTENER_DEDUP", "true"),
+            ("SHORTENER_IDEMPOTENCY_WINDOW_SECS", "600"),
+            ("SHORTENER_NAMESPACES", "docs, blog"),
+            ("SHORTENER_SNAPSHOT_PATH", "/data/links.json"),
+            ("SHORTENER_NOT_FOUND_REDIRECT", "https://example.com/"),
+            ("SHORTENER_MIN_ALIAS_LENGTH", "5"),
+            ("SHORTENER_TRUSTED_PROXY", "true"),
+            ("SHORTENER_CASE_INSENSITIVE_TOKENS", "true"),
+            (
+                "SHORTENER_DOMAIN_ALLOWLIST",
+                "*.MyCompany.com, münchen.de., 10.0.0.1",
+            ),
+            ("SHORTENER_ROOT_REDIRECT", "https://mycompany.com/"),
+            ("SHORTENER_EXPIRED_BEHAVIOR", "NOT_FOUND"),
+            ("SHORTENER_PERMANENT_CACHE_CONTROL", "public, max-age=3600"),
+            ("UNRELATED", "ignored"),
+        ])
+        .unwrap();
+
+        assert_eq!(config.token_length, 8);
+        assert_eq!(config.token_config().length, 8);
+        assert_eq!(config.token_config().strategy, TokenStrategy::Sequential);
+        assert_eq!(config.token_config().charset, Charset::Unambiguous);
+        assert!(config.token_config().is_reserved("login"));
+        assert!(config.token_config().is_reserved("api"));
+        assert!(config.token_config().is_reserved("healthz"));
+        assert!(!config.token_config().is_reserved("stats"));
+        assert!(config.namespace_token_config().is_reserved("stats"));
+        assert!(config.namespace_token_config().is_reserved("login"));
+        assert!(config.normalization().strip_trailing_slash);
+        assert!(config.normalization().sort_query);
+        assert_eq!(config.base_url.unwrap().as_str(), "https://sho.rt/s/");
+        assert_eq!(config.allowed_schemes, vec!["https", "ftp"]);
+        assert_eq!(config.max_url_length, 512);
+        assert!(config.record_clicks);
+        assert_eq!(
+            config.api_keys,
+            HashSet::from(["alpha".to_string(), "beta".to_string()])
+        );
+        assert!(config.block_private_hosts);
+        assert_eq!(
+            config.cors_origins,
+            vec!["https://app.example.com", "http://localhost:5173"]
+        );
+        assert_eq!(config.redirect_kind, RedirectKind::Permanent);
+        assert_eq!(config.link_quota, Some(100));
+        assert_eq!(config.sweep_interval, Some(Duration::from_secs(300)));
+        assert_eq!(config.interstitial_seconds, Some(5));
+        assert!(config.unique_urls);
+        assert!(config.dedup);
+        assert_eq!(config.idempotency_window, Duration::from_secs(600));
+        assert_eq!(
+            config.namespaces,
+            HashSet::from(["docs".to_string(), "blog".to_string()])
+        );
+        assert_eq!(
+            config.snapshot_path,
+            Some(PathBuf::from("/data/links.json"))
+        );
+        assert_eq!(
+            config.not_found_behavior,
+            NotFoundBehavior::Redirect(Url::parse("https://example.com/").unwrap())
+        );
+        assert_eq!(config.min_alias_length, 5);
+        assert!(config.trusted_proxy);
+        assert!(config.case_insensitive_tokens);
+        assert_eq!(
+            config.domain_allowlist,
+            vec!["*.mycompany.com", "xn--mnchen-3ya.de", "10.0.0.1"]
+        );
+        assert_eq!(
+            config.root_redirect,
+            Some(Url::parse("https://mycompany.com/").unwrap())
+        );
+        assert_eq!(config.expired_behavior, ExpiredBehavior::NotFound);
+        assert_eq!(
+            config.permanent_cache_control.as_str(),
+            "public, max-age=3600"
+        );
+        assert_eq!(config.temporary_cache_control.as_str(), "no-store");
+    }
+
+    #[test]
+    fn test_from_vars_reads_not_found_page() {
+        let path = std::env::temp_dir().join(format!("shortener-404-{}.html", std::process::id()));
+        fs::write(&path, "<h1>Nothing here</h1>").unwrap();
+        let config = from_pairs(&[("SHORTENER_NOT_FOUND_PAGE", path.to_str().unwrap())]);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            config.unwrap().not_found_behavior,
+            NotFoundBehavior::Page("<h1>Nothing here</h1>".to_string())
+        );
+    }
+
+    #[test]
+    fn test_from_vars_reads_token_blocklist() {
+        let path =
+            std::env::temp_dir().join(format!("shortener-blocklist-{}.txt", std::process::id()));
+        fs::write(&path, "# Offensive\nBad\n\n  ugh  \n").unwrap();
+        let config = from_pairs(&[("SHORTENER_TOKEN_BLOCKLIST", path.to_str().unwrap())]);
+        fs::remove_file(&path).unwrap();
+
+        let config = config.unwrap();
+        assert_eq!(config.token_blocklist, vec!["bad", "ugh"]);
+        assert!(config.token_config().is_blocked("xBADx1"));
+        assert!(config.namespace_token_config().is_blocked("aUgh"));
+    }
+
+    #[test]
+    fn test_expired_behavior_from_str() {
+        assert_eq!(
+            "410".parse::<ExpiredBehavior>().unwrap(),
+            ExpiredBehavior::Gone
+        );
+        assert_eq!(
+            "https://example.com/expired"
+                .parse::<ExpiredBehavior>()
+                .unwrap(),
+            ExpiredBehavior::Redirect(Url::parse("https://example.com/expired").unwrap())
+        );
+    }
+
+    #[test]
+    fn test_redirect_kind_from_str() {
+        assert_eq!(
+            "307".parse::<RedirectKind>().unwrap(),
+            RedirectKind::TemporaryPreserveMethod
+        );
+        assert_eq!(
+            "temporary".parse::<RedirectKind>().unwrap(),
+            RedirectKind::Temporary
+        );
+        assert!("moved".parse::<RedirectKind>().is_err());
+    }
+
+    #[test]
+    fn test_redirect_cache_control() {
+        let mut config = Config::default();
+        assert_eq!(config.redirect_cache_control().as_str(), "no-store");
+        config.redirect_kind = RedirectKind::Permanent;
+        assert_eq!(
+            config.redirect_cache_control().as_str(),
+            "public, max-age=86400"
+        );
+    }
+
+    #[test]
+    fn test_from_vars_defaults() {
+        assert_eq!(from_pairs(&[]).unwrap(), Config::default());
+        assert_eq!(
+            from_pairs(&[("SHORTENER_BASE_URL", "")]).unwrap(),
+            Config::default()
+        );
+        // Forwarded headers are spoofable unless a proxy is known to overwrite them
+        assert!(!Config::default().trusted_proxy);
+    }
+
+    #[test]
+    fn test_from_vars_disables_sweeping() {
+        let config = from_pairs(&[("SHORTENER_SWEEP_INTERVAL_SECS", "0")]).unwrap();
+        assert_eq!(config.sweep_interval, None);
+    }
+
+    #[test]
+    fn test_from_vars_rejects_invalid_values() {
+        assert!(from_pairs(&[("SHORTENER_TOKEN_LENGTH", "six")]).is_err());
+        assert!(from_pairs(&[("SHORTENER_TOKEN_LENGTH", "0")]).is_err());
+        assert!(from_pairs(&[("SHORTENER_TOKEN_LENGTH", "17")]).is_err());
+        assert!(from_pairs(&[("SHORTENER_TOKEN_STRATEGY", "counter")]).is_err());
+        assert!(from_pairs(&[("SHORTENER_TOKEN_CHARSET", "emoji")]).is_err());
+        assert!(from_pairs(&[("SHORTENER_BASE_URL", "not a url")]).is_err());
+        assert!(from_pairs(&[("SHORTENER_ALLOWED_SCHEMES", " , ")]).is_err());
+        assert!(from_pairs(&[("SHORTENER_STRIP_TRAILING_SLASH", "yes")]).is_err());
+        assert!(from_pairs(&[("SHORTENER_SORT_QUERY", "1")]).is_err());
+        assert!(from_pairs(&[("SHORTENER_RECORD_CLICKS", "on")]).is_err());
+        assert!(from_pairs(&[("SHORTENER_BLOCK_PRIVATE_HOSTS", "yes")]).is_err());
+        assert!(from_pairs(&[("SHORTENER_CORS_ORIGINS", "app.example.com")]).is_err());
+        assert!(from_pairs(&[("SHORTENER_REDIRECT_KIND", "308")]).is_err());
+        assert!(from_pairs(&[("SHORTENER_LINK_QUOTA", "-1")]).is_err());
+        assert!(from_pairs(&[("SHORTENER_SWEEP_INTERVAL_SECS", "1m")]).is_err());
+        assert!(from_pairs(&[("SHORTENER_NAMESPACES", "docs/v2")]).is_err());
+        assert!(from_pairs(&[("SHORTENER_NAMESPACES", "Admin")]).is_err());
+        assert!(from_pairs(&[("SHORTENER_NOT_FOUND_REDIRECT", "/home")]).is_err());
+        assert!(from_pairs(&[("SHORTENER_MIN_ALIAS_LENGTH", "0")]).is_err());
+        assert!(from_pairs(&[("SHORTENER_TRUSTED_PROXY", "maybe")]).is_err());
+        assert!(from_pairs(&[("SHORTENER_CASE_INSENSITIVE_TOKENS", "1")]).is_err());
+        assert!(from_pairs(&[("SHORTENER_DEDUP", "yes")]).is_err());
+        assert!(from_pairs(&[("SHORTENER_DOMAIN_ALLOWLIST", "*")]).is_err());
+        assert!(from_pairs(&[("SHORTENER_DOMAIN_ALLOWLIST", "docs.*.com")]).is_err());
+        assert!(from_pairs(&[("SHORTENER_DOMAIN_ALLOWLIST", "exa mple.com")]).is_err());
+        assert!(from_pairs(&[("SHORTENER_ROOT_REDIRECT", "mycompany.com")]).is_err());
+        assert!(from_pairs(&[("SHORTENER_TOKEN_BLOCKLIST", "/nonexistent/words.txt")]).is_err());
+        assert!(from_pairs(&[("SHORTENER_EXPIRED_BEHAVIOR", "expired.html")]).is_err());
+        assert!(from_pairs(&[(
+            "SHORTENER_TEMPORARY_CACHE_CONTROL",
+            "no-store\r\nSet-Cookie: a=b"
+        )])
+        .is_err());
+        assert!(from_pairs(&[("SHORTENER_MIN_ALIAS_LENGTH", "33")]).is_err());
+        assert!(from_pairs(&[("SHORTENER_NOT_FOUND_PAGE", "/nonexistent/404.html")]).is_err());
+        assert!(from_pairs(&[
+            ("SHORTENER_NOT_FOUND_REDIRECT", "https://example.com/"),
+            ("SHORTENER_NOT_FOUND_PAGE", "404.html"),
+        ])
+        .is_err());
+    }
+}
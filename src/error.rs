@@ -0,0 +1,154 @@
+use axum::{
+    body::Body,
+    extract::Request,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use std::fmt::Display;
+
+/// Handler error, rendered as `{ "error": message }` with `status`, or as the bare
+/// message for clients that asked for text, see `negotiate_format`.
+#[derive(Debug)]
+pub struct AppError {
+    pub status: StatusCode,
+    pub message: String,
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    error: &'a str,
+}
+
+/// Message of an `AppError`, left on its response for `negotiate_format`.
+#[derive(Clone)]
+struct ErrorMessage(String);
+
+impl AppError {
+    pub fn new(status: StatusCode, message: impl Display) -> Self {
+        Self {
+            status,
+            message: message.to_string(),
+        }
+    }
+
+    pub fn bad_request(message: impl Display) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, message)
+    }
+
+    pub fn not_found(message: impl Display) -> Self {
+        Self::new(StatusCode::NOT_FOUND, message)
+    }
+
+    pub fn internal(message: impl Display) -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, message)
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        if self.status.is_server_error() {
+            tracing::error!("{}", self.message);
+        }
+        let body = Json(ErrorBody {
+            error: &self.message,
+        });
+        let mut response = (self.status, body).into_response();
+        response.extensions_mut().insert(ErrorMessage(self.message));
+        response
+    }
+}
+
+/// Whether the client asked for a bare string instead of JSON.
+pub fn accepts_plain_text(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::ACCEPT)
+        .and_then(|h| h.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/plain") && !accept.contains("application/json"))
+}
+
+/// Rewrites `AppError` bodies as plain text for clients that prefer it; everyone else keeps JSON.
+pub async fn negotiate_format(req: Request, next: Next) -> Response {
+    let plain_text = accepts_plain_text(req.headers());
+    let mut response = next.run(req).await;
+    let Some(ErrorMessage(message)) = response.extensions_mut().remove::<ErrorMessage>() else {
+        return response;
+    };
+    if !plain_text {
+        return response;
+    }
+
+    let (mut parts, _) = response.into_parts();
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts.headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("text/plain; charset=utf-8"),
+    );
+    Response::from_parts(parts, Body::from(message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_into_response() {
+        let response = AppError::bad_request("Failed to parse URL").into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], br#"{"error":"Failed to parse URL"}"#);
+    }
+
+    async fn failing_request(accept: &str) -> Response {
+        let app = Router::new()
+            .route(
+                "/",
+                get(|| async { Err::<(), _>(AppError::not_found("Token not found")) }),
+            )
+            .layer(middleware::from_fn(negotiate_format));
+        app.oneshot(
+            Request::builder()
+                .uri("/")
+                .header(header::ACCEPT, accept)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap()
+    }
+
+    async fn body_bytes(response: Response) -> axum::body::Bytes {
+        axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_negotiate_format_json() {
+        let response = failing_request("application/json").await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        assert_eq!(
+            &body_bytes(response).await[..],
+            br#"{"error":"Token not found"}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn test_negotiate_format_plain_text() {
+        let response = failing_request("text/plain").await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/plain; charset=utf-8"
+        );
+        assert_eq!(&body_bytes(response).await[..], b"Token not found");
+    }
+}
@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::time::{Duration, SystemTime};
+
+use crate::token::Token;
+
+/// Tokens registered under an `Idempotency-Key`, so retried registrations get the same link.
+/// Each is kept with a hash of the request that registered it.
+pub struct IdempotencyCache {
+    window: Duration,
+    entries: HashMap<String, (SystemTime, u64, Token)>,
+}
+
+/// A key was sent again with a different request than the one it registered.
+#[derive(Debug, PartialEq)]
+pub struct KeyReused;
+
+impl Display for KeyReused {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Idempotency-Key was already used for a different request"
+        )
+    }
+}
+
+impl std::error::Error for KeyReused {}
+
+impl IdempotencyCache {
+    /// Remembers each key for `window` after its first registration.
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            entries: HashMap::new(),
+        }
+    }
+
+    /// The token first registered under `key`, unless the window has passed since. Fails
+    /// if that registration's `request_hash` differs.
+    pub fn get(
+        &self,
+        key: &str,
+        request_hash: u64,
+        now: SystemTime,
+    ) -> Result<Option<&Token>, KeyReused> {
+        match self.entries.get(key) {
+            Some((at, _, _)) if !is_fresh(*at, now, self.window) => Ok(None),
+            Some((_, hash, _)) if *hash != request_hash => Err(KeyReused),
+            Some((_, _, token)) => Ok(Some(token)),
+            None => Ok(None),
+        }
+    }
+
+    /// Records `token` for `key` and the request hashed to `request_hash`, forgetting
+    /// keys whose window has passed.
+    pub fn insert(&mut self, key: String, request_hash: u64, token: Token, now: SystemTime) {
+        let window = self.window;
+        self.entries
+            .retain(|_, (at, _, _)| is_fresh(*at, now, window));
+        self.entries.insert(key, (now, request_hash, token));
+    }
+}
+
+fn is_fresh(at: SystemTime, now: SystemTime, window: Duration) -> bool {
+    now.duration_since(at).unwrap_or_default() < window
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_returns_token_within_window() {
+        let mut cache = IdempotencyCache::new(Duration::from_secs(60));
+        let token = Token::try_from("abc123").unwrap();
+        cache.insert("key".to_string(), 7, token.clone(), SystemTime::UNIX_EPOCH);
+
+        let later = SystemTime::UNIX_EPOCH + Duration::from_secs(59);
+        assert_eq!(cache.get("key", 7, later), Ok(Some(&token)));
+        assert_eq!(cache.get("other", 7, later), Ok(None));
+    }
+
+    #[test]
+    fn test_rejects_key_reused_for_another_request() {
+        let mut cache = IdempotencyCache::new(Duration::from_secs(60));
+        let token = Token::try_from("abc123").unwrap();
+        cache.insert("key".to_string(), 7, token, SystemTime::UNIX_EPOCH);
+
+        assert_eq!(cache.get("key", 8, SystemTime::UNIX_EPOCH), Err(KeyReused));
+        let later = SystemTime::UNIX_EPOCH + Duration::from_secs(60);
+        assert_eq!(cache.get("key", 8, later), Ok(None));
+    }
+
+    #[test]
+    fn test_forgets_token_after_window() {
+        let mut cache = IdempotencyCache::new(Duration::from_secs(60));
+        let token = Token::try_from("abc123").unwrap();
+        cache.insert("key".to_string(), 7, token.clone(), SystemTime::UNIX_EPOCH);
+
+        let later = SystemTime::UNIX_EPOCH + Duration::from_secs(60);
+        assert_eq!(cache.get("key", 7, later), Ok(None));
+
+        cache.insert("other".to_string(), 7, token, later);
+        assert!(!cache.entries.contains_key("key"));
+    }
+}
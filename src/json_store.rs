@@ -0,0 +1,317 @@
+//! `StoreAccess` kept in memory and written through to a JSON file, a lighter
+//! alternative to SQLite for small deployments.
+//!
+//! Every change rewrites the whole file with `snapshot::save`, which is fine for
+//! thousands of links but not for millions. Changes take `&mut self`, so they are
+//! already serialized by whoever owns the store, and each write replaces the file
+//! in one rename. Hits and clicks are counted in memory only; hits reach the file
+//! with the next change, clicks never do.
+
+use crate::snapshot;
+use crate::store::{
+    ClickInfo, LinkOptions, LinkPredicate, LinkRecord, Store, StoreAccess, StoreStats,
+};
+use crate::token::{Token, TokenConfig};
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+use url::Url;
+
+pub struct JsonFileStore {
+    links: Store,
+    path: PathBuf,
+}
+
+impl JsonFileStore {
+    /// Loads the links in `path`, starting empty if it doesn't exist yet. A file that
+    /// can't be parsed is moved aside to `<path>.corrupt` rather than overwritten.
+    pub fn open(path: impl Into<PathBuf>, token_config: TokenConfig) -> Result<Self> {
+        let path = path.into();
+        let links = match fs::read(&path) {
+            Ok(json) => snapshot::from_json(&json).unwrap_or_else(|e| {
+                let mut corrupt = path.clone().into_os_string();
+                corrupt.push(".corrupt");
+                tracing::error!(
+                    "Invalid store file {}, moving it to {} and starting empty: {e:#}",
+                    path.display(),
+                    PathBuf::from(&corrupt).display()
+                );
+                if let Err(e) = fs::rename(&path, &corrupt) {
+                    tracing::error!("Failed to move {}: {e}", path.display());
+                }
+                Vec::new()
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e).wrap_err_with(|| format!("Failed to read {}", path.display())),
+        };
+        Ok(Self {
+            links: Store::new(token_config).with_links(links),
+            path,
+        })
+    }
+
+    /// See `Store::with_unique_urls`.
+    pub fn with_unique_urls(mut self, unique_urls: bool) -> Self {
+        self.links = self.links.with_unique_urls(unique_urls);
+        self
+    }
+
+    /// See `Store::with_dedup`.
+    pub fn with_dedup(mut self, dedup: bool) -> Self {
+        self.links = self.links.with_dedup(dedup);
+        self
+    }
+
+    /// See `Store::with_case_insensitive_tokens`.
+    pub fn with_case_insensitive_tokens(mut self, case_insensitive: bool) -> Self {
+        self.links = self.links.with_case_insensitive_tokens(case_insensitive);
+        self
+    }
+
+    /// Writes every link, deleted ones included so their tokens stay taken.
+    fn persist(&self) -> Result<()> {
+        snapshot::save(&self.path, &self.links.to_json()?)
+    }
+
+    /// Runs `change` on the in-memory links and writes them out if it succeeded. If they
+    /// can't be written, the change is undone so memory never gets ahead of the file.
+    fn write_through<T>(&mut self, change: impl FnOnce(&mut Store) -> Result<T>) -> Result<T> {
+        let before = self.links.list(0, usize::MAX)?;
+        let result = change(&mut self.links)?;
+        self.persist_or_undo(before)?;
+        Ok(result)
+    }
+
+    /// `persist`, putting back the links `before` the change if it fails.
+    fn persist_or_undo(&mut self, before: Vec<(Token, LinkRecord)>) -> Result<()> {
+        let persisted = self.persist();
+        if persisted.is_err() {
+            self.links.reset_links(before);
+        }
+        persisted
+    }
+
+    /// `write_through` for a change of `len` independent results, like `register_batch`.
+    /// If the links can't be written, every result is that error.
+    fn write_through_each(
+        &mut self,
+        len: usize,
+        change: impl FnOnce(&mut Store) -> Vec<Result<Token>>,
+    ) -> Vec<Result<Token>> {
+        let persisted = self.links.list(0, usize::MAX).and_then(|before| {
+            let results = change(&mut self.links);
+            self.persist_or_undo(before).map(|()| results)
+        });
+        persisted.unwrap_or_else(|e| {
+            let message = format!("{e:#}");
+            (0..len).map(|_| Err(eyre!("{message}"))).collect()
+        })
+    }
+}
+
+impl StoreAccess for JsonFileStore {
+    fn register_with_options(
+        &mut self,
+        url: Url,
+        alias: Option<&str>,
+        ttl: Option<Duration>,
+        options: LinkOptions,
+    ) -> Result<Token> {
+        self.write_through(|links| links.register_with_options(url, alias, ttl, options))
+    }
+
+    fn register_or_get(&mut self, url: Url) -> Result<(Token, bool)> {
+        self.write_through(|links| links.register_or_get(url))
+    }
+
+    fn check_alias(&self, alias: &str) -> Result<()> {
+        self.links.check_alias(alias)
+    }
+
+    fn top_by_hits(&self, n: usize) -> Result<Vec<(Token, LinkRecord)>> {
+        self.links.top_by_hits(n)
+    }
+
+    /// Written once for the whole batch rather than once per URL.
+    fn register_batch(&mut self, urls: Vec<Url>, options: LinkOptions) -> Vec<Result<Token>> {
+        self.write_through_each(urls.len(), |links| links.register_batch(urls, options))
+    }
+
+    fn resolve_token(&self, token: &str) -> Result<Url> {
+        self.links.resolve_token(token)
+    }
+
+    fn link_info(&self, token: &str) -> Result<LinkRecord> {
+        self.links.link_info(token)
+    }
+
+    fn owner(&self, token: &str) -> Result<Option<String>> {
+        self.links.owner(token)
+    }
+
+    fn delete_token(&mut self, token: &str) -> Result<()> {
+        self.write_through(|links| links.delete_token(token))
+    }
+
+    fn restore_token(&mut self, token: &str) -> Result<()> {
+        self.write_through(|links| links.restore_token(token))
+    }
+
+    fn update_url(&mut self, token: &str, new_url: Url) -> Result<()> {
+        self.write_through(|links| links.update_url(token, new_url))
+    }
+
+    fn set_password_hash(&mut self, token: &str, password_hash: Option<String>) -> Result<()> {
+        self.write_through(|links| links.set_password_hash(token, password_hash))
+    }
+
+    fn set_owner(&mut self, token: &str, owner: Option<String>) -> Result<()> {
+        self.write_through(|links| links.set_owner(token, owner))
+    }
+
+    fn set_max_hits(&mut self, token: &str, max_hits: Option<u64>) -> Result<()> {
+        self.write_through(|links| links.set_max_hits(token, max_hits))
+    }
+
+    fn set_destinations(&mut self, token: &str, destinations: Vec<(Url, u32)>) -> Result<()> {
+        self.write_through(|links| links.set_destinations(token, destinations))
+    }
+
+    fn set_utm_params(&mut self, token: &str, utm_params: Vec<(String, String)>) -> Result<()> {
+        self.write_through(|links| links.set_utm_params(token, utm_params))
+    }
+
+    fn purge_expired(&mut self, now: SystemTime) -> Result<usize> {
+        self.write_through(|links| links.purge_expired(now))
+    }
+
+    fn clear(&mut self) -> Result<usize> {
+        self.write_through(|links| links.clear())
+    }
+
+    /// Written once for all matching links.
+    fn delete_matching(&mut self, matches: &LinkPredicate<'_>) -> Result<usize> {
+        self.write_through(|links| links.delete_matching(matches))
+    }
+
+    fn list(&self, offset: usize, limit: usize) -> Result<Vec<(Token, LinkRecord)>> {
+        self.links.list(offset, limit)
+    }
+
+    fn record_click(&self, token: &str, click: ClickInfo) -> Result<()> {
+        self.links.record_click(token, click)
+    }
+
+    fn clicks(&self, token: &str) -> Result<Vec<ClickInfo>> {
+        self.links.clicks(token)
+    }
+
+    fn stats(&self, now: SystemTime) -> Result<StoreStats> {
+        self.links.stats(now)
+    }
+
+    /// Written once for the whole import, like `register_batch`.
+    fn import(&mut self, links: Vec<(String, Url)>) -> Vec<Result<Token>> {
+        self.write_through_each(links.len(), |store| store.import(links))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::StoreError;
+
+    /// A path in the temp directory, cleared of anything an earlier run left behind.
+    fn store_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "shortener-json-store-{}-{name}.json",
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_persists_across_reopen() -> Result<()> {
+        let path = store_path("reopen");
+        let mut store = JsonFileStore::open(&path, TokenConfig::default())?;
+        let token = store.register_url(Url::parse("https://example.com")?)?;
+        store.register_url_with_alias(Url::parse("https://alias.com")?, "launch")?;
+        store.delete_token("launch")?;
+        store.resolve_token(token.as_str())?;
+        store.set_owner(token.as_str(), Some("alpha".to_string()))?;
+        drop(store);
+
+        let store = JsonFileStore::open(&path, TokenConfig::default())?;
+        fs::remove_file(&path)?;
+        assert_eq!(
+            store.resolve_token(token.as_str())?,
+            Url::parse("https://example.com")?
+        );
+        let record = store.link_info(token.as_str())?;
+        assert_eq!(record.hits.into_inner(), 2);
+        assert_eq!(record.owner.as_deref(), Some("alpha"));
+        let err = store.resolve_token("launch").unwrap_err();
+        assert_eq!(err.downcast_ref(), Some(&StoreError::Deleted));
+        Ok(())
+    }
+
+    #[test]
+    fn test_failed_write_is_undone() -> Result<()> {
+        let path = store_path("unwritable");
+        let mut store = JsonFileStore::open(&path, TokenConfig::default())?;
+        let token = store.register_url(Url::parse("https://example.com")?)?;
+        // Writes go through `<path>.tmp`, which can't be replaced while it's a directory
+        let mut partial = path.clone().into_os_string();
+        partial.push(".tmp");
+        fs::create_dir(&partial)?;
+
+        let results = store.register_batch(
+            vec![Url::parse("https://a.com")?, Url::parse("https://b.com")?],
+            LinkOptions::default(),
+        );
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(Result::is_err));
+        assert!(store.delete_token(token.as_str()).is_err());
+        fs::remove_dir(&partial)?;
+        fs::remove_file(&path)?;
+
+        let links = store.list(0, 10)?;
+        assert_eq!(links.len(), 1);
+        assert!(!links[0].1.deleted);
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_missing_file() -> Result<()> {
+        let path = store_path("missing");
+        let store = JsonFileStore::open(&path, TokenConfig::default())?;
+        assert!(store.list(0, 10)?.is_empty());
+        assert!(!path.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_corrupt_file() -> Result<()> {
+        let path = store_path("corrupt");
+        fs::write(&path, "{not json")?;
+
+        let mut store = JsonFileStore::open(&path, TokenConfig::default())?;
+        assert!(store.list(0, 10)?.is_empty());
+        let mut corrupt = path.clone().into_os_string();
+        corrupt.push(".corrupt");
+        assert_eq!(fs::read_to_string(&corrupt)?, "{not json");
+
+        store.register_url(Url::parse("https://example.com")?)?;
+        assert_eq!(
+            JsonFileStore::open(&path, TokenConfig::default())?
+                .list(0, 10)?
+                .len(),
+            1
+        );
+        fs::remove_file(&path)?;
+        fs::remove_file(&corrupt)?;
+        Ok(())
+    }
+}
@@ -1,9 +1,295 @@
+mod async_store;
+mod clock;
+mod config;
+mod error;
+mod idempotency;
+#[cfg(feature = "json")]
+mod json_store;
+mod metrics;
+mod normalize;
+mod password;
+#[cfg(feature = "postgres")]
+mod postgres_store;
+mod qr;
+mod rate_limit;
+#[cfg(feature = "redis")]
+mod redis_store;
+mod request_log;
 mod shortener;
+mod snapshot;
+#[cfg(feature = "sqlite")]
+mod sqlite_store;
 mod store;
 mod token;
 
+use crate::async_store::AsyncStoreAccess;
+use crate::config::Config;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use tracing_subscriber::EnvFilter;
+
+#[cfg(all(feature = "sqlite", not(feature = "redis")))]
+const SQLITE_PATH: &str = "links.db";
+#[cfg(all(feature = "json", not(any(feature = "sqlite", feature = "redis"))))]
+const JSON_PATH: &str = "links.json";
+
 #[shuttle_runtime::main]
-async fn main() -> shuttle_axum::ShuttleAxum {
+async fn main() -> Result<ShortenerService, shuttle_runtime::Error> {
     color_eyre::install().expect("Failed to install color_eyre");
-    Ok(shortener::create_router().into())
+    // Shuttle's own subscriber is disabled in Cargo.toml so `RUST_LOG` is honoured here
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .init();
+
+    let config =
+        Config::from_env().map_err(|e| shuttle_runtime::CustomError::msg(format!("{e:#}")))?;
+    if config.api_keys.is_empty() {
+        tracing::warn!("No SHORTENER_API_KEYS configured, anyone can register links");
+    }
+    if !config.trusted_proxy {
+        tracing::warn!(
+            "SHORTENER_TRUSTED_PROXY is off, so behind a proxy such as Shuttle's every \
+             client shares the proxy's registration rate limit"
+        );
+    }
+
+    #[cfg(feature = "redis")]
+    let store: Box<dyn AsyncStoreAccess> = {
+        let url = std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1/".into());
+        let mut store = redis_store::RedisStore::connect(&url)
+            .map_err(|e| shuttle_runtime::CustomError::msg(e.to_string()))?;
+        store.token_config = config.token_config();
+        if config.unique_urls {
+            tracing::warn!(
+                "SHORTENER_UNIQUE_URLS is not supported by the Redis store, ignoring it"
+            );
+        }
+        if config.case_insensitive_tokens {
+            tracing::warn!(
+                "SHORTENER_CASE_INSENSITIVE_TOKENS is not supported by the Redis store, ignoring it"
+            );
+        }
+        Box::new(store)
+    };
+    #[cfg(feature = "redis")]
+    let namespaces: HashMap<String, Box<dyn AsyncStoreAccess>> = {
+        if !config.namespaces.is_empty() {
+            tracing::warn!("SHORTENER_NAMESPACES is not supported by the Redis store, ignoring it");
+        }
+        HashMap::new()
+    };
+    #[cfg(all(feature = "sqlite", not(feature = "redis")))]
+    let store: Box<dyn AsyncStoreAccess> = {
+        let mut store = sqlite_store::SqliteStore::open(SQLITE_PATH)
+            .map_err(|e| shuttle_runtime::CustomError::msg(e.to_string()))?;
+        store.token_config = config.token_config();
+        store.unique_urls = config.unique_urls;
+        if config.case_insensitive_tokens {
+            tracing::warn!(
+                "SHORTENER_CASE_INSENSITIVE_TOKENS is not supported by the SQLite store, ignoring it"
+            );
+        }
+        Box::new(store)
+    };
+    // Each namespace gets a database file of its own next to `SQLITE_PATH`
+    #[cfg(all(feature = "sqlite", not(feature = "redis")))]
+    let namespaces = config
+        .namespaces
+        .iter()
+        .map(|namespace| {
+            let mut store = sqlite_store::SqliteStore::open(&format!("links-{namespace}.db"))
+                .map_err(|e| shuttle_runtime::CustomError::msg(e.to_string()))?;
+            store.token_config = config.namespace_token_config();
+            store.unique_urls = config.unique_urls;
+            let store: Box<dyn AsyncStoreAccess> = Box::new(store);
+            Ok((namespace.clone(), store))
+        })
+        .collect::<Result<HashMap<_, _>, shuttle_runtime::CustomError>>()?;
+    #[cfg(all(feature = "json", not(any(feature = "sqlite", feature = "redis"))))]
+    let store: Box<dyn AsyncStoreAccess> = Box::new(
+        json_store::JsonFileStore::open(JSON_PATH, config.token_config())
+            .map_err(|e| shuttle_runtime::CustomError::msg(format!("{e:#}")))?
+            .with_unique_urls(config.unique_urls)
+            .with_dedup(config.dedup)
+            .with_case_insensitive_tokens(config.case_insensitive_tokens),
+    );
+    // Each namespace gets a file of its own next to `JSON_PATH`
+    #[cfg(all(feature = "json", not(any(feature = "sqlite", feature = "redis"))))]
+    let namespaces = config
+        .namespaces
+        .iter()
+        .map(|namespace| {
+            let store = json_store::JsonFileStore::open(
+                format!("links-{namespace}.json"),
+                config.namespace_token_config(),
+            )
+            .map_err(|e| shuttle_runtime::CustomError::msg(format!("{e:#}")))?
+            .with_unique_urls(config.unique_urls)
+            .with_dedup(config.dedup)
+            .with_case_insensitive_tokens(config.case_insensitive_tokens);
+            let store: Box<dyn AsyncStoreAccess> = Box::new(store);
+            Ok((namespace.clone(), store))
+        })
+        .collect::<Result<HashMap<_, _>, shuttle_runtime::CustomError>>()?;
+    #[cfg(all(
+        feature = "postgres",
+        not(any(feature = "sqlite", feature = "redis", feature = "json"))
+    ))]
+    let store: Box<dyn AsyncStoreAccess> = {
+        let url = std::env::var("DATABASE_URL")
+            .unwrap_or_else(|_| "postgres://postgres@localhost/shortener".into());
+        let mut store = postgres_store::PostgresStore::connect(&url)
+            .await
+            .map_err(|e| shuttle_runtime::CustomError::msg(format!("{e:#}")))?;
+        store.token_config = config.token_config();
+        store.unique_urls = config.unique_urls;
+        if config.case_insensitive_tokens {
+            tracing::warn!(
+                "SHORTENER_CASE_INSENSITIVE_TOKENS is not supported by the Postgres store, ignoring it"
+            );
+        }
+        Box::new(store)
+    };
+    #[cfg(all(
+        feature = "postgres",
+        not(any(feature = "sqlite", feature = "redis", feature = "json"))
+    ))]
+    let namespaces: HashMap<String, Box<dyn AsyncStoreAccess>> = {
+        if !config.namespaces.is_empty() {
+            tracing::warn!(
+                "SHORTENER_NAMESPACES is not supported by the Postgres store, ignoring it"
+            );
+        }
+        HashMap::new()
+    };
+    #[cfg(any(
+        feature = "sqlite",
+        feature = "redis",
+        feature = "json",
+        feature = "postgres"
+    ))]
+    let snapshot_path = {
+        if config.snapshot_path.is_some() {
+            tracing::warn!(
+                "SHORTENER_SNAPSHOT_PATH only applies to the in-memory store, ignoring it"
+            );
+        }
+        None
+    };
+    #[cfg(not(any(
+        feature = "sqlite",
+        feature = "redis",
+        feature = "json",
+        feature = "postgres"
+    )))]
+    let snapshot_path = config.snapshot_path.clone();
+    #[cfg(not(any(
+        feature = "sqlite",
+        feature = "redis",
+        feature = "json",
+        feature = "postgres"
+    )))]
+    let store: Box<dyn AsyncStoreAccess> = {
+        let store = match &snapshot_path {
+            Some(path) => {
+                let store = snapshot::load(path, config.token_config())
+                    .map_err(|e| shuttle_runtime::CustomError::msg(format!("{e:#}")))?;
+                tracing::info!("Loaded the links saved in {}", path.display());
+                store
+            }
+            None => store::Store::new(config.token_config()),
+        };
+        Box::new(
+            store
+                .with_unique_urls(config.unique_urls)
+                .with_dedup(config.dedup)
+                .with_case_insensitive_tokens(config.case_insensitive_tokens),
+        )
+    };
+    #[cfg(not(any(
+        feature = "sqlite",
+        feature = "redis",
+        feature = "json",
+        feature = "postgres"
+    )))]
+    let namespaces: HashMap<String, Box<dyn AsyncStoreAccess>> = config
+        .namespaces
+        .iter()
+        .map(|namespace| {
+            let store = store::Store::new(config.namespace_token_config())
+                .with_unique_urls(config.unique_urls)
+                .with_dedup(config.dedup)
+                .with_case_insensitive_tokens(config.case_insensitive_tokens);
+            let store: Box<dyn AsyncStoreAccess> = Box::new(store);
+            (namespace.clone(), store)
+        })
+        .collect();
+
+    let (router, app) = shortener::create_namespaced_router(store, namespaces, config);
+    Ok(ShortenerService {
+        router,
+        app,
+        snapshot_path,
+    })
+}
+
+/// Serves the router like `shuttle_axum` would, then saves the in-memory store once
+/// in-flight requests have drained.
+struct ShortenerService {
+    router: axum::Router,
+    app: shortener::AppHandle,
+    snapshot_path: Option<PathBuf>,
+}
+
+#[async_trait::async_trait]
+impl shuttle_runtime::Service for ShortenerService {
+    async fn bind(self, addr: SocketAddr) -> Result<(), shuttle_runtime::Error> {
+        let listener = tokio::net::TcpListener::bind(addr)
+            .await
+            .map_err(shuttle_runtime::CustomError::new)?;
+        axum::serve(
+            listener,
+            self.router
+                .into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .with_graceful_shutdown(shutdown_signal())
+        .await
+        .map_err(shuttle_runtime::CustomError::new)?;
+
+        if let Some(path) = &self.snapshot_path {
+            self.app
+                .save_snapshot(path)
+                .await
+                .map_err(|e| shuttle_runtime::CustomError::msg(format!("{e:#}")))?;
+            tracing::info!("Saved the links to {}", path.display());
+        }
+        Ok(())
+    }
+}
+
+/// Resolves on Ctrl+C or SIGTERM, the latter being how deployments stop the process.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to listen for Ctrl+C");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+    tracing::info!("Shutting down");
 }
@@ -0,0 +1,76 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Counters exposed on `/metrics`; atomics so handlers only need shared access.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    urls_registered: AtomicU64,
+    redirects: AtomicU64,
+    redirects_not_found: AtomicU64,
+}
+
+impl Metrics {
+    /// Content type of `render`'s output.
+    pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+    pub fn record_registrations(&self, count: u64) {
+        self.urls_registered.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn record_redirect(&self) {
+        self.redirects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_not_found(&self) {
+        self.redirects_not_found.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Renders every counter in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let counters = [
+            (
+                "urls_registered_total",
+                "Short links created.",
+                &self.urls_registered,
+            ),
+            (
+                "redirects_total",
+                "Tokens resolved to a redirect.",
+                &self.redirects,
+            ),
+            (
+                "redirects_not_found_total",
+                "Lookups of unknown tokens.",
+                &self.redirects_not_found,
+            ),
+        ];
+
+        let mut out = String::new();
+        for (name, help, counter) in counters {
+            let value = counter.load(Ordering::Relaxed);
+            // Writing to a String cannot fail
+            let _ = write!(
+                out,
+                "# HELP {name} {help}\n# TYPE {name} counter\n{name} {value}\n"
+            );
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::default();
+        metrics.record_redirect();
+        metrics.record_redirect();
+
+        let body = metrics.render();
+        assert!(body.contains("# TYPE redirects_total counter\nredirects_total 2\n"));
+        assert!(body.contains("\nurls_registered_total 0\n"));
+        assert!(body.contains("\nredirects_not_found_total 0\n"));
+    }
+}
@@ -0,0 +1,114 @@
+use url::Url;
+
+/// Canonicalization applied to targets before they are stored.
+///
+/// `Url::parse` already lowercases hosts of special schemes, drops default
+/// ports and turns an empty path into `/`; this covers the rest.
+/// Fragments are always kept, they often pick the section a link is about.
+#[derive(Debug, Clone, Default)]
+pub struct Normalization {
+    /// Drop a trailing `/` from non-root paths, e.g. `/docs/` becomes `/docs`.
+    pub strip_trailing_slash: bool,
+    /// Sort query parameters by name, keeping repeated names in their original order.
+    pub sort_query: bool,
+}
+
+impl Normalization {
+    pub fn apply(&self, mut url: Url) -> Url {
+        if let Some(host) = url.host_str().map(str::to_lowercase) {
+            // Lowercasing a host that already parsed cannot make it invalid
+            let _ = url.set_host(Some(&host));
+        }
+        if url.query() == Some("") {
+            url.set_query(None);
+        }
+
+        if self.strip_trailing_slash && url.path().len() > 1 && url.path().ends_with('/') {
+            let path = url.path().trim_end_matches('/').to_string();
+            url.set_path(&path);
+        }
+        if let Some(query) = url.query().filter(|_| self.sort_query) {
+            // The raw pieces are sorted rather than decoded pairs, which would be
+            // re-encoded differently, e.g. `?flag` as `?flag=` and `%20` as `+`
+            let mut pieces: Vec<&str> = query.split('&').collect();
+            pieces.sort_by_key(|piece| piece.split('=').next());
+            let query = pieces.join("&");
+            url.set_query(Some(&query));
+        }
+        url
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalize(normalization: &Normalization, input: &str) -> Url {
+        normalization.apply(Url::parse(input).unwrap())
+    }
+
+    #[test]
+    fn test_default_normalization() {
+        let normalization = Normalization::default();
+
+        assert_eq!(
+            normalize(&normalization, "HTTPS://Example.COM:443?"),
+            normalize(&normalization, "https://example.com/")
+        );
+        assert_eq!(
+            normalize(&normalization, "https://example.com/docs/?b=1&a=2").as_str(),
+            "https://example.com/docs/?b=1&a=2"
+        );
+    }
+
+    #[test]
+    fn test_strip_trailing_slash() {
+        let normalization = Normalization {
+            strip_trailing_slash: true,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            normalize(&normalization, "https://example.com/docs//"),
+            normalize(&normalization, "https://example.com/docs")
+        );
+        assert_eq!(
+            normalize(&normalization, "https://example.com/").as_str(),
+            "https://example.com/"
+        );
+    }
+
+    #[test]
+    fn test_sort_query() {
+        let normalization = Normalization {
+            sort_query: true,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            normalize(&normalization, "https://example.com/?b=1&a=2&b=0").as_str(),
+            "https://example.com/?a=2&b=1&b=0"
+        );
+        assert_eq!(
+            normalize(&normalization, "https://example.com/?flag").as_str(),
+            "https://example.com/?flag"
+        );
+        assert_eq!(
+            normalize(&normalization, "https://example.com/?q=a%20b&a=1").as_str(),
+            "https://example.com/?a=1&q=a%20b"
+        );
+    }
+
+    #[test]
+    fn test_keeps_fragment() {
+        let normalization = Normalization {
+            strip_trailing_slash: true,
+            sort_query: true,
+        };
+
+        assert_eq!(
+            normalize(&normalization, "https://Example.com/docs/?b=1&a=2#Section").as_str(),
+            "https://example.com/docs?a=2&b=1#Section"
+        );
+    }
+}
@@ -0,0 +1,47 @@
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use color_eyre::eyre::{eyre, Result};
+use rand::Rng;
+
+/// Hashes a link password into a self-describing PHC string, salt included.
+pub fn hash(password: &str) -> Result<String> {
+    let mut salt = [0u8; 16];
+    rand::rng().fill(&mut salt);
+    let salt = SaltString::encode_b64(&salt).map_err(|e| eyre!("Failed to encode salt: {e}"))?;
+
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| eyre!("Failed to hash password: {e}"))
+}
+
+/// Checks `password` against a hash produced by `hash`; malformed hashes never match.
+pub fn verify(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash).is_ok_and(|hash| {
+        Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_and_verify() -> Result<()> {
+        let hash = hash("hunter2")?;
+
+        assert!(!hash.contains("hunter2"));
+        assert!(verify("hunter2", &hash));
+        assert!(!verify("hunter3", &hash));
+        assert!(!verify("hunter2", "not-a-hash"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_hashes_are_salted() -> Result<()> {
+        assert_ne!(hash("hunter2")?, hash("hunter2")?);
+        Ok(())
+    }
+}
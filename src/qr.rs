@@ -0,0 +1,74 @@
+use color_eyre::eyre::Result;
+use image::{ImageFormat, Luma};
+use qrcode::render::svg;
+use qrcode::{EcLevel, QrCode};
+use std::io::Cursor;
+
+/// Side of an SVG QR code, in pixels, when none or an unusable one is asked for.
+pub const DEFAULT_SVG_SIZE: u32 = 256;
+/// SVG sizes accepted from clients; anything outside falls back to `DEFAULT_SVG_SIZE`.
+pub const SVG_SIZES: std::ops::RangeInclusive<u32> = 32..=4096;
+
+/// Renders `data` as a QR code PNG.
+pub fn render_png(data: &str) -> Result<Vec<u8>> {
+    let image = QrCode::new(data.as_bytes())?.render::<Luma<u8>>().build();
+
+    let mut png = Vec::new();
+    image.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+    Ok(png)
+}
+
+/// Renders `data` as a QR code SVG at least `size` pixels wide, without an XML declaration.
+pub fn render_svg(data: &str, size: u32, ec_level: EcLevel) -> Result<String> {
+    let svg = QrCode::with_error_correction_level(data.as_bytes(), ec_level)?
+        .render::<svg::Color>()
+        .min_dimensions(size, size)
+        .build();
+    Ok(match svg.find("<svg") {
+        Some(start) => svg[start..].to_string(),
+        None => svg,
+    })
+}
+
+/// Parses an error-correction level as written in the QR spec, `L`, `M`, `Q` or `H`.
+pub fn parse_ec_level(level: &str) -> Option<EcLevel> {
+    match level.trim().to_ascii_uppercase().as_str() {
+        "L" => Some(EcLevel::L),
+        "M" => Some(EcLevel::M),
+        "Q" => Some(EcLevel::Q),
+        "H" => Some(EcLevel::H),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+    #[test]
+    fn test_render_png() -> Result<()> {
+        let png = render_png("https://example.com/abc123")?;
+        assert!(png.starts_with(PNG_SIGNATURE));
+        Ok(())
+    }
+
+    #[test]
+    fn test_render_svg() -> Result<()> {
+        let low = render_svg("https://example.com/abc123", DEFAULT_SVG_SIZE, EcLevel::L)?;
+        let high = render_svg("https://example.com/abc123", DEFAULT_SVG_SIZE, EcLevel::H)?;
+        assert!(low.starts_with("<svg"));
+        assert!(high.starts_with("<svg"));
+        assert_ne!(low, high);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_ec_level() {
+        assert_eq!(parse_ec_level("h"), Some(EcLevel::H));
+        assert_eq!(parse_ec_level("Q"), Some(EcLevel::Q));
+        assert_eq!(parse_ec_level("X"), None);
+        assert_eq!(parse_ec_level(""), None);
+    }
+}
@@ -0,0 +1,183 @@
+use crate::clock::{Clock, SystemClock};
+use crate::error::AppError;
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, SystemTime};
+
+/// Token bucket per client IP: `capacity` requests, refilled evenly over `window`.
+pub struct RateLimiter {
+    capacity: f64,
+    window: Duration,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+    clock: Arc<dyn Clock>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_at: SystemTime,
+}
+
+impl RateLimiter {
+    /// Past this many clients, buckets idle for a whole window (and so full again) are dropped.
+    const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+    pub fn per_minute(requests: u32) -> Self {
+        Self {
+            capacity: f64::from(requests),
+            window: Duration::from_secs(60),
+            buckets: Mutex::new(HashMap::new()),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Takes a token from `client`'s bucket, returning whether one was available.
+    pub fn try_acquire(&self, client: IpAddr) -> bool {
+        let now = self.clock.now();
+        // Buckets are always left consistent, so a poisoned lock is safe to reuse
+        let mut buckets = self.buckets.lock().unwrap_or_else(PoisonError::into_inner);
+        if buckets.len() > Self::MAX_TRACKED_CLIENTS {
+            buckets
+                .retain(|_, b| now.duration_since(b.updated_at).unwrap_or_default() < self.window);
+        }
+
+        let bucket = buckets.entry(client).or_insert(Bucket {
+            tokens: self.capacity,
+            updated_at: now,
+        });
+        let elapsed = now.duration_since(bucket.updated_at).unwrap_or_default();
+        let refill = elapsed.as_secs_f64() / self.window.as_secs_f64() * self.capacity;
+        bucket.tokens = (bucket.tokens + refill).min(self.capacity);
+        bucket.updated_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Client address from `x-forwarded-for`, falling back to the socket peer. Only the
+/// rightmost entry is used, the one our proxy appended; clients can put anything before it.
+fn client_ip(req: &Request) -> IpAddr {
+    req.headers()
+        .get("x-forwarded-for")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.rsplit(',').next())
+        .and_then(|ip| ip.trim().parse().ok())
+        .or_else(|| {
+            req.extensions()
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip())
+        })
+        .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED))
+}
+
+/// Middleware rejecting requests with `429` once the client's bucket is empty.
+pub async fn limit_by_ip(
+    State(limiter): State<Arc<RateLimiter>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let client = client_ip(&req);
+    if !limiter.try_acquire(client) {
+        tracing::warn!("Rate limit exceeded for {client}");
+        return AppError::new(
+            http::StatusCode::TOO_MANY_REQUESTS,
+            "Too many registrations, try again later",
+        )
+        .into_response();
+    }
+    next.run(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use axum::{middleware, routing::post, Router};
+    use tower::ServiceExt;
+
+    #[test]
+    fn test_bucket_refills_over_time() {
+        let clock = Arc::new(MockClock::new(SystemTime::UNIX_EPOCH));
+        let limiter = RateLimiter {
+            clock: clock.clone(),
+            ..RateLimiter::per_minute(2)
+        };
+        let client = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+        assert!(limiter.try_acquire(client));
+        assert!(limiter.try_acquire(client));
+        assert!(!limiter.try_acquire(client));
+
+        clock.advance(Duration::from_secs(30));
+        assert!(limiter.try_acquire(client));
+        assert!(!limiter.try_acquire(client));
+    }
+
+    #[test]
+    fn test_buckets_are_per_client() {
+        let limiter = RateLimiter::per_minute(1);
+
+        assert!(limiter.try_acquire(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1))));
+        assert!(limiter.try_acquire(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2))));
+        assert!(!limiter.try_acquire(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1))));
+    }
+
+    #[tokio::test]
+    async fn test_limit_by_ip_returns_429() {
+        let limiter = Arc::new(RateLimiter::per_minute(2));
+        let app = Router::new()
+            .route("/", post(|| async { "ok" }))
+            .layer(middleware::from_fn_with_state(limiter, limit_by_ip));
+
+        let mut statuses = Vec::new();
+        for _ in 0..4 {
+            let req = Request::builder()
+                .method("POST")
+                .uri("/")
+                .header("x-forwarded-for", "203.0.113.7, 10.0.0.1")
+                .body(axum::body::Body::empty())
+                .unwrap();
+            statuses.push(app.clone().oneshot(req).await.unwrap().status());
+        }
+
+        assert_eq!(
+            statuses,
+            [
+                http::StatusCode::OK,
+                http::StatusCode::OK,
+                http::StatusCode::TOO_MANY_REQUESTS,
+                http::StatusCode::TOO_MANY_REQUESTS,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_client_ip_ignores_spoofed_hops() {
+        let request = |forwarded_for: &str| {
+            Request::builder()
+                .header("x-forwarded-for", forwarded_for)
+                .body(axum::body::Body::empty())
+                .unwrap()
+        };
+        let client = IpAddr::V4(Ipv4Addr::new(198, 51, 100, 4));
+
+        assert_eq!(client_ip(&request("198.51.100.4")), client);
+        // Whatever the client sent comes first, the address our proxy saw last
+        assert_eq!(client_ip(&request("203.0.113.7, 198.51.100.4")), client);
+        assert_eq!(
+            client_ip(&request("10.0.0.1, 203.0.113.7,198.51.100.4")),
+            client
+        );
+    }
+}
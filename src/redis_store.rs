@@ -0,0 +1,608 @@
+//! `StoreAccess` backed by Redis so several instances can share links.
+//!
+//! `StoreAccess` is synchronous, so this wraps a blocking `redis::Connection`
+//! behind a `Mutex` instead of introducing an async variant of the trait. The
+//! tradeoff is that every call blocks the calling tokio worker for a network
+//! round trip and all calls on one instance are serialized on that connection.
+//! That is acceptable at shortener request volumes and keeps every backend on
+//! the same trait; a pooled async client is the way out if it stops being so.
+
+use crate::clock::{Clock, SystemClock};
+use crate::store::{
+    self, ClickInfo, LinkOptions, LinkRecord, StoreAccess, StoreError, MAX_CLICKS,
+    MAX_TOKEN_ATTEMPTS,
+};
+use crate::token::{Token, TokenConfig, TokenStrategy};
+use color_eyre::eyre::{eyre, Result};
+use redis::Commands;
+use std::collections::HashMap;
+use std::sync::atomic::AtomicU64;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use url::Url;
+
+pub struct RedisStore {
+    conn: Mutex<redis::Connection>,
+    /// Shape of freshly generated tokens.
+    pub token_config: TokenConfig,
+    clock: Arc<dyn Clock>,
+}
+
+impl RedisStore {
+    const KEY_PREFIX: &str = "link:";
+    const CLICKS_PREFIX: &str = "clicks:";
+    /// Token of a shareable link per URL, see `REGISTER_OR_GET`.
+    const URL_PREFIX: &str = "url:";
+    /// Counter behind `TokenStrategy::Sequential` tokens.
+    const SEQUENCE_KEY: &str = "sequence";
+
+    /// Connects to the server at `url`, e.g. `redis://127.0.0.1/`.
+    pub fn connect(url: &str) -> Result<Self> {
+        let conn = redis::Client::open(url)?.get_connection()?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+            token_config: TokenConfig::default(),
+            clock: Arc::new(SystemClock),
+        })
+    }
+
+    fn key(token: &str) -> String {
+        format!("{}{token}", Self::KEY_PREFIX)
+    }
+
+    fn clicks_key(token: &str) -> String {
+        format!("{}{token}", Self::CLICKS_PREFIX)
+    }
+
+    fn url_key(url: &Url) -> String {
+        format!("{}{url}", Self::URL_PREFIX)
+    }
+
+    /// Recovers from poisoning: no caller panics halfway through writing a command.
+    fn connection(&self) -> MutexGuard<'_, redis::Connection> {
+        self.conn.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Each link is a hash; expiry is left to Redis, so expired links read as missing.
+    fn insert(
+        &self,
+        token: &Token,
+        url: &Url,
+        ttl: Option<Duration>,
+        options: &LinkOptions,
+    ) -> Result<()> {
+        let created_at = self.clock.now().duration_since(UNIX_EPOCH)?.as_secs();
+        let script = redis::Script::new(INSERT);
+        let mut invocation = script.prepare_invoke();
+        invocation
+            .key(Self::key(token.as_str()))
+            .arg(url.as_str())
+            .arg(created_at)
+            .arg(ttl.map(|ttl| ttl.as_secs().to_string()).unwrap_or_default());
+        for (field, value) in Self::option_fields(options)? {
+            invocation.arg(field).arg(value);
+        }
+
+        let inserted: bool = invocation.invoke(&mut *self.connection())?;
+        if !inserted {
+            return Err(StoreError::AliasTaken.into());
+        }
+        Ok(())
+    }
+
+    /// The hash fields `options` set, absent ones left out like `record` expects.
+    fn option_fields(options: &LinkOptions) -> Result<Vec<(&'static str, String)>> {
+        let mut fields = Vec::new();
+        if let Some(hash) = &options.password_hash {
+            fields.push(("password_hash", hash.clone()));
+        }
+        if let Some(owner) = &options.owner {
+            fields.push(("owner", owner.clone()));
+        }
+        if let Some(max_hits) = options.max_hits {
+            fields.push(("max_hits", max_hits.to_string()));
+        }
+        if !options.destinations.is_empty() {
+            fields.push((
+                "destinations",
+                serde_json::to_string(&options.destinations)?,
+            ));
+        }
+        if !options.utm_params.is_empty() {
+            fields.push(("utm_params", serde_json::to_string(&options.utm_params)?));
+        }
+        Ok(fields)
+    }
+
+    /// Next candidate token under `token_config.strategy`.
+    fn generate_token(&self) -> Result<Token> {
+        match self.token_config.strategy {
+            TokenStrategy::Random => Ok(Token::generate(&self.token_config)?),
+            TokenStrategy::Sequential => {
+                let next: u64 = self.connection().incr(Self::SEQUENCE_KEY, 1)?;
+                Ok(Token::from_sequence(next - 1, self.token_config.charset))
+            }
+        }
+    }
+
+    /// Inserts under a freshly generated token, retrying while it is already taken.
+    fn insert_fresh(
+        &self,
+        url: &Url,
+        ttl: Option<Duration>,
+        options: &LinkOptions,
+    ) -> Result<Token> {
+        for _ in 0..MAX_TOKEN_ATTEMPTS {
+            let token = self.generate_token()?;
+            let token_str = token.as_str();
+            if self.token_config.is_reserved(token_str) || self.token_config.is_blocked(token_str) {
+                continue;
+            }
+            match self.insert(&token, url, ttl, options) {
+                Err(e) if e.downcast_ref::<StoreError>() == Some(&StoreError::AliasTaken) => {
+                    tracing::warn!("Token collision on {token}, retrying");
+                }
+                result => return result.map(|_| token),
+            }
+        }
+        Err(eyre!(
+            "Failed to generate an unused token after {MAX_TOKEN_ATTEMPTS} attempts"
+        ))
+    }
+
+    fn record(&self, conn: &mut redis::Connection, token: &str) -> Result<LinkRecord> {
+        let fields: HashMap<String, String> = conn.hgetall(Self::key(token))?;
+        let url = fields.get("url").ok_or_else(|| eyre!("Token not found"))?;
+        let field = |name: &str| -> Result<u64> {
+            Ok(fields
+                .get(name)
+                .map(|v| v.parse::<u64>())
+                .transpose()?
+                .unwrap_or_default())
+        };
+
+        Ok(LinkRecord {
+            url: Url::parse(url)?,
+            created_at: UNIX_EPOCH + Duration::from_secs(field("created_at")?),
+            expires_at: None,
+            hits: AtomicU64::new(field("hits")?),
+            password_hash: fields.get("password_hash").cloned(),
+            deleted: fields.contains_key("deleted"),
+            owner: fields.get("owner").cloned(),
+            max_hits: fields.get("max_hits").map(|v| v.parse()).transpose()?,
+            destinations: fields
+                .get("destinations")
+                .map(|v| serde_json::from_str(v))
+                .transpose()?
+                .unwrap_or_default(),
+            utm_params: fields
+                .get("utm_params")
+                .map(|v| serde_json::from_str(v))
+                .transpose()?
+                .unwrap_or_default(),
+        })
+    }
+
+    /// `record`, failing for tombstoned and used up links.
+    fn live_record(&self, conn: &mut redis::Connection, token: &str) -> Result<LinkRecord> {
+        let record = self.record(conn, token)?;
+        if record.deleted {
+            return Err(StoreError::Deleted.into());
+        }
+        if record.is_exhausted() {
+            return Err(StoreError::Exhausted.into());
+        }
+        Ok(record)
+    }
+}
+
+/// `RedisStore::insert` in one server-side step, so no link is seen half written. KEYS is
+/// the link's key; ARGV the URL, the creation time, the TTL in seconds or `""` for none,
+/// then field and value pairs of its options. Returns 0 if the key is taken.
+const INSERT: &str = r#"
+if redis.call('HSETNX', KEYS[1], 'url', ARGV[1]) == 0 then
+    return 0
+end
+redis.call('HSET', KEYS[1], 'created_at', ARGV[2], 'hits', 0)
+for i = 4, #ARGV, 2 do
+    redis.call('HSET', KEYS[1], ARGV[i], ARGV[i + 1])
+end
+if ARGV[3] ~= '' then
+    redis.call('EXPIRE', KEYS[1], ARGV[3])
+end
+return 1
+"#;
+
+/// `register_or_get` in one server-side step. KEYS are the URL's index entry and the key
+/// of a fresh candidate link; ARGV the URL, `KEY_PREFIX`, the creation time and the
+/// candidate token. Returns `{token, 0}` for an existing link, `{token, 1}` for the new
+/// one, or nil if the candidate is taken. Index entries are checked before they are used,
+/// since links change or expire without updating them.
+const REGISTER_OR_GET: &str = r#"
+local existing = redis.call('GET', KEYS[1])
+if existing then
+    local link = ARGV[2] .. existing
+    if redis.call('HGET', link, 'url') == ARGV[1] and redis.call('TTL', link) == -1 then
+        local extras = redis.call('HMGET', link, 'password_hash', 'deleted', 'owner',
+            'max_hits', 'destinations', 'utm_params')
+        local shareable = true
+        for _, value in ipairs(extras) do
+            if value then
+                shareable = false
+            end
+        end
+        if shareable then
+            return {existing, 0}
+        end
+    end
+end
+if redis.call('HSETNX', KEYS[2], 'url', ARGV[1]) == 0 then
+    return false
+end
+redis.call('HSET', KEYS[2], 'created_at', ARGV[3], 'hits', 0)
+redis.call('SET', KEYS[1], ARGV[4])
+return {ARGV[4], 1}
+"#;
+
+impl StoreAccess for RedisStore {
+    fn register_with_options(
+        &mut self,
+        url: Url,
+        alias: Option<&str>,
+        ttl: Option<Duration>,
+        options: LinkOptions,
+    ) -> Result<Token> {
+        let token = match alias {
+            Some(alias) => {
+                let token = Token::from_alias(alias)?;
+                if self.token_config.is_reserved(token.as_str()) {
+                    return Err(StoreError::AliasReserved.into());
+                }
+                self.insert(&token, &url, ttl, &options)?;
+                token
+            }
+            None => self.insert_fresh(&url, ttl, &options)?,
+        };
+
+        store::log_registration(&token, alias, ttl);
+        Ok(token)
+    }
+
+    fn register_or_get(&mut self, url: Url) -> Result<(Token, bool)> {
+        let created_at = self.clock.now().duration_since(UNIX_EPOCH)?.as_secs();
+        for _ in 0..MAX_TOKEN_ATTEMPTS {
+            let token = self.generate_token()?;
+            let token_str = token.as_str();
+            if self.token_config.is_reserved(token_str) || self.token_config.is_blocked(token_str) {
+                continue;
+            }
+            let registered: Option<(String, bool)> = redis::Script::new(REGISTER_OR_GET)
+                .key(Self::url_key(&url))
+                .key(Self::key(token_str))
+                .arg(url.as_str())
+                .arg(Self::KEY_PREFIX)
+                .arg(created_at)
+                .arg(token_str)
+                .invoke(&mut *self.connection())?;
+            match registered {
+                Some((existing, false)) => {
+                    return Ok((Token::parse_any(&existing, &self.token_config)?, false))
+                }
+                Some(_) => {
+                    tracing::info!("Registered a new token: {token}");
+                    return Ok((token, true));
+                }
+                None => tracing::warn!("Token collision on {token}, retrying"),
+            }
+        }
+        Err(eyre!(
+            "Failed to generate an unused token after {MAX_TOKEN_ATTEMPTS} attempts"
+        ))
+    }
+
+    fn check_alias(&self, alias: &str) -> Result<()> {
+        let token = Token::from_alias(alias)?;
+        if self.token_config.is_reserved(token.as_str()) {
+            return Err(StoreError::AliasReserved.into());
+        }
+        let taken: bool = self.connection().exists(Self::key(token.as_str()))?;
+        if taken {
+            return Err(StoreError::AliasTaken.into());
+        }
+        Ok(())
+    }
+
+    fn resolve_token(&self, token: &str) -> Result<Url> {
+        let mut conn = self.connection();
+        let record = self.live_record(&mut conn, token)?;
+        let hits: u64 = conn.hincr(Self::key(token), "hits", 1)?;
+        // Another instance may have taken the last hit since `live_record`
+        if record.max_hits.is_some_and(|max_hits| hits > max_hits) {
+            return Err(StoreError::Exhausted.into());
+        }
+        Ok(record.redirect_url(&mut rand::rng()))
+    }
+
+    fn link_info(&self, token: &str) -> Result<LinkRecord> {
+        let mut conn = self.connection();
+        self.live_record(&mut conn, token)
+    }
+
+    fn owner(&self, token: &str) -> Result<Option<String>> {
+        let mut conn = self.connection();
+        Ok(self.record(&mut conn, token)?.owner)
+    }
+
+    /// The tombstone is a `deleted` field on the link hash, so a TTL still removes it for good.
+    fn delete_token(&mut self, token: &str) -> Result<()> {
+        let mut conn = self.connection();
+        self.live_record(&mut conn, token)?;
+        let _: () = conn.hset(Self::key(token), "deleted", 1)?;
+
+        tracing::info!("Deleted token: {token}");
+        Ok(())
+    }
+
+    fn restore_token(&mut self, token: &str) -> Result<()> {
+        let mut conn = self.connection();
+        self.record(&mut conn, token)?;
+        let _: () = conn.hdel(Self::key(token), "deleted")?;
+
+        tracing::info!("Restored token: {token}");
+        Ok(())
+    }
+
+    fn update_url(&mut self, token: &str, new_url: Url) -> Result<()> {
+        let mut conn = self.connection();
+        self.live_record(&mut conn, token)?;
+        let _: () = conn.hset(Self::key(token), "url", new_url.as_str())?;
+
+        tracing::info!("Updated token: {token}");
+        Ok(())
+    }
+
+    fn set_password_hash(&mut self, token: &str, password_hash: Option<String>) -> Result<()> {
+        let key = Self::key(token);
+        let mut conn = self.connection();
+        self.live_record(&mut conn, token)?;
+        let _: () = match password_hash {
+            Some(hash) => conn.hset(&key, "password_hash", hash)?,
+            None => conn.hdel(&key, "password_hash")?,
+        };
+        Ok(())
+    }
+
+    fn set_owner(&mut self, token: &str, owner: Option<String>) -> Result<()> {
+        let key = Self::key(token);
+        let mut conn = self.connection();
+        self.live_record(&mut conn, token)?;
+        let _: () = match owner {
+            Some(owner) => conn.hset(&key, "owner", owner)?,
+            None => conn.hdel(&key, "owner")?,
+        };
+        Ok(())
+    }
+
+    fn set_max_hits(&mut self, token: &str, max_hits: Option<u64>) -> Result<()> {
+        let key = Self::key(token);
+        let mut conn = self.connection();
+        self.live_record(&mut conn, token)?;
+        let _: () = match max_hits {
+            Some(max_hits) => conn.hset(&key, "max_hits", max_hits)?,
+            None => conn.hdel(&key, "max_hits")?,
+        };
+        Ok(())
+    }
+
+    /// Kept as a JSON array of `[url, weight]` pairs in the `destinations` field.
+    fn set_destinations(&mut self, token: &str, destinations: Vec<(Url, u32)>) -> Result<()> {
+        let key = Self::key(token);
+        let mut conn = self.connection();
+        self.live_record(&mut conn, token)?;
+        let _: () = if destinations.is_empty() {
+            conn.hdel(&key, "destinations")?
+        } else {
+            conn.hset(&key, "destinations", serde_json::to_string(&destinations)?)?
+        };
+        Ok(())
+    }
+
+    /// Kept as a JSON array of `[name, value]` pairs in the `utm_params` field.
+    fn set_utm_params(&mut self, token: &str, utm_params: Vec<(String, String)>) -> Result<()> {
+        let key = Self::key(token);
+        let mut conn = self.connection();
+        self.live_record(&mut conn, token)?;
+        let _: () = if utm_params.is_empty() {
+            conn.hdel(&key, "utm_params")?
+        } else {
+            conn.hset(&key, "utm_params", serde_json::to_string(&utm_params)?)?
+        };
+        Ok(())
+    }
+
+    /// Nothing to do, Redis drops keys whose TTL ran out by itself.
+    /// Used up links stay until deleted, answering `StoreError::Exhausted`.
+    fn purge_expired(&mut self, _now: SystemTime) -> Result<usize> {
+        Ok(0)
+    }
+
+    fn clear(&mut self) -> Result<usize> {
+        let mut conn = self.connection();
+        let links: Vec<String> = conn
+            .scan_match::<_, String>(format!("{}*", Self::KEY_PREFIX))?
+            .collect();
+        let clicks: Vec<String> = conn
+            .scan_match::<_, String>(format!("{}*", Self::CLICKS_PREFIX))?
+            .collect();
+        let urls: Vec<String> = conn
+            .scan_match::<_, String>(format!("{}*", Self::URL_PREFIX))?
+            .collect();
+        let _: () = conn.del(Self::SEQUENCE_KEY)?;
+        for keys in [&links, &clicks, &urls] {
+            if !keys.is_empty() {
+                let _: () = conn.del(keys)?;
+            }
+        }
+
+        tracing::info!("Cleared {} links", links.len());
+        Ok(links.len())
+    }
+
+    /// Scans every link key, so this is O(total links) regardless of the page size.
+    fn list(&self, offset: usize, limit: usize) -> Result<Vec<(Token, LinkRecord)>> {
+        let mut conn = self.connection();
+        let pattern = format!("{}*", Self::KEY_PREFIX);
+        let keys: Vec<String> = conn.scan_match::<_, String>(&pattern)?.collect();
+
+        let mut links = Vec::with_capacity(keys.len());
+        for key in keys {
+            let token = &key[Self::KEY_PREFIX.len()..];
+            // A key may expire between the scan and the read
+            match self.record(&mut conn, token) {
+                Ok(record) => links.push((Token::parse_any(token, &self.token_config)?, record)),
+                Err(_) => continue,
+            }
+        }
+        links.sort_by(|(a_token, a), (b_token, b)| {
+            (a.created_at, a_token.as_str()).cmp(&(b.created_at, b_token.as_str()))
+        });
+        Ok(links.into_iter().skip(offset).take(limit).collect())
+    }
+
+    /// Clicks are a capped list next to the link hash, expiring along with it.
+    fn record_click(&self, token: &str, click: ClickInfo) -> Result<()> {
+        let key = Self::clicks_key(token);
+        let mut conn = self.connection();
+        let _: () = conn.lpush(&key, serde_json::to_string(&click)?)?;
+        let _: () = conn.ltrim(&key, 0, MAX_CLICKS as isize - 1)?;
+        let ttl: i64 = conn.ttl(Self::key(token))?;
+        if ttl > 0 {
+            let _: () = conn.expire(&key, ttl)?;
+        }
+        Ok(())
+    }
+
+    fn clicks(&self, token: &str) -> Result<Vec<ClickInfo>> {
+        let mut conn = self.connection();
+        self.live_record(&mut conn, token)?;
+        let clicks: Vec<String> = conn.lrange(Self::clicks_key(token), 0, -1)?;
+        clicks
+            .iter()
+            .map(|click| Ok(serde_json::from_str(click)?))
+            .collect()
+    }
+}
+
+/// These need a running server: `REDIS_URL` or `redis://127.0.0.1/`.
+/// Each test is skipped when nothing is listening.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn connect() -> Option<RedisStore> {
+        let url = std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1/".into());
+        match RedisStore::connect(&url) {
+            Ok(store) => Some(store),
+            Err(e) => {
+                eprintln!("Skipping Redis test, cannot connect to {url}: {e}");
+                None
+            }
+        }
+    }
+
+    /// Removes a test link outright, since `delete_token` leaves a tombstone behind.
+    fn purge(store: &RedisStore, token: &str) -> Result<()> {
+        let _: () = store
+            .connection()
+            .del(&[RedisStore::key(token), RedisStore::clicks_key(token)])?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_register_and_resolve() -> Result<()> {
+        let Some(mut store) = connect() else {
+            return Ok(());
+        };
+        let url = Url::parse("https://example.com")?;
+        let token = store.register_url(url.clone())?;
+
+        assert_eq!(store.resolve_token(token.as_str())?, url);
+        assert_eq!(store.hit_count(token.as_str())?, 1);
+        purge(&store, token.as_str())
+    }
+
+    #[test]
+    fn test_register_or_get() -> Result<()> {
+        let Some(mut store) = connect() else {
+            return Ok(());
+        };
+        let url = Url::parse(&format!("https://example.com/{}", Token::default()))?;
+
+        let (token, created) = store.register_or_get(url.clone())?;
+        assert!(created);
+        assert_eq!(store.register_or_get(url.clone())?, (token.clone(), false));
+
+        // Owned links aren't handed to other callers
+        store.set_owner(token.as_str(), Some("alpha".into()))?;
+        let (fresh, created) = store.register_or_get(url.clone())?;
+        assert!(created);
+        let _: () = store.connection().del(RedisStore::url_key(&url))?;
+        purge(&store, token.as_str())?;
+        purge(&store, fresh.as_str())
+    }
+
+    #[test]
+    fn test_register_taken_alias() -> Result<()> {
+        let Some(mut store) = connect() else {
+            return Ok(());
+        };
+        let alias = format!("alias-{}", Token::default());
+        store.register_url_with_alias(Url::parse("https://example1.com")?, &alias)?;
+
+        let err = store
+            .register_url_with_alias(Url::parse("https://example2.com")?, &alias)
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<StoreError>(),
+            Some(&StoreError::AliasTaken)
+        );
+        purge(&store, &alias)
+    }
+
+    #[test]
+    fn test_delete_token() -> Result<()> {
+        let Some(mut store) = connect() else {
+            return Ok(());
+        };
+        let token = store.register_url(Url::parse("https://example.com")?)?;
+
+        store.delete_token(token.as_str())?;
+        let err = store.resolve_token(token.as_str()).unwrap_err();
+        assert_eq!(err.downcast_ref::<StoreError>(), Some(&StoreError::Deleted));
+        assert!(store.delete_token(token.as_str()).is_err());
+
+        store.restore_token(token.as_str())?;
+        assert_eq!(
+            store.resolve_token(token.as_str())?.as_str(),
+            "https://example.com/"
+        );
+        purge(&store, token.as_str())
+    }
+
+    #[test]
+    fn test_click_log() -> Result<()> {
+        let Some(mut store) = connect() else {
+            return Ok(());
+        };
+        let token = store.register_url(Url::parse("https://example.com")?)?;
+        let click = ClickInfo {
+            at: UNIX_EPOCH,
+            referer: None,
+            user_agent: Some("curl/8.0".to_string()),
+        };
+
+        store.record_click(token.as_str(), click.clone())?;
+        assert_eq!(store.clicks(token.as_str())?, vec![click]);
+        purge(&store, token.as_str())
+    }
+}
@@ -0,0 +1,63 @@
+use axum::{
+    extract::{MatchedPath, Request},
+    response::Response,
+};
+use std::time::Duration;
+use tracing::Span;
+
+/// Span wrapping each request, see `create_router`.
+///
+/// `token` is only recorded on routes under `/{token}`, so lookups can be grepped per link.
+pub fn request_span(req: &Request) -> Span {
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(MatchedPath::as_str);
+    let token = route
+        .filter(|route| route.starts_with("/{token}"))
+        .and_then(|_| req.uri().path().split('/').nth(1));
+    tracing::info_span!(
+        "request",
+        method = %req.method(),
+        path = req.uri().path(),
+        token,
+    )
+}
+
+/// Logs the status and latency of a finished request within its span.
+pub fn log_response(response: &Response, latency: Duration, _span: &Span) {
+    let status = response.status();
+    tracing::info!(
+        status = status.as_u16(),
+        outcome = outcome(status),
+        latency_ms = latency.as_millis() as u64,
+        "finished request"
+    );
+}
+
+/// Coarse label so redirects and misses stand apart from other responses.
+fn outcome(status: axum::http::StatusCode) -> &'static str {
+    match status.as_u16() {
+        300..=399 => "redirect",
+        404 | 410 => "not_found",
+        400..=499 => "client_error",
+        500..=599 => "server_error",
+        _ => "ok",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::StatusCode;
+
+    #[test]
+    fn test_outcome() {
+        assert_eq!(outcome(StatusCode::TEMPORARY_REDIRECT), "redirect");
+        assert_eq!(outcome(StatusCode::NOT_FOUND), "not_found");
+        assert_eq!(outcome(StatusCode::GONE), "not_found");
+        assert_eq!(outcome(StatusCode::BAD_REQUEST), "client_error");
+        assert_eq!(outcome(StatusCode::INTERNAL_SERVER_ERROR), "server_error");
+        assert_eq!(outcome(StatusCode::OK), "ok");
+    }
+}
@@ -1,293 +1,6373 @@
-use crate::store::{Store, StoreAccess};
+use crate::async_store::AsyncStoreAccess;
+use crate::config::{CacheControl, Config, ExpiredBehavior, NotFoundBehavior, RedirectKind};
+use crate::error::{self, AppError};
+use crate::idempotency::IdempotencyCache;
+use crate::metrics::Metrics;
+use crate::normalize::Normalization;
+use crate::password;
+use crate::qr;
+use crate::rate_limit::{self, RateLimiter};
+use crate::request_log;
+use crate::snapshot;
+use crate::store::{ClickInfo, LinkOptions, LinkRecord, Store, StoreError};
+use crate::token::Token;
 use axum::{
-    extract::{Path, Request, State},
+    body::Bytes,
+    extract::{Path, Query, Request, State},
     http,
-    response::Redirect,
-    routing::{get, post},
-    Router,
+    middleware::{self, Next},
+    response::{Html, IntoResponse, Response},
+    routing::{delete, get, post, put},
+    Json, Router,
 };
+use chrono::{DateTime, SecondsFormat, Utc};
 use color_eyre::eyre::{eyre, Result};
-use std::sync::{Arc, Mutex};
-use url::Url;
+use http_body_util::LengthLimitError;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error::Error as _;
+use std::fmt::{self, Display};
+use std::hash::{Hash, Hasher};
+use std::net::Ipv4Addr;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::RwLock;
+use tower_http::compression::CompressionLayer;
+use tower_http::cors::{Any, CorsLayer};
+use tower_http::decompression::RequestDecompressionLayer;
+use tower_http::trace::TraceLayer;
+use url::{Host, Url};
+use utoipa::{OpenApi, ToSchema};
 
-pub fn create_router() -> Router {
-    let state = Arc::new(Mutex::new(AppState::default()));
+type SharedState = Arc<RwLock<AppState>>;
+
+/// Registrations accepted per client IP per minute.
+const REGISTRATIONS_PER_MINUTE: u32 = 30;
+
+/// Builds the app with a store of its own for each of `config.namespaces` and, unless
+/// disabled, starts sweeping expired links in the background. Also returns a handle on
+/// the state for use once the server has stopped.
+pub fn create_namespaced_router(
+    store: Box<dyn AsyncStoreAccess>,
+    namespaces: HashMap<String, Box<dyn AsyncStoreAccess>>,
+    config: Config,
+) -> (Router, AppHandle) {
+    let sweep_interval = config.sweep_interval;
+    let mut state = AppState::with_config(store, config);
+    state.namespaces = namespaces;
+    let cors = cors_layer(&state.cors_origins);
+    let state = Arc::new(RwLock::new(state));
+    if let Some(interval) = sweep_interval {
+        tokio::spawn(sweep_periodically(state.clone(), interval));
+    }
+    (routes(state.clone(), cors), AppHandle(state))
+}
+
+/// Access to the links behind a router from outside its handlers.
+pub struct AppHandle(SharedState);
+
+impl AppHandle {
+    /// Writes every link of the default store to `path` with `snapshot::save`.
+    /// Namespaces are left out.
+    pub async fn save_snapshot(&self, path: &std::path::Path) -> Result<()> {
+        let json = self.0.read().await.store.to_json().await?;
+        snapshot::save(path, &json)
+    }
+}
+
+fn routes(state: SharedState, cors: CorsLayer) -> Router {
+    let limiter = Arc::new(RateLimiter::per_minute(REGISTRATIONS_PER_MINUTE));
+    let rate_limited = middleware::from_fn_with_state(limiter, rate_limit::limit_by_ip);
+    let authorized = middleware::from_fn_with_state(state.clone(), require_api_key);
+    let admin = middleware::from_fn_with_state(state.clone(), require_admin_key);
+    // Static segments take precedence over `/{token}` in axum's router.
+    // The last route layer runs first, so rate limiting also covers bad keys.
     Router::new()
-        .route("/{token}", get(resolve_url))
-        .route("/", post(register_url))
+        .route("/healthz", get(healthz))
+        .route("/metrics", get(metrics))
+        .route("/favicon.ico", get(favicon))
+        .route("/openapi.json", get(openapi))
+        .route(
+            "/admin/links",
+            get(list_links)
+                .delete(clear_links)
+                .route_layer(admin.clone()),
+        )
+        .route(
+            "/admin/links/matching",
+            delete(delete_matching_links).route_layer(admin.clone()),
+        )
+        .route("/admin/usage", get(key_usage).route_layer(admin.clone()))
+        .route(
+            "/links",
+            get(list_own_links).route_layer(authorized.clone()),
+        )
+        .route("/available/{alias}", get(alias_availability))
+        .route("/admin/stats", get(store_stats).route_layer(admin.clone()))
+        .route("/admin/top", get(top_links).route_layer(admin.clone()))
+        .route(
+            "/admin/export",
+            get(export_links).route_layer(admin.clone()),
+        )
+        // Migrations may send the export gzipped, which `MAX_IMPORT_SIZE` still caps once inflated
+        .route(
+            "/admin/import",
+            post(import_links)
+                .route_layer(admin.clone())
+                .layer(RequestDecompressionLayer::new()),
+        )
+        // Only routes added before `route_layer` are covered, so GET and HEAD stay open
+        .route(
+            "/{token}",
+            put(update_url)
+                .delete(delete_url)
+                .route_layer(authorized.clone())
+                .get(resolve_url)
+                .head(peek_url),
+        )
+        // A single trailing slash, e.g. `/abc123/`, resolves like the bare token
+        .route("/{token}/", get(resolve_url).head(peek_url))
+        .route("/{token}/info", get(link_info))
+        .route("/{token}/stats", get(link_stats))
+        .route("/{token}/qr", get(link_qr))
+        .route("/{token}/qr.svg", get(link_qr_svg))
+        .route("/{token}/preview", get(link_preview))
+        .route(
+            "/{token}/restore",
+            post(restore_url).route_layer(authorized.clone()),
+        )
+        .route("/{token}/redirect", get(redirect_url))
+        .route(
+            "/{token}/clicks",
+            get(link_clicks).route_layer(admin.clone()),
+        )
+        // Loses to the static segments above, which `Config::NAMESPACE_RESERVED` keeps free
+        .route(
+            "/{namespace}/{token}",
+            get(resolve_namespaced_url).head(peek_namespaced_url),
+        )
+        .route(
+            "/",
+            post(register_url)
+                .route_layer(authorized.clone())
+                .route_layer(rate_limited.clone())
+                .get(index),
+        )
+        // Same as `POST /`, for proxies that mishandle posts to the root
+        .route(
+            "/shorten",
+            post(register_url)
+                .route_layer(authorized.clone())
+                .route_layer(rate_limited.clone()),
+        )
+        .route(
+            "/batch",
+            post(register_batch)
+                .route_layer(authorized)
+                .route_layer(rate_limited),
+        )
+        // Only covers the routes above; axum still adds their `Allow` header
+        .method_not_allowed_fallback(method_not_allowed)
+        .layer(middleware::from_fn(error::negotiate_format))
+        // Gzip or deflate per `Accept-Encoding`; tiny bodies such as redirects are left alone
+        .layer(CompressionLayer::new())
+        // Answers preflight `OPTIONS` requests before they reach the routes
+        .layer(cors)
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(request_log::request_span)
+                .on_response(request_log::log_response),
+        )
+        // Runs first, so nothing above sees headers from an untrusted client
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            strip_forwarded_headers,
+        ))
         .with_state(state)
 }
 
-struct AppState {
-    pub store: Box<dyn StoreAccess>,
-}
+fn cors_layer(origins: &[String]) -> CorsLayer {
+    let layer = CorsLayer::new()
+        .allow_methods(Any)
+        .allow_headers(Any)
+        .expose_headers([http::header::LOCATION]);
+    if origins.is_empty() {
+        return layer.allow_origin(Any);
+    }
+    let origins: Vec<http::HeaderValue> = origins
+        .iter()
+        .filter_map(|origin| http::HeaderValue::from_str(origin).ok())
+        .collect();
+    layer.allow_origin(origins)
+}
+
+struct AppState {
+    pub store: Box<dyn AsyncStoreAccess>,
+    /// URL schemes accepted on registration.
+    pub allowed_schemes: Vec<String>,
+    /// Longest target URL accepted on registration, in bytes.
+    pub max_url_length: usize,
+    /// Keys accepted as `Authorization: Bearer <key>` on registration; empty leaves it open.
+    pub api_keys: HashSet<String>,
+    /// Canonicalization applied to targets before they are stored.
+    pub normalization: Normalization,
+    pub metrics: Metrics,
+    /// Log `Referer` and `User-Agent` of each resolve, see `StoreAccess::record_click`.
+    pub record_clicks: bool,
+    /// Public URL short links are built on, overriding the request headers when set.
+    /// Keep a trailing slash to preserve a path prefix, e.g. `https://example.com/s/`.
+    pub base_url: Option<Url>,
+    /// Reject targets on loopback, private or link-local addresses, see `is_private_host`.
+    pub block_private_hosts: bool,
+    /// Origins allowed to call the API from a browser; empty allows any origin.
+    pub cors_origins: Vec<String>,
+    /// Status code `resolve_url` redirects with.
+    pub redirect_kind: RedirectKind,
+    /// Sent with link redirects, see `Config::redirect_cache_control`.
+    pub redirect_cache_control: CacheControl,
+    /// Most live links a single API key may own; `None` is unlimited.
+    pub link_quota: Option<usize>,
+    /// Show a countdown page for this many seconds instead of redirecting, see `interstitial`.
+    pub interstitial_seconds: Option<u64>,
+    /// Answer plain registrations of a known URL with its link, see `register_url`.
+    pub dedup: bool,
+    /// Links registered under an `Idempotency-Key`, keyed by owner and key.
+    pub idempotency: IdempotencyCache,
+    /// Stores behind `/{namespace}/{token}`, separate from `store` and from each other.
+    pub namespaces: HashMap<String, Box<dyn AsyncStoreAccess>>,
+    /// Answer to unknown tokens, see `not_found`.
+    pub not_found_behavior: NotFoundBehavior,
+    /// Shortest alias accepted on registration.
+    pub min_alias_length: usize,
+    /// Keep `x-forwarded-*` headers, see `strip_forwarded_headers`.
+    pub trusted_proxy: bool,
+    /// Hosts targets must be on, see `is_allowed_domain`; empty allows any.
+    pub domain_allowlist: Vec<String>,
+    /// Sent to `GET /` with a `302` in place of the landing page.
+    pub root_redirect: Option<Url>,
+    /// Answer to expired links, see `lookup_failure`.
+    pub expired_behavior: ExpiredBehavior,
+}
+
+impl AppState {
+    fn new(store: Box<dyn AsyncStoreAccess>) -> Self {
+        Self::with_config(store, Config::default())
+    }
+
+    fn with_config(store: Box<dyn AsyncStoreAccess>, config: Config) -> Self {
+        let normalization = config.normalization();
+        let redirect_cache_control = config.redirect_cache_control().clone();
+        Self {
+            store,
+            allowed_schemes: config.allowed_schemes,
+            max_url_length: config.max_url_length,
+            api_keys: config.api_keys,
+            normalization,
+            metrics: Metrics::default(),
+            record_clicks: config.record_clicks,
+            base_url: config.base_url,
+            block_private_hosts: config.block_private_hosts,
+            cors_origins: config.cors_origins,
+            redirect_kind: config.redirect_kind,
+            redirect_cache_control,
+            link_quota: config.link_quota,
+            interstitial_seconds: config.interstitial_seconds,
+            dedup: config.dedup,
+            idempotency: IdempotencyCache::new(config.idempotency_window),
+            namespaces: HashMap::new(),
+            not_found_behavior: config.not_found_behavior,
+            min_alias_length: config.min_alias_length,
+            trusted_proxy: config.trusted_proxy,
+            domain_allowlist: config.domain_allowlist,
+            root_redirect: config.root_redirect,
+            expired_behavior: config.expired_behavior,
+        }
+    }
+
+    /// The store of `namespace`, or the default one for `None`.
+    fn namespace_store(&self, namespace: Option<&str>) -> Option<&dyn AsyncStoreAccess> {
+        match namespace {
+            None => Some(&*self.store),
+            Some(namespace) => self.namespaces.get(namespace).map(|store| &**store),
+        }
+    }
+
+    /// Mutable `namespace_store`.
+    fn namespace_store_mut(
+        &mut self,
+        namespace: Option<&str>,
+    ) -> Option<&mut Box<dyn AsyncStoreAccess>> {
+        match namespace {
+            None => Some(&mut self.store),
+            Some(namespace) => self.namespaces.get_mut(namespace),
+        }
+    }
+
+    fn base_url(&self, headers: &http::HeaderMap) -> Result<Url> {
+        match &self.base_url {
+            Some(base_url) => Ok(base_url.clone()),
+            None => extract_base_url(headers),
+        }
+    }
+
+    /// The configured API key a request is made with, which then owns what it registers.
+    fn request_owner(&self, req: &Request) -> Option<String> {
+        bearer_key(req.headers())
+            .filter(|key| self.api_keys.contains(*key))
+            .map(String::from)
+    }
+
+    /// Rejects changes to a link registered with another API key with `403`. Links
+    /// registered without a key are left to anyone allowed through `require_api_key`.
+    async fn check_owner(&self, token: &str, headers: &http::HeaderMap) -> Result<(), AppError> {
+        match self.store.owner(token).await.map_err(lookup_error)? {
+            Some(owner) if bearer_key(headers) != Some(owner.as_str()) => Err(AppError::new(
+                http::StatusCode::FORBIDDEN,
+                "The link belongs to another API key",
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Rejects aliases shorter than `min_alias_length` with `400`.
+    fn check_alias_length(&self, alias: &str) -> Result<(), AppError> {
+        if alias.len() < self.min_alias_length {
+            return Err(AppError::bad_request(format!(
+                "Alias must be at least {} characters long",
+                self.min_alias_length
+            )));
+        }
+        Ok(())
+    }
+
+    /// Fails when `owner` can't take `count` more links without exceeding `link_quota`.
+    async fn check_quota(&self, owner: Option<&str>, count: usize) -> Result<(), AppError> {
+        let (Some(quota), Some(owner)) = (self.link_quota, owner) else {
+            return Ok(());
+        };
+        let usage = self
+            .store
+            .usage(SystemTime::now())
+            .await
+            .map_err(AppError::internal)?;
+        if usage.get(owner).copied().unwrap_or(0) + count > quota {
+            return Err(AppError::new(
+                http::StatusCode::TOO_MANY_REQUESTS,
+                format!("This API key has reached its quota of {quota} links"),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks a registration target against the configured policy.
+    fn validate_target(&self, target: &Url, base_url: &Url) -> Result<()> {
+        if !self.allowed_schemes.iter().any(|s| s == target.scheme()) {
+            return Err(eyre!("URL scheme '{}' is not allowed", target.scheme()));
+        }
+        // Pointing back at ourselves would allow chains and redirect loops
+        if target.host() == base_url.host() {
+            return Err(eyre!("URL must not point at the shortener itself"));
+        }
+        if self.block_private_hosts && target.host().is_some_and(is_private_host) {
+            return Err(eyre!("URL must not point at a private or local address"));
+        }
+        if !self.domain_allowlist.is_empty() {
+            let host = target.host_str().unwrap_or_default();
+            if !is_allowed_domain(host, &self.domain_allowlist) {
+                return Err(DomainNotAllowed(host.to_string()).into());
+            }
+        }
+        if target.as_str().len() > self.max_url_length {
+            return Err(eyre!(
+                "URL must be at most {} characters long",
+                self.max_url_length
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// A target outside `AppState::domain_allowlist`, answered with `403` rather than `400`.
+#[derive(Debug)]
+struct DomainNotAllowed(String);
+
+impl Display for DomainNotAllowed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Links to {:?} are not allowed here", self.0)
+    }
+}
+
+impl std::error::Error for DomainNotAllowed {}
+
+/// Whether `host` is on `allowlist`, where `*.example.com` stands for any subdomain of
+/// `example.com` but not `example.com` itself. Entries are normalized like `Url` hosts.
+fn is_allowed_domain(host: &str, allowlist: &[String]) -> bool {
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    allowlist
+        .iter()
+        .any(|entry| match entry.strip_prefix("*.") {
+            Some(parent) => host
+                .strip_suffix(parent)
+                .is_some_and(|sub| sub.len() > 1 && sub.ends_with('.')),
+            None => host == *entry,
+        })
+}
+
+/// Whether `host` is an internal address, e.g. `127.0.0.1`, `10.0.0.1` or `169.254.169.254`.
+///
+/// Only literal IPs and `localhost` names are caught; hostnames are not resolved,
+/// so a public name pointing at a private address still gets through.
+fn is_private_host(host: Host<&str>) -> bool {
+    match host {
+        Host::Domain(domain) => {
+            let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+            domain == "localhost" || domain.ends_with(".localhost")
+        }
+        Host::Ipv4(ip) => is_private_ipv4(ip),
+        Host::Ipv6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_private_ipv4(ip),
+            None => {
+                ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local()
+            }
+        },
+    }
+}
+
+fn is_private_ipv4(ip: Ipv4Addr) -> bool {
+    ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+}
+
+impl Default for AppState {
+    fn default() -> Self {
+        Self::new(Box::new(Store::default()))
+    }
+}
+
+/// Room for the JSON framing and optional fields around the URL itself.
+const JSON_BODY_SLACK: usize = 1024;
+
+/// Most URLs accepted by a single `POST /batch`.
+const MAX_BATCH_SIZE: usize = 100;
+
+/// Longest `Idempotency-Key` accepted on registration.
+const MAX_IDEMPOTENCY_KEY_LENGTH: usize = 255;
+
+/// Marker for request bodies over the configured limit, mapped to `413`.
+#[derive(Debug)]
+struct PayloadTooLarge;
+
+impl Display for PayloadTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Request body too large")
+    }
+}
+
+impl std::error::Error for PayloadTooLarge {}
+
+/// JSON form of a registration request.
+#[derive(Deserialize, ToSchema, Hash)]
+struct RegisterRequest {
+    url: Url,
+    alias: Option<String>,
+    /// One of `Config::namespaces`; the link is then served at `/{namespace}/{token}`.
+    namespace: Option<String>,
+    /// Required to follow the link; only the hash is stored.
+    password: Option<String>,
+    /// Resolves allowed before the link is gone, e.g. `1` for a one-time share.
+    max_hits: Option<u64>,
+    /// Targets each resolve picks from by weight instead of `url`, e.g. for A/B tests;
+    /// `url` remains the link's target everywhere else. At most `MAX_DESTINATIONS`.
+    #[serde(default)]
+    destinations: Vec<Destination>,
+    /// Campaign tags added to the target's query on every redirect, e.g.
+    /// `{"utm_source": "newsletter"}`; parameters the target already has are kept.
+    /// Keys must be among `UTM_PARAMS`.
+    #[serde(default)]
+    utm: BTreeMap<String, String>,
+}
+
+/// One weighted target of `RegisterRequest::destinations`.
+#[derive(Deserialize, ToSchema, Hash)]
+struct Destination {
+    url: Url,
+    /// Relative share of resolves, at least 1.
+    weight: u32,
+}
+
+/// Most `RegisterRequest::destinations` a link may split between.
+const MAX_DESTINATIONS: usize = 10;
+
+/// Query parameters `RegisterRequest::utm` may set.
+const UTM_PARAMS: [&str; 5] = [
+    "utm_source",
+    "utm_medium",
+    "utm_campaign",
+    "utm_term",
+    "utm_content",
+];
+
+/// JSON form of an update request.
+#[derive(Deserialize, ToSchema)]
+struct UrlBody {
+    url: Url,
+}
+
+/// Query string accepted by `resolve_url`.
+#[derive(Default, Deserialize)]
+struct ResolveParams {
+    /// Password of a protected link; the `x-link-password` header keeps it out of access logs.
+    pw: Option<String>,
+}
+
+/// Query string accepted by `redirect_url`, next to `ResolveParams`.
+#[derive(Deserialize)]
+struct DryRunParams {
+    /// Describe the redirect in the body instead of answering with it.
+    #[serde(default)]
+    dry_run: bool,
+}
+
+/// JSON body returned by `redirect_url` on a dry run.
+#[derive(Debug, Serialize, ToSchema)]
+struct DryRunResponse {
+    /// Where the link leads; one pick for links split between destinations.
+    url: String,
+    /// Status the redirect would have, `200` when an interstitial page is shown.
+    status: u16,
+    hits: u64,
+    /// RFC 3339 in UTC, absent for links that never expire.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_hits: Option<u64>,
+}
+
+/// Header carrying the password of a protected link.
+const PASSWORD_HEADER: &str = "x-link-password";
+
+/// JSON body returned by `register_url`.
+#[derive(Serialize, ToSchema)]
+struct RegisterResponse {
+    short_url: String,
+    token: String,
+}
+
+/// Outcome for one URL of a `POST /batch`; exactly one of `short_url` and `error` is set.
+#[derive(Debug, Serialize, ToSchema)]
+struct BatchItemResponse {
+    url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    short_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// JSON body returned by `link_info`.
+#[derive(Debug, Serialize, ToSchema)]
+struct LinkInfoResponse {
+    url: String,
+    /// RFC 3339 in UTC, e.g. `2025-01-31T12:00:00Z`.
+    created_at: String,
+    hits: u64,
+    /// Only with `?debug=true` and an API key.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    token_encodings: Option<TokenEncodings>,
+}
+
+impl From<LinkRecord> for LinkInfoResponse {
+    fn from(record: LinkRecord) -> Self {
+        Self {
+            url: record.url.to_string(),
+            created_at: rfc3339(record.created_at),
+            hits: record.hits.load(Ordering::Relaxed),
+            token_encodings: None,
+        }
+    }
+}
+
+/// The token's raw forms, for tracking down collisions.
+#[derive(Debug, Serialize, ToSchema)]
+struct TokenEncodings {
+    /// Bytes of the token in hex.
+    hex: String,
+    /// Decimal value of the token read as base62, see `Token::to_base62`; absent for aliases
+    /// with `-` or `_`. A string since it may not fit a JSON number.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    base62: Option<String>,
+}
+
+impl From<&Token> for TokenEncodings {
+    fn from(token: &Token) -> Self {
+        Self {
+            hex: token.to_hex(),
+            base62: token.to_base62().map(|n| n.to_string()),
+        }
+    }
+}
+
+/// Query string accepted by `link_info`.
+#[derive(Default, Deserialize)]
+struct InfoParams {
+    /// Add `LinkInfoResponse::token_encodings`; needs an API key.
+    #[serde(default)]
+    debug: bool,
+}
+
+/// Query string accepted by `delete_matching_links`; links must meet every criterion given.
+#[derive(Deserialize)]
+struct DeleteMatchingParams {
+    /// Start of the token, e.g. `promo-`.
+    prefix: Option<String>,
+    /// RFC 3339 or seconds since the Unix epoch; only links created earlier match.
+    created_before: Option<String>,
+}
+
+/// Query string accepted by `list_links`.
+#[derive(Deserialize)]
+struct ListParams {
+    #[serde(default)]
+    offset: usize,
+    #[serde(default = "ListParams::default_limit")]
+    limit: usize,
+}
+
+impl ListParams {
+    const DEFAULT_LIMIT: usize = 50;
+    const MAX_LIMIT: usize = 500;
+
+    fn default_limit() -> usize {
+        Self::DEFAULT_LIMIT
+    }
+}
+
+/// One entry of the JSON array returned by `list_links` and `list_own_links`.
+#[derive(Debug, Serialize, ToSchema)]
+struct ListedLink {
+    token: String,
+    url: String,
+    /// RFC 3339 in UTC, like `LinkInfoResponse::created_at`.
+    created_at: String,
+}
+
+impl ListedLink {
+    fn new(token: Token, record: LinkRecord) -> Self {
+        Self {
+            token: token.to_string(),
+            url: record.url.to_string(),
+            created_at: rfc3339(record.created_at),
+        }
+    }
+}
+
+/// Query string accepted by `top_links`.
+#[derive(Deserialize)]
+struct TopParams {
+    #[serde(default = "TopParams::default_limit")]
+    limit: usize,
+}
+
+impl TopParams {
+    const DEFAULT_LIMIT: usize = 10;
+    const MAX_LIMIT: usize = 100;
+
+    fn default_limit() -> usize {
+        Self::DEFAULT_LIMIT
+    }
+}
+
+/// One entry of the JSON array returned by `top_links`.
+#[derive(Debug, Serialize, ToSchema)]
+struct TopLink {
+    token: String,
+    url: String,
+    hits: u64,
+}
+
+/// Body of `GET /admin/usage`.
+#[derive(Debug, Serialize, ToSchema)]
+struct UsageResponse {
+    quota: Option<usize>,
+    /// Live links per configured API key, including keys without any.
+    links: BTreeMap<String, usize>,
+}
+
+/// Body of `GET /admin/stats`.
+#[derive(Debug, Serialize, ToSchema)]
+struct StatsResponse {
+    /// Links that aren't deleted, including expired ones the sweeper hasn't purged yet.
+    total_links: usize,
+    /// Redirects counted on stored links.
+    total_redirects: u64,
+    /// Links that expired or used up their hits, awaiting the sweeper.
+    expired_count: usize,
+}
+
+/// One line of the newline-delimited JSON written by `export_links` and read by `import_links`.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+struct ExportedLink {
+    token: String,
+    url: Url,
+}
+
+const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+
+/// Largest body accepted by `import_links`, counted after any gzip is inflated.
+const MAX_IMPORT_SIZE: usize = 16 * 1024 * 1024;
+
+/// Body of `DELETE /admin/links`.
+#[derive(Debug, Serialize, ToSchema)]
+struct ClearResponse {
+    deleted: usize,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct ImportResponse {
+    imported: usize,
+    /// Links left out, e.g. because their token is already in use.
+    skipped: Vec<SkippedLink>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct SkippedLink {
+    token: String,
+    error: String,
+}
+
+/// One entry of the JSON array returned by `link_clicks`.
+#[derive(Debug, Serialize, ToSchema)]
+struct ClickResponse {
+    /// Seconds since the Unix epoch.
+    at: u64,
+    referer: Option<String>,
+    user_agent: Option<String>,
+}
+
+impl From<ClickInfo> for ClickResponse {
+    fn from(click: ClickInfo) -> Self {
+        Self {
+            at: epoch_secs(click.at),
+            referer: click.referer,
+            user_agent: click.user_agent,
+        }
+    }
+}
+
+/// JSON body returned by `link_stats`.
+#[derive(Debug, Serialize, ToSchema)]
+struct LinkStatsResponse {
+    hits: u64,
+    /// RFC 3339 in UTC, like `LinkInfoResponse::created_at`.
+    created_at: String,
+}
+
+// Helpers
+fn epoch_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn rfc3339(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time).to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+fn extract_base_url(headers: &http::HeaderMap) -> Result<Url> {
+    // Check for forwarded protocol (https/http)
+    let proto = headers
+        .get("x-forwarded-proto")
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or("http");
+
+    // Check for forwarded host
+    let host = headers
+        .get("x-forwarded-host")
+        .or_else(|| headers.get("host"))
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or("localhost");
+
+    Url::parse(&format!("{}://{}", proto, host))
+        .map_err(|e| eyre!("Failed to parse base URL: {}", e))
+}
+
+/// Lifetime asked for with `x-expires-in` (seconds) or `x-expires-at` (a timestamp).
+fn extract_ttl(req: &Request) -> Result<Option<Duration>> {
+    let headers = req.headers();
+    match (headers.get("x-expires-in"), headers.get("x-expires-at")) {
+        (Some(_), Some(_)) => Err(eyre!("Send either x-expires-in or x-expires-at, not both")),
+        (Some(h), None) => {
+            let secs = h.to_str()?.parse::<u64>()?;
+            Ok(Some(Duration::from_secs(secs)))
+        }
+        (None, Some(h)) => {
+            let expires_at = parse_timestamp(h.to_str()?)?;
+            match expires_at.duration_since(SystemTime::now()) {
+                Ok(ttl) if !ttl.is_zero() => Ok(Some(ttl)),
+                _ => Err(eyre!("x-expires-at must be in the future")),
+            }
+        }
+        (None, None) => Ok(None),
+    }
+}
+
+/// The `Idempotency-Key` header, scoped to `owner` so API keys can't replay each other's links.
+fn extract_idempotency_key(req: &Request, owner: Option<&str>) -> Result<Option<String>> {
+    let Some(h) = req.headers().get("idempotency-key") else {
+        return Ok(None);
+    };
+    let key = h.to_str()?;
+    if key.is_empty() || key.len() > MAX_IDEMPOTENCY_KEY_LENGTH {
+        return Err(eyre!(
+            "Idempotency-Key must be 1 to {MAX_IDEMPOTENCY_KEY_LENGTH} characters"
+        ));
+    }
+    // Header values can't contain a newline, so the two parts can't run together
+    Ok(Some(format!("{}\n{key}", owner.unwrap_or_default())))
+}
+
+/// RFC 3339, e.g. `2030-01-31T12:00:00Z`, or seconds since the Unix epoch.
+fn parse_timestamp(value: &str) -> Result<SystemTime> {
+    if let Ok(secs) = value.parse::<u64>() {
+        return Ok(UNIX_EPOCH + Duration::from_secs(secs));
+    }
+    let time = DateTime::parse_from_rfc3339(value)
+        .map_err(|e| eyre!("Invalid timestamp {value:?}: {e}"))?;
+    Ok(time.into())
+}
+
+/// `409` for registrations clashing with an existing link, `fallback` for anything else.
+fn conflict_or(e: color_eyre::Report, fallback: fn(color_eyre::Report) -> AppError) -> AppError {
+    match e.downcast_ref::<StoreError>() {
+        Some(StoreError::AliasTaken | StoreError::AliasReserved | StoreError::DuplicateUrl) => {
+            AppError::new(http::StatusCode::CONFLICT, e)
+        }
+        _ => fallback(e),
+    }
+}
+
+/// Error for a target `AppState::validate_target` refused.
+fn target_error(e: color_eyre::Report) -> AppError {
+    if e.downcast_ref::<DomainNotAllowed>().is_some() {
+        return AppError::new(http::StatusCode::FORBIDDEN, e);
+    }
+    AppError::bad_request(e)
+}
+
+/// Error for a failed token lookup.
+fn lookup_error(e: color_eyre::Report) -> AppError {
+    if is_gone(&e) {
+        return AppError::new(http::StatusCode::GONE, e);
+    }
+    AppError::not_found(e)
+}
+
+/// Whether a lookup failed because the link existed but can't be followed anymore.
+fn is_gone(e: &color_eyre::Report) -> bool {
+    matches!(
+        e.downcast_ref::<StoreError>(),
+        Some(StoreError::Expired | StoreError::Deleted | StoreError::Exhausted)
+    )
+}
+
+fn bearer_key(headers: &http::HeaderMap) -> Option<&str> {
+    headers
+        .get(http::header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+}
+
+fn unauthorized() -> Response {
+    (
+        [(http::header::WWW_AUTHENTICATE, "Bearer")],
+        AppError::new(http::StatusCode::UNAUTHORIZED, "Missing or invalid API key"),
+    )
+        .into_response()
+}
+
+// Routes
+/// Middleware rejecting requests without a configured API key with `401`.
+async fn require_api_key(
+    State(state): State<SharedState>,
+    req: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let authorized = {
+        let state = state.read().await;
+        state.api_keys.is_empty()
+            || bearer_key(req.headers()).is_some_and(|k| state.api_keys.contains(k))
+    };
+    if !authorized {
+        return Ok(unauthorized());
+    }
+    Ok(next.run(req).await)
+}
+
+/// Like `require_api_key`, but admin routes stay closed while no keys are configured.
+async fn require_admin_key(
+    State(state): State<SharedState>,
+    req: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let authorized = {
+        let state = state.read().await;
+        bearer_key(req.headers()).is_some_and(|k| state.api_keys.contains(k))
+    };
+    if !authorized {
+        return Ok(unauthorized());
+    }
+    Ok(next.run(req).await)
+}
+
+/// Middleware dropping `Forwarded` and `x-forwarded-*` headers unless `trusted_proxy`
+/// is set, so handlers fall back to `Host` and the socket peer.
+async fn strip_forwarded_headers(
+    State(state): State<SharedState>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    if !state.read().await.trusted_proxy {
+        let headers = req.headers_mut();
+        let forwarded: Vec<http::HeaderName> = headers
+            .keys()
+            .filter(|name| name.as_str().starts_with("x-forwarded-"))
+            .cloned()
+            .collect();
+        for name in forwarded {
+            headers.remove(name);
+        }
+        headers.remove(http::header::FORWARDED);
+    }
+    next.run(req).await
+}
+
+/// Usage summary served on `GET /`.
+const INDEX_HTML: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head><meta charset="utf-8"><title>URL shortener</title></head>
+<body>
+<h1>URL shortener</h1>
+<ul>
+<li><code>POST /</code> with a URL (or JSON <code>{"url": ...}</code>) as the body, or <code>POST /?url=...</code>, returns its short link; <code>POST /shorten</code> does the same.</li>
+<li><code>POST /batch</code> with a JSON array of URLs shortens up to 100 at once.</li>
+<li><code>GET /links</code> lists the links registered with your API key.</li>
+<li><code>GET /available/{alias}</code> tells whether an alias is still free.</li>
+<li><code>GET /{token}</code> redirects to the original URL.</li>
+<li><code>GET /{token}/preview</code> shows where a link leads without redirecting.</li>
+<li><code>DELETE /{token}</code> disables a link; <code>POST /{token}/restore</code> brings it back.</li>
+<li><code>GET /{token}/info</code> (or <code>/{token}.json</code>), <code>/{token}/stats</code>, <code>/{token}/qr</code> and <code>/{token}/qr.svg</code> describe a link.</li>
+</ul>
+</body>
+</html>
+"#;
+
+/// The landing page, unless `AppState::root_redirect` sends visitors elsewhere.
+async fn index(State(state): State<SharedState>) -> Response {
+    match &state.read().await.root_redirect {
+        Some(url) => redirect(RedirectKind::Temporary, url.as_str()),
+        None => Html(INDEX_HTML).into_response(),
+    }
+}
+
+#[utoipa::path(get, path = "/healthz", responses((status = 200, description = "The service is up", body = String)))]
+async fn healthz() -> &'static str {
+    "ok"
+}
+
+/// Drops expired links once, namespaces included. The write lock is held for this
+/// single sweep only, so resolves queue behind one sweep at a time rather than the whole loop.
+async fn sweep_expired(state: &SharedState) -> Result<usize> {
+    let mut state = state.write().await;
+    let now = SystemTime::now();
+    let mut purged = state.store.purge_expired(now).await?;
+    for store in state.namespaces.values_mut() {
+        purged += store.purge_expired(now).await?;
+    }
+    if purged > 0 {
+        tracing::info!("Swept {purged} expired links");
+    }
+    Ok(purged)
+}
+
+async fn sweep_periodically(state: SharedState, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        ticker.tick().await;
+        if let Err(e) = sweep_expired(&state).await {
+            tracing::error!("Sweeping expired links failed: {e}");
+        }
+    }
+}
+
+/// OpenAPI 3 description of the routes, served on `GET /openapi.json`.
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "URL shortener"),
+    paths(
+        register_url,
+        register_batch,
+        resolve_url,
+        peek_url,
+        redirect_url,
+        resolve_namespaced_url,
+        peek_namespaced_url,
+        update_url,
+        delete_url,
+        restore_url,
+        link_info,
+        link_stats,
+        link_preview,
+        link_qr,
+        link_qr_svg,
+        link_clicks,
+        list_links,
+        list_own_links,
+        alias_availability,
+        clear_links,
+        delete_matching_links,
+        key_usage,
+        store_stats,
+        top_links,
+        export_links,
+        import_links,
+        healthz,
+        metrics,
+    )
+)]
+struct ApiDoc;
+
+async fn openapi() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+/// Browsers ask for this on every page; answering here keeps it out of token lookups.
+async fn favicon() -> http::StatusCode {
+    http::StatusCode::NO_CONTENT
+}
+
+/// A known path asked for with a method it doesn't take, e.g. `POST /{token}`.
+async fn method_not_allowed(method: http::Method) -> AppError {
+    AppError::new(
+        http::StatusCode::METHOD_NOT_ALLOWED,
+        format!("Method {method} not allowed here"),
+    )
+}
+
+#[utoipa::path(get, path = "/metrics", responses((status = 200, description = "Counters in the Prometheus text format", body = String)))]
+async fn metrics(State(state): State<SharedState>) -> Result<Response, AppError> {
+    let body = state.read().await.metrics.render();
+    Ok(([(http::header::CONTENT_TYPE, Metrics::CONTENT_TYPE)], body).into_response())
+}
+
+/// Reads the body, refusing to buffer more than `limit` bytes.
+async fn read_body(req: Request, limit: usize) -> Result<Bytes> {
+    let declared_length = req
+        .headers()
+        .get(http::header::CONTENT_LENGTH)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.parse::<usize>().ok());
+    if declared_length.is_some_and(|len| len > limit) {
+        return Err(PayloadTooLarge.into());
+    }
+
+    axum::body::to_bytes(req.into_body(), limit)
+        .await
+        .map_err(|e| match e.source() {
+            Some(source) if source.is::<LengthLimitError>() => PayloadTooLarge.into(),
+            _ => e.into(),
+        })
+}
+
+/// Reads the target from the body, either as raw text or as `{"url": ...}` JSON.
+async fn extract_body_url(req: Request, max_url_length: usize) -> Result<Url> {
+    if !is_json_request(&req) {
+        let body = read_body(req, max_url_length).await?;
+        return parse_url(&body);
+    }
+
+    let body = read_body(req, max_url_length + JSON_BODY_SLACK).await?;
+    let UrlBody { url } =
+        serde_json::from_slice(&body).map_err(|e| eyre!("Failed to parse request body: {}", e))?;
+    if url.as_str().len() > max_url_length {
+        return Err(PayloadTooLarge.into());
+    }
+    Ok(url)
+}
+
+fn is_json_request(req: &Request) -> bool {
+    req.headers()
+        .get(http::header::CONTENT_TYPE)
+        .and_then(|h| h.to_str().ok())
+        .is_some_and(|ct| ct.starts_with("application/json"))
+}
+
+/// Parses a raw target. Hosts come out in their ASCII form, so `http://例え.jp`
+/// is stored and redirected to as `http://xn--r8jz45g.jp/`.
+fn parse_url(body: &[u8]) -> Result<Url> {
+    let str = std::str::from_utf8(body).map_err(|_| eyre!("Body is not valid UTF-8"))?;
+    Url::parse(str).map_err(|e| eyre!("Failed to parse URL: {}", e))
+}
+
+/// The `url` query parameter, e.g. `POST /?url=https://example.com`.
+fn query_url(req: &Request) -> Option<String> {
+    url::form_urlencoded::parse(req.uri().query()?.as_bytes())
+        .find(|(name, _)| name == "url")
+        .map(|(_, value)| value.into_owned())
+}
+
+/// Reads the target from the body, falling back to the `url` query parameter when it is empty.
+async fn extract_register_request(req: Request, max_url_length: usize) -> Result<RegisterRequest> {
+    let is_json = is_json_request(&req);
+    let query_url = query_url(&req);
+
+    let limit = if is_json {
+        (1 + MAX_DESTINATIONS) * max_url_length + JSON_BODY_SLACK
+    } else {
+        max_url_length
+    };
+    let body = read_body(req, limit).await?;
+
+    let request = if body.is_empty() {
+        let url = query_url.ok_or_else(|| {
+            eyre!("Missing URL, send it as the request body or a `url` query parameter")
+        })?;
+        RegisterRequest {
+            url: parse_url(url.as_bytes())?,
+            alias: None,
+            namespace: None,
+            password: None,
+            max_hits: None,
+            destinations: Vec::new(),
+            utm: BTreeMap::new(),
+        }
+    } else if is_json {
+        serde_json::from_slice(&body).map_err(|e| eyre!("Failed to parse request body: {}", e))?
+    } else {
+        RegisterRequest {
+            url: parse_url(&body)?,
+            alias: None,
+            namespace: None,
+            password: None,
+            max_hits: None,
+            destinations: Vec::new(),
+            utm: BTreeMap::new(),
+        }
+    };
+    let mut urls = std::iter::once(&request.url).chain(request.destinations.iter().map(|d| &d.url));
+    if urls.any(|url| url.as_str().len() > max_url_length) {
+        return Err(PayloadTooLarge.into());
+    }
+    Ok(request)
+}
+
+/// Base URL and longest accepted target for a registration-like request with `headers`.
+/// Takes the headers rather than the `Request`, whose body isn't `Sync`, so the
+/// handlers awaiting this stay `Send`.
+async fn registration_limits(
+    state: &SharedState,
+    headers: &http::HeaderMap,
+) -> Result<(Url, usize), AppError> {
+    let state = state.read().await;
+    let base_url = state.base_url(headers).map_err(AppError::internal)?;
+    Ok((base_url, state.max_url_length))
+}
+
+fn body_error(e: color_eyre::Report) -> AppError {
+    if e.downcast_ref::<PayloadTooLarge>().is_some() {
+        AppError::new(http::StatusCode::PAYLOAD_TOO_LARGE, e)
+    } else {
+        AppError::bad_request(e)
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/{token}",
+    params(
+        ("token" = String, Path, description = "Token of the short link"),
+        ("pw" = Option<String>, Query, description = "Password of a protected link"),
+    ),
+    responses(
+        (status = 303, description = "Redirect to the target; the status follows `SHORTENER_REDIRECT_KIND`"),
+        (status = 401, description = "The link is password protected"),
+        (status = 404, description = "Unknown token"),
+        (status = 410, description = "The link expired or was deleted"),
+    )
+)]
+async fn resolve_url(
+    State(state): State<SharedState>,
+    Path(token): Path<String>,
+    Query(params): Query<ResolveParams>,
+    headers: http::HeaderMap,
+) -> Result<Response, AppError> {
+    resolve(state, token, &params, &headers, true).await
+}
+
+#[utoipa::path(
+    head,
+    path = "/{token}",
+    params(
+        ("token" = String, Path, description = "Token of the short link"),
+        ("pw" = Option<String>, Query, description = "Password of a protected link"),
+    ),
+    responses(
+        (status = 303, description = "The redirect `GET` would answer, without a body or counting a hit"),
+        (status = 401, description = "The link is password protected"),
+        (status = 404, description = "Unknown token"),
+        (status = 410, description = "The link expired or was deleted"),
+    )
+)]
+async fn peek_url(
+    State(state): State<SharedState>,
+    Path(token): Path<String>,
+    Query(params): Query<ResolveParams>,
+    headers: http::HeaderMap,
+) -> Result<Response, AppError> {
+    // axum drops the body of responses to `HEAD`
+    resolve(state, token, &params, &headers, false).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/{token}/redirect",
+    params(
+        ("token" = String, Path, description = "Token of the short link"),
+        ("pw" = Option<String>, Query, description = "Password of a protected link"),
+        ("dry_run" = Option<bool>, Query, description = "Describe the redirect instead of following it"),
+    ),
+    responses(
+        (status = 200, description = "Dry run: where the link leads, without counting a hit", body = DryRunResponse),
+        (status = 303, description = "Without `dry_run`, the same redirect as `GET /{token}`"),
+        (status = 401, description = "The link is password protected"),
+        (status = 404, description = "Unknown token"),
+        (status = 410, description = "The link expired or was deleted"),
+    )
+)]
+async fn redirect_url(
+    State(state): State<SharedState>,
+    Path(token): Path<String>,
+    Query(params): Query<ResolveParams>,
+    Query(DryRunParams { dry_run }): Query<DryRunParams>,
+    headers: http::HeaderMap,
+) -> Result<Response, AppError> {
+    if !dry_run {
+        return resolve(state, token, &params, &headers, true).await;
+    }
+    check_token_format(&token)?;
+    let state = state.read().await;
+    let record = state.store.link_info(&token).await.map_err(lookup_error)?;
+    if let Some(hash) = &record.password_hash {
+        check_password(hash, &params, &headers)?;
+    }
+    let status = match state.interstitial_seconds {
+        Some(_) => http::StatusCode::OK,
+        None => redirect_status(state.redirect_kind),
+    };
+    Ok(Json(DryRunResponse {
+        url: record.redirect_url(&mut rand::rng()).to_string(),
+        status: status.as_u16(),
+        hits: record.hits.load(Ordering::Relaxed),
+        expires_at: record.expires_at.map(rfc3339),
+        max_hits: record.max_hits,
+    })
+    .into_response())
+}
+
+async fn resolve(
+    state: SharedState,
+    token: String,
+    params: &ResolveParams,
+    headers: &http::HeaderMap,
+    count_hit: bool,
+) -> Result<Response, AppError> {
+    // `/{token}.json` is shorthand for `/{token}/info`
+    if let Some(token) = token.strip_suffix(".json") {
+        return link_info(
+            State(state),
+            Path(token.to_string()),
+            Query(InfoParams::default()),
+            Query(ResolveParams {
+                pw: params.pw.clone(),
+            }),
+            headers.clone(),
+        )
+        .await;
+    }
+    check_token_format(&token)?;
+    let state = state.read().await;
+    follow_link(&state, &*state.store, &token, params, headers, count_hit).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/{namespace}/{token}",
+    params(
+        ("namespace" = String, Path, description = "One of the configured namespaces"),
+        ("token" = String, Path, description = "Token of the short link"),
+        ("pw" = Option<String>, Query, description = "Password of a protected link"),
+    ),
+    responses(
+        (status = 303, description = "Redirect to the target"),
+        (status = 404, description = "Unknown namespace or token"),
+        (status = 410, description = "The link expired or was deleted"),
+    )
+)]
+async fn resolve_namespaced_url(
+    State(state): State<SharedState>,
+    Path((namespace, token)): Path<(String, String)>,
+    Query(params): Query<ResolveParams>,
+    headers: http::HeaderMap,
+) -> Result<Response, AppError> {
+    resolve_namespaced(state, namespace, token, &params, &headers, true).await
+}
+
+#[utoipa::path(
+    head,
+    path = "/{namespace}/{token}",
+    params(
+        ("namespace" = String, Path, description = "One of the configured namespaces"),
+        ("token" = String, Path, description = "Token of the short link"),
+        ("pw" = Option<String>, Query, description = "Password of a protected link"),
+    ),
+    responses(
+        (status = 303, description = "The redirect `GET` would answer, without a body or counting a hit"),
+        (status = 404, description = "Unknown namespace or token"),
+        (status = 410, description = "The link expired or was deleted"),
+    )
+)]
+async fn peek_namespaced_url(
+    State(state): State<SharedState>,
+    Path((namespace, token)): Path<(String, String)>,
+    Query(params): Query<ResolveParams>,
+    headers: http::HeaderMap,
+) -> Result<Response, AppError> {
+    resolve_namespaced(state, namespace, token, &params, &headers, false).await
+}
+
+async fn resolve_namespaced(
+    state: SharedState,
+    namespace: String,
+    token: String,
+    params: &ResolveParams,
+    headers: &http::HeaderMap,
+    count_hit: bool,
+) -> Result<Response, AppError> {
+    check_token_format(&token)?;
+    let state = state.read().await;
+    let store = state
+        .namespace_store(Some(&namespace))
+        .ok_or_else(|| AppError::not_found("Namespace not found"))?;
+    follow_link(&state, store, &token, params, headers, count_hit).await
+}
+
+/// Redirects to the target of `token` in `store`, which is `state.store` or a namespace's.
+/// Unless `count_hit` is set, as for `HEAD`, the link is only looked up: no hit, click or
+/// redirect metric is recorded.
+async fn follow_link(
+    state: &AppState,
+    store: &dyn AsyncStoreAccess,
+    token: &str,
+    params: &ResolveParams,
+    headers: &http::HeaderMap,
+    count_hit: bool,
+) -> Result<Response, AppError> {
+    // Lookup failures are left to `resolve_token` below so they are reported in one place
+    if let Some(hash) = store
+        .link_info(token)
+        .await
+        .ok()
+        .and_then(|record| record.password_hash)
+    {
+        check_password(&hash, params, headers)?;
+    }
+    if !count_hit {
+        // `link_info` fails exactly when `resolve_token` would, without counting a hit
+        return match store.link_info(token).await {
+            Ok(record) => {
+                let url = record.redirect_url(&mut rand::rng());
+                Ok(redirect_or_interstitial(state, url.as_str()))
+            }
+            Err(e) => lookup_failure(state, e),
+        };
+    }
+    let url = match store.resolve_token(token).await {
+        Ok(url) => url.to_string(),
+        Err(e) => {
+            if !is_gone(&e) {
+                state.metrics.record_not_found();
+            }
+            return lookup_failure(state, e);
+        }
+    };
+
+    state.metrics.record_redirect();
+    if state.record_clicks {
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|h| h.to_str().ok())
+                .map(String::from)
+        };
+        let click = ClickInfo {
+            at: SystemTime::now(),
+            referer: header(http::header::REFERER),
+            user_agent: header(http::header::USER_AGENT),
+        };
+        // Analytics must never break the redirect itself
+        if let Err(e) = store.record_click(token, click).await {
+            tracing::warn!("Failed to record click on {token}: {e}");
+        }
+    }
+    Ok(redirect_or_interstitial(state, &url))
+}
+
+/// Answer to a link that couldn't be followed: `state.expired_behavior` for expired links,
+/// `not_found` for the rest.
+fn lookup_failure(state: &AppState, e: color_eyre::Report) -> Result<Response, AppError> {
+    if e.downcast_ref() != Some(&StoreError::Expired) {
+        return not_found(state, lookup_error(e));
+    }
+    match &state.expired_behavior {
+        ExpiredBehavior::Gone => Err(lookup_error(e)),
+        ExpiredBehavior::NotFound => not_found(state, AppError::not_found(e)),
+        ExpiredBehavior::Redirect(url) => Ok(redirect(RedirectKind::Temporary, url.as_str())),
+    }
+}
+
+/// Applies `state.not_found_behavior` to a `404` from looking up a link; other errors pass.
+fn not_found(state: &AppState, e: AppError) -> Result<Response, AppError> {
+    if e.status != http::StatusCode::NOT_FOUND {
+        return Err(e);
+    }
+    match &state.not_found_behavior {
+        NotFoundBehavior::Status => Err(e),
+        NotFoundBehavior::Page(html) => {
+            Ok((http::StatusCode::NOT_FOUND, Html(html.clone())).into_response())
+        }
+        NotFoundBehavior::Redirect(url) => Ok(redirect(RedirectKind::Temporary, url.as_str())),
+    }
+}
+
+fn redirect_or_interstitial(state: &AppState, url: &str) -> Response {
+    if let Some(seconds) = state.interstitial_seconds {
+        return interstitial(url, seconds).into_response();
+    }
+    let mut response = redirect(state.redirect_kind, url);
+    if response.status().is_redirection() {
+        if let Ok(cache_control) = state.redirect_cache_control.as_str().parse() {
+            response
+                .headers_mut()
+                .insert(http::header::CACHE_CONTROL, cache_control);
+        }
+    }
+    response
+}
+
+/// Page that follows `url` after `seconds` through a `<meta http-equiv="refresh">`.
+fn interstitial(url: &str, seconds: u64) -> Html<String> {
+    let url = escape_html(url);
+    Html(format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head><meta charset="utf-8"><meta name="robots" content="noindex"><meta name="referrer" content="no-referrer">
+<meta http-equiv="refresh" content="{seconds}; url={url}"><title>Redirecting</title></head>
+<body>
+<h1>You are leaving for</h1>
+<p><code>{url}</code></p>
+<p>You will be redirected in {seconds} seconds. <a href="{url}" rel="noreferrer">Continue now</a></p>
+</body>
+</html>
+"#
+    ))
+}
+
+fn redirect_status(kind: RedirectKind) -> http::StatusCode {
+    match kind {
+        RedirectKind::SeeOther => http::StatusCode::SEE_OTHER,
+        RedirectKind::Temporary => http::StatusCode::FOUND,
+        RedirectKind::Permanent => http::StatusCode::MOVED_PERMANENTLY,
+        RedirectKind::TemporaryPreserveMethod => http::StatusCode::TEMPORARY_REDIRECT,
+    }
+}
+
+fn redirect(kind: RedirectKind, url: &str) -> Response {
+    let status = redirect_status(kind);
+    match http::HeaderValue::try_from(url) {
+        Ok(location) => (status, [(http::header::LOCATION, location)]).into_response(),
+        Err(e) => {
+            AppError::internal(format!("Stored URL is not a valid header: {e}")).into_response()
+        }
+    }
+}
+
+/// Rejects path segments no store could have issued as a token with `400`,
+/// so `404` only ever means a well-formed token that isn't stored.
+fn check_token_format(token: &str) -> Result<(), AppError> {
+    // Aliases are the loosest form: generated tokens are shorter and alphanumeric
+    Token::from_alias(token)
+        .map(|_| ())
+        .map_err(|e| AppError::bad_request(format!("Malformed token: {e}")))
+}
+
+/// Password from the `x-link-password` header or the `pw` query parameter, checked against `hash`.
+fn check_password(
+    hash: &str,
+    params: &ResolveParams,
+    headers: &http::HeaderMap,
+) -> Result<(), AppError> {
+    let supplied = headers
+        .get(PASSWORD_HEADER)
+        .and_then(|h| h.to_str().ok())
+        .or(params.pw.as_deref());
+    if !supplied.is_some_and(|pw| password::verify(pw, hash)) {
+        return Err(AppError::new(
+            http::StatusCode::UNAUTHORIZED,
+            "This link is password protected",
+        ));
+    }
+    Ok(())
+}
+
+/// Escapes text for use in HTML content and quoted attribute values.
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Shows where a link leads instead of redirecting; viewing it doesn't count as a hit.
+#[utoipa::path(
+    get,
+    path = "/{token}/preview",
+    params(("token" = String, Path, description = "Token of the short link")),
+    responses(
+        (status = 200, description = "Page showing the target", body = String, content_type = "text/html"),
+        (status = 404, description = "Unknown token"),
+    )
+)]
+async fn link_preview(
+    State(state): State<SharedState>,
+    Path(token): Path<String>,
+    Query(params): Query<ResolveParams>,
+    headers: http::HeaderMap,
+) -> Result<Html<String>, AppError> {
+    check_token_format(&token)?;
+    let state = state.read().await;
+    let record = state.store.link_info(&token).await.map_err(lookup_error)?;
+    if let Some(hash) = &record.password_hash {
+        check_password(hash, &params, &headers)?;
+    }
+
+    let url = escape_html(record.url.as_str());
+    Ok(Html(format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head><meta charset="utf-8"><meta name="robots" content="noindex"><title>Link preview</title></head>
+<body>
+<h1>This link leads to</h1>
+<p><code>{url}</code></p>
+<p><a href="{url}" rel="noreferrer">Continue</a></p>
+</body>
+</html>
+"#
+    )))
+}
+
+#[utoipa::path(
+    get,
+    path = "/{token}/info",
+    params(
+        ("token" = String, Path, description = "Token of the short link"),
+        ("debug" = Option<bool>, Query, description = "Add the token's hex and base62 forms; needs an API key"),
+        ("pw" = Option<String>, Query, description = "Password of a protected link"),
+    ),
+    responses(
+        (status = 200, description = "Metadata of the link", body = LinkInfoResponse),
+        (status = 401, description = "Debug output without an API key, or the link is password protected"),
+        (status = 404, description = "Unknown token"),
+        (status = 410, description = "The link expired or was deleted"),
+    )
+)]
+async fn link_info(
+    State(state): State<SharedState>,
+    Path(token): Path<String>,
+    Query(params): Query<InfoParams>,
+    Query(resolve_params): Query<ResolveParams>,
+    headers: http::HeaderMap,
+) -> Result<Response, AppError> {
+    check_token_format(&token)?;
+    let state = state.read().await;
+    if params.debug && !bearer_key(&headers).is_some_and(|k| state.api_keys.contains(k)) {
+        return Ok(unauthorized());
+    }
+    let record = state.store.get_record(&token).await.map_err(lookup_error)?;
+    if let Some(hash) = &record.password_hash {
+        check_password(hash, &resolve_params, &headers)?;
+    }
+
+    let mut info = LinkInfoResponse::from(record);
+    if params.debug {
+        let token = Token::from_alias(&token).map_err(AppError::bad_request)?;
+        info.token_encodings = Some(TokenEncodings::from(&token));
+    }
+    Ok(Json(info).into_response())
+}
+
+#[utoipa::path(
+    get,
+    path = "/{token}/stats",
+    params(
+        ("token" = String, Path, description = "Token of the short link"),
+        ("pw" = Option<String>, Query, description = "Password of a protected link"),
+    ),
+    responses(
+        (status = 200, description = "Hits of the link", body = LinkStatsResponse),
+        (status = 401, description = "The link is password protected"),
+        (status = 404, description = "Unknown token"),
+    )
+)]
+async fn link_stats(
+    State(state): State<SharedState>,
+    Path(token): Path<String>,
+    Query(params): Query<ResolveParams>,
+    headers: http::HeaderMap,
+) -> Result<Json<LinkStatsResponse>, AppError> {
+    check_token_format(&token)?;
+    let state = state.read().await;
+    let record = state.store.get_record(&token).await.map_err(lookup_error)?;
+    if let Some(hash) = &record.password_hash {
+        check_password(hash, &params, &headers)?;
+    }
+
+    Ok(Json(LinkStatsResponse {
+        hits: record.hits.into_inner(),
+        created_at: rfc3339(record.created_at),
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/{token}/clicks",
+    params(("token" = String, Path, description = "Token of the short link")),
+    responses(
+        (status = 200, description = "Most recent clicks, needs an API key", body = Vec<ClickResponse>),
+        (status = 401, description = "Missing or unknown API key"),
+        (status = 404, description = "Unknown token"),
+    )
+)]
+async fn link_clicks(
+    State(state): State<SharedState>,
+    Path(token): Path<String>,
+) -> Result<Json<Vec<ClickResponse>>, AppError> {
+    check_token_format(&token)?;
+    let state = state.read().await;
+    let clicks = state.store.clicks(&token).await.map_err(lookup_error)?;
+
+    Ok(Json(clicks.into_iter().map(ClickResponse::from).collect()))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/{token}",
+    params(("token" = String, Path, description = "Token of the short link")),
+    responses(
+        (status = 204, description = "The link was deleted"),
+        (status = 401, description = "Missing or unknown API key"),
+        (status = 403, description = "The link belongs to another API key"),
+        (status = 404, description = "Unknown token"),
+        (status = 410, description = "The link is already deleted"),
+    )
+)]
+async fn delete_url(
+    State(state): State<SharedState>,
+    Path(token): Path<String>,
+    headers: http::HeaderMap,
+) -> Result<http::StatusCode, AppError> {
+    check_token_format(&token)?;
+    let mut state = state.write().await;
+    state.check_owner(&token, &headers).await?;
+    state
+        .store
+        .delete_token(&token)
+        .await
+        .map_err(lookup_error)?;
+
+    Ok(http::StatusCode::NO_CONTENT)
+}
+
+/// Undoes `delete_url`.
+#[utoipa::path(
+    post,
+    path = "/{token}/restore",
+    params(("token" = String, Path, description = "Token of the short link")),
+    responses(
+        (status = 204, description = "The link was restored"),
+        (status = 401, description = "Missing or unknown API key"),
+        (status = 403, description = "The link belongs to another API key"),
+        (status = 404, description = "Unknown token"),
+    )
+)]
+async fn restore_url(
+    State(state): State<SharedState>,
+    Path(token): Path<String>,
+    headers: http::HeaderMap,
+) -> Result<http::StatusCode, AppError> {
+    check_token_format(&token)?;
+    let mut state = state.write().await;
+    state.check_owner(&token, &headers).await?;
+    state
+        .store
+        .restore_token(&token)
+        .await
+        .map_err(lookup_error)?;
+
+    Ok(http::StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    put,
+    path = "/{token}",
+    params(("token" = String, Path, description = "Token of the short link")),
+    request_body(content = UrlBody, description = "The new target, as JSON or a raw URL"),
+    responses(
+        (status = 204, description = "The link now points at the new target"),
+        (status = 400, description = "Invalid target"),
+        (status = 401, description = "Missing or unknown API key"),
+        (status = 403, description = "The link belongs to another API key, or the target's host is not on the domain allowlist"),
+        (status = 404, description = "Unknown token"),
+    )
+)]
+async fn update_url(
+    State(state): State<SharedState>,
+    Path(token): Path<String>,
+    req: Request,
+) -> Result<http::StatusCode, AppError> {
+    check_token_format(&token)?;
+    // Owners never change, so this holds once the write lock is taken below
+    state
+        .read()
+        .await
+        .check_owner(&token, req.headers())
+        .await?;
+    let (base_url, max_url_length) = registration_limits(&state, req.headers()).await?;
+    let target_url = extract_body_url(req, max_url_length)
+        .await
+        .map_err(body_error)?;
+
+    let mut state = state.write().await;
+    let target_url = state.normalization.apply(target_url);
+    state
+        .validate_target(&target_url, &base_url)
+        .map_err(target_error)?;
+    state
+        .store
+        .update_url(&token, target_url)
+        .await
+        .map_err(lookup_error)?;
+
+    Ok(http::StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    get,
+    path = "/{token}/qr",
+    params(("token" = String, Path, description = "Token of the short link")),
+    responses(
+        (status = 200, description = "QR code of the short URL", body = Vec<u8>, content_type = "image/png"),
+        (status = 404, description = "Unknown token"),
+    )
+)]
+async fn link_qr(
+    State(state): State<SharedState>,
+    Path(token): Path<String>,
+    headers: http::HeaderMap,
+) -> Result<Response, AppError> {
+    let short_url = qr_short_url(&state, &token, &headers).await?;
+    let png = qr::render_png(short_url.as_str()).map_err(AppError::internal)?;
+
+    Ok(([(http::header::CONTENT_TYPE, "image/png")], png).into_response())
+}
+
+/// Query string accepted by `link_qr_svg`. Both are strings so a bad value falls
+/// back to its default instead of failing the request.
+#[derive(Deserialize)]
+struct QrSvgParams {
+    size: Option<String>,
+    ecc: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/{token}/qr.svg",
+    params(
+        ("token" = String, Path, description = "Token of the short link"),
+        ("size" = Option<u32>, Query, description = "Minimum width in pixels, 32 to 4096; 256 otherwise"),
+        ("ecc" = Option<String>, Query, description = "Error correction, `L`, `M`, `Q` or `H`; `M` otherwise"),
+    ),
+    responses(
+        (status = 200, description = "QR code of the short URL", body = String, content_type = "image/svg+xml"),
+        (status = 404, description = "Unknown token"),
+    )
+)]
+async fn link_qr_svg(
+    State(state): State<SharedState>,
+    Path(token): Path<String>,
+    Query(params): Query<QrSvgParams>,
+    headers: http::HeaderMap,
+) -> Result<Response, AppError> {
+    let short_url = qr_short_url(&state, &token, &headers).await?;
+    let size = params
+        .size
+        .and_then(|size| size.trim().parse().ok())
+        .filter(|size| qr::SVG_SIZES.contains(size))
+        .unwrap_or(qr::DEFAULT_SVG_SIZE);
+    let ec_level = params
+        .ecc
+        .as_deref()
+        .and_then(qr::parse_ec_level)
+        .unwrap_or(qrcode::EcLevel::M);
+    let svg = qr::render_svg(short_url.as_str(), size, ec_level).map_err(AppError::internal)?;
+
+    Ok(([(http::header::CONTENT_TYPE, "image/svg+xml")], svg).into_response())
+}
+
+/// The short URL of `token` for a QR code, or 404 if there is no such link.
+async fn qr_short_url(
+    state: &SharedState,
+    token: &str,
+    headers: &http::HeaderMap,
+) -> Result<Url, AppError> {
+    check_token_format(token)?;
+    let base_url = {
+        let state = state.read().await;
+        state.store.link_info(token).await.map_err(lookup_error)?;
+        state.base_url(headers)
+    };
+
+    base_url
+        .and_then(|base_url| Ok(base_url.join(token)?))
+        .map_err(AppError::internal)
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/export",
+    responses(
+        (status = 200, description = "Every link, one JSON object per line", body = ExportedLink, content_type = "application/x-ndjson"),
+        (status = 401, description = "Missing or unknown API key"),
+    )
+)]
+async fn export_links(State(state): State<SharedState>) -> Result<Response, AppError> {
+    let links = state
+        .read()
+        .await
+        .store
+        .export()
+        .await
+        .map_err(AppError::internal)?;
+
+    let mut body = String::new();
+    for (token, record) in links {
+        let link = ExportedLink {
+            token: token.to_string(),
+            url: record.url,
+        };
+        body.push_str(&serde_json::to_string(&link).map_err(AppError::internal)?);
+        body.push('\n');
+    }
+    Ok(([(http::header::CONTENT_TYPE, NDJSON_CONTENT_TYPE)], body).into_response())
+}
+
+/// Loads the output of `export_links`, never overwriting links that already exist.
+/// The body may be sent with `Content-Encoding: gzip`.
+#[utoipa::path(
+    post,
+    path = "/admin/import",
+    request_body(content = ExportedLink, description = "Output of `GET /admin/export`", content_type = "application/x-ndjson"),
+    responses(
+        (status = 200, description = "Links imported and skipped", body = ImportResponse),
+        (status = 400, description = "A line is not a valid link"),
+        (status = 401, description = "Missing or unknown API key"),
+        (status = 415, description = "The body uses a `Content-Encoding` other than gzip"),
+    )
+)]
+async fn import_links(
+    State(state): State<SharedState>,
+    req: Request,
+) -> Result<Json<ImportResponse>, AppError> {
+    let body = read_body(req, MAX_IMPORT_SIZE).await.map_err(body_error)?;
+    let body = std::str::from_utf8(&body).map_err(AppError::bad_request)?;
+    let links = body
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str::<ExportedLink>(line)
+                .map(|link| (link.token, link.url))
+                .map_err(|e| AppError::bad_request(format!("Invalid link on line {}: {e}", i + 1)))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let tokens: Vec<String> = links.iter().map(|(token, _)| token.clone()).collect();
+    let results = state.write().await.store.import(links).await;
+
+    let mut response = ImportResponse {
+        imported: 0,
+        skipped: Vec::new(),
+    };
+    for (token, result) in tokens.into_iter().zip(results) {
+        match result {
+            Ok(_) => response.imported += 1,
+            Err(e) => response.skipped.push(SkippedLink {
+                token,
+                error: e.to_string(),
+            }),
+        }
+    }
+    Ok(Json(response))
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/links",
+    params(
+        ("offset" = Option<usize>, Query, description = "Links to skip"),
+        ("limit" = Option<usize>, Query, description = "Links to return, at most 500"),
+    ),
+    responses(
+        (status = 200, description = "A page of links", body = Vec<ListedLink>),
+        (status = 401, description = "Missing or unknown API key"),
+    )
+)]
+async fn list_links(
+    State(state): State<SharedState>,
+    Query(params): Query<ListParams>,
+) -> Result<Json<Vec<ListedLink>>, AppError> {
+    let state = state.read().await;
+    let links = state
+        .store
+        .list(params.offset, params.limit.min(ListParams::MAX_LIMIT))
+        .await
+        .map_err(AppError::internal)?;
+
+    Ok(Json(
+        links
+            .into_iter()
+            .map(|(token, record)| ListedLink::new(token, record))
+            .collect(),
+    ))
+}
+
+/// The links registered with the caller's API key; `GET /admin/links` lists everyone's.
+#[utoipa::path(
+    get,
+    path = "/links",
+    responses(
+        (status = 200, description = "Links owned by the API key", body = Vec<ListedLink>),
+        (status = 401, description = "Missing or unknown API key"),
+    )
+)]
+async fn list_own_links(
+    State(state): State<SharedState>,
+    req: Request,
+) -> Result<Response, AppError> {
+    let state = state.read().await;
+    // Without configured keys nothing has an owner to list by
+    let Some(owner) = state.request_owner(&req) else {
+        return Ok(unauthorized());
+    };
+    let links = state
+        .store
+        .list_by_owner(&owner)
+        .await
+        .map_err(AppError::internal)?;
+
+    let links: Vec<_> = links
+        .into_iter()
+        .map(|(token, record)| ListedLink::new(token, record))
+        .collect();
+    Ok(Json(links).into_response())
+}
+
+/// Body of `GET /available/{alias}`.
+#[derive(Debug, Serialize, ToSchema)]
+struct Availability {
+    available: bool,
+    /// Why registering the alias would fail; absent when it wouldn't.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+}
+
+/// Whether registering `alias` would succeed right now, for forms validating as the user
+/// types. Nothing is registered, so the alias may still be taken by the time it is.
+#[utoipa::path(
+    get,
+    path = "/available/{alias}",
+    params(("alias" = String, Path, description = "Alias to check")),
+    responses((status = 200, description = "Whether the alias is free, and why not if it isn't", body = Availability))
+)]
+async fn alias_availability(
+    State(state): State<SharedState>,
+    Path(alias): Path<String>,
+) -> Result<Json<Availability>, AppError> {
+    let state = state.read().await;
+    let checked = match state.check_alias_length(&alias) {
+        Err(e) => Err(e.message),
+        Ok(()) => match state.store.check_alias(&alias).await {
+            Ok(()) => Ok(()),
+            // Anything but a taken or reserved alias failed validation in `Token::from_alias`
+            Err(e) if e.downcast_ref::<StoreError>().is_some() => Err(e.to_string()),
+            Err(e) if Token::from_alias(&alias).is_err() => Err(e.to_string()),
+            Err(e) => return Err(AppError::internal(e)),
+        },
+    };
+
+    Ok(Json(Availability {
+        available: checked.is_ok(),
+        reason: checked.err(),
+    }))
+}
+
+/// Wipes the whole store, meant for test environments.
+#[utoipa::path(
+    delete,
+    path = "/admin/links",
+    responses(
+        (status = 200, description = "Every link was deleted", body = ClearResponse),
+        (status = 401, description = "Missing or unknown API key"),
+    )
+)]
+async fn clear_links(State(state): State<SharedState>) -> Result<Json<ClearResponse>, AppError> {
+    let deleted = state
+        .write()
+        .await
+        .store
+        .clear()
+        .await
+        .map_err(AppError::internal)?;
+
+    Ok(Json(ClearResponse { deleted }))
+}
+
+/// Deletes the links matching every given criterion, as `DELETE /{token}` would.
+#[utoipa::path(
+    delete,
+    path = "/admin/links/matching",
+    params(
+        ("prefix" = Option<String>, Query, description = "Start of the token"),
+        ("created_before" = Option<String>, Query, description = "RFC 3339 or Unix seconds; only older links match"),
+    ),
+    responses(
+        (status = 200, description = "How many links were deleted", body = ClearResponse),
+        (status = 400, description = "No criterion or an invalid timestamp"),
+        (status = 401, description = "Missing or unknown API key"),
+    )
+)]
+async fn delete_matching_links(
+    State(state): State<SharedState>,
+    Query(params): Query<DeleteMatchingParams>,
+) -> Result<Json<ClearResponse>, AppError> {
+    let created_before = params
+        .created_before
+        .as_deref()
+        .map(parse_timestamp)
+        .transpose()
+        .map_err(AppError::bad_request)?;
+    // `DELETE /admin/links` is there to delete everything
+    if params.prefix.is_none() && created_before.is_none() {
+        return Err(AppError::bad_request(
+            "Give a prefix or created_before to match links by",
+        ));
+    }
+    let prefix = params.prefix.unwrap_or_default();
+    let matches = |token: &Token, record: &LinkRecord| {
+        token.as_str().starts_with(&prefix)
+            && created_before.is_none_or(|cutoff| record.created_at < cutoff)
+    };
+    let deleted = state
+        .write()
+        .await
+        .store
+        .delete_matching(&matches)
+        .await
+        .map_err(AppError::internal)?;
+
+    Ok(Json(ClearResponse { deleted }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/stats",
+    responses(
+        (status = 200, description = "Link and redirect totals of the default namespace", body = StatsResponse),
+        (status = 401, description = "Missing or unknown API key"),
+    )
+)]
+async fn store_stats(State(state): State<SharedState>) -> Result<Json<StatsResponse>, AppError> {
+    let state = state.read().await;
+    let stats = state
+        .store
+        .stats(SystemTime::now())
+        .await
+        .map_err(AppError::internal)?;
+
+    Ok(Json(StatsResponse {
+        total_links: stats.links,
+        total_redirects: stats.hits,
+        expired_count: stats.expired,
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/top",
+    params(
+        ("limit" = Option<usize>, Query, description = "Links to return, 10 by default and at most 100"),
+    ),
+    responses(
+        (status = 200, description = "The most resolved links, most hits first", body = Vec<TopLink>),
+        (status = 401, description = "Missing or unknown API key"),
+    )
+)]
+async fn top_links(
+    State(state): State<SharedState>,
+    Query(params): Query<TopParams>,
+) -> Result<Json<Vec<TopLink>>, AppError> {
+    let state = state.read().await;
+    let links = state
+        .store
+        .top_by_hits(params.limit.min(TopParams::MAX_LIMIT))
+        .await
+        .map_err(AppError::internal)?;
+
+    Ok(Json(
+        links
+            .into_iter()
+            .map(|(token, record)| TopLink {
+                token: token.to_string(),
+                url: record.url.to_string(),
+                hits: record.hits.load(Ordering::Relaxed),
+            })
+            .collect(),
+    ))
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/usage",
+    responses(
+        (status = 200, description = "Live links per API key", body = UsageResponse),
+        (status = 401, description = "Missing or unknown API key"),
+    )
+)]
+async fn key_usage(State(state): State<SharedState>) -> Result<Json<UsageResponse>, AppError> {
+    let state = state.read().await;
+    let usage = state
+        .store
+        .usage(SystemTime::now())
+        .await
+        .map_err(AppError::internal)?;
+
+    Ok(Json(UsageResponse {
+        quota: state.link_quota,
+        links: state
+            .api_keys
+            .iter()
+            .map(|key| (key.clone(), usage.get(key).copied().unwrap_or(0)))
+            .collect(),
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/",
+    request_body(content = RegisterRequest, description = "The target, as JSON or a raw URL"),
+    params(
+        ("Idempotency-Key" = Option<String>, Header, description = "Retries with the same key get the same link"),
+        ("x-expires-in" = Option<u64>, Header, description = "Lifetime of the link in seconds"),
+        ("x-expires-at" = Option<String>, Header, description = "Expiry as RFC 3339 or Unix seconds"),
+    ),
+    responses(
+        (status = 201, description = "The link was created; the bare short URL for `Accept: text/plain`, an `<a>` for `text/html`", body = RegisterResponse),
+        (status = 200, description = "The existing link, for a retried alias or with dedup on; same formats", body = RegisterResponse),
+        (status = 400, description = "Invalid target or options"),
+        (status = 401, description = "Missing or unknown API key"),
+        (status = 403, description = "The target's host is not on the domain allowlist"),
+        (status = 409, description = "The alias is taken by another URL, or the target already has a link"),
+        (status = 413, description = "The target is too long"),
+        (status = 422, description = "The Idempotency-Key was already used for a different request"),
+        (status = 429, description = "Too many registrations or the key's quota is used up"),
+    )
+)]
+async fn register_url(
+    State(state): State<SharedState>,
+    req: Request,
+) -> Result<Response, AppError> {
+    let format = CreatedFormat::from_headers(req.headers());
+    let owner = state.read().await.request_owner(&req);
+    let ttl = extract_ttl(&req).map_err(AppError::bad_request)?;
+    let idempotency_key =
+        extract_idempotency_key(&req, owner.as_deref()).map_err(AppError::bad_request)?;
+    let (base_url, max_url_length) = registration_limits(&state, req.headers()).await?;
+    let request = extract_register_request(req, max_url_length)
+        .await
+        .map_err(body_error)?;
+    // A retry must send the same registration; its key can't register anything else
+    let request_hash = {
+        let mut hasher = std::hash::DefaultHasher::new();
+        request.hash(&mut hasher);
+        hasher.finish()
+    };
+    let RegisterRequest {
+        url: target_url,
+        alias,
+        namespace,
+        password,
+        max_hits,
+        destinations,
+        utm,
+    } = request;
+    if max_hits == Some(0) {
+        return Err(AppError::bad_request("max_hits must be at least 1"));
+    }
+    if destinations.len() > MAX_DESTINATIONS {
+        return Err(AppError::bad_request(format!(
+            "At most {MAX_DESTINATIONS} destinations per link"
+        )));
+    }
+    if destinations.iter().any(|d| d.weight == 0) {
+        return Err(AppError::bad_request(
+            "Destination weights must be at least 1",
+        ));
+    }
+    if let Some(name) = utm.keys().find(|name| !UTM_PARAMS.contains(&name.as_str())) {
+        return Err(AppError::bad_request(format!(
+            "Unknown UTM parameter {name}, expected one of {}",
+            UTM_PARAMS.join(", ")
+        )));
+    }
+    // The same key may register once per namespace
+    let idempotency_key =
+        idempotency_key.map(|key| format!("{key}\n{}", namespace.as_deref().unwrap_or_default()));
+    // Hashing is deliberately slow, so it happens before taking the lock
+    let password_hash = match password.as_deref() {
+        Some("") => return Err(AppError::bad_request("Password must not be empty")),
+        Some(password) => Some(password::hash(password).map_err(AppError::internal)?),
+        None => None,
+    };
+
+    let (token, created) = {
+        let mut state = state.write().await;
+        // Looked up under the write lock so concurrent retries can't both register
+        if let Some(key) = idempotency_key.as_deref() {
+            let replayed = state
+                .idempotency
+                .get(key, request_hash, SystemTime::now())
+                .map_err(|e| AppError::new(http::StatusCode::UNPROCESSABLE_ENTITY, e))?;
+            if let Some(token) = replayed {
+                return created_response(
+                    &base_url,
+                    namespace.as_deref(),
+                    token,
+                    format,
+                    http::StatusCode::CREATED,
+                );
+            }
+        }
+        let target_url = state.normalization.apply(target_url);
+        state
+            .validate_target(&target_url, &base_url)
+            .map_err(target_error)?;
+        let destinations = destinations
+            .into_iter()
+            .map(|Destination { url, weight }| {
+                let url = state.normalization.apply(url);
+                state.validate_target(&url, &base_url)?;
+                Ok((url, weight))
+            })
+            .collect::<Result<Vec<_>>>()
+            .map_err(target_error)?;
+        if let Some(alias) = alias.as_deref() {
+            state.check_alias_length(alias)?;
+        }
+        state.check_quota(owner.as_deref(), 1).await?;
+        let options = LinkOptions {
+            password_hash,
+            owner: owner.clone(),
+            max_hits,
+            destinations,
+            utm_params: utm.into_iter().collect(),
+        };
+        // Another caller's link must not pick up this request's owner or settings
+        let dedup = state.dedup
+            && options.owner.is_none()
+            && options.password_hash.is_none()
+            && options.max_hits.is_none()
+            && options.destinations.is_empty()
+            && options.utm_params.is_empty();
+        let store = state
+            .namespace_store_mut(namespace.as_deref())
+            .ok_or_else(|| AppError::bad_request("Unknown namespace"))?;
+        let (token, created) = match (alias, ttl) {
+            (Some(alias), None) => {
+                match store
+                    .register_with_options(target_url.clone(), Some(&alias), None, options)
+                    .await
+                {
+                    Ok(token) => (token, true),
+                    Err(e) if e.downcast_ref() == Some(&StoreError::AliasTaken) => {
+                        // A retry gets the link it registered the first time
+                        let existing =
+                            existing_alias(&**store, &alias, &target_url, owner.as_deref()).await;
+                        return match existing {
+                            Some(token) => created_response(
+                                &base_url,
+                                namespace.as_deref(),
+                                &token,
+                                format,
+                                http::StatusCode::OK,
+                            ),
+                            None => Err(conflict_or(e, AppError::bad_request)),
+                        };
+                    }
+                    Err(e) => return Err(conflict_or(e, AppError::bad_request)),
+                }
+            }
+            (None, Some(ttl)) => {
+                let token = store
+                    .register_with_options(target_url, None, Some(ttl), options)
+                    .await
+                    .map_err(|e| conflict_or(e, AppError::internal))?;
+                (token, true)
+            }
+            (None, None) if dedup => store
+                .register_or_get(target_url)
+                .await
+                .map_err(|e| conflict_or(e, AppError::internal))?,
+            (None, None) => {
+                let token = store
+                    .register_with_options(target_url, None, None, options)
+                    .await
+                    .map_err(|e| conflict_or(e, AppError::internal))?;
+                (token, true)
+            }
+            // Aliases are meant to be permanent
+            (Some(_), Some(_)) => {
+                return Err(AppError::bad_request(
+                    "Aliases cannot expire, drop either alias or the expiry header",
+                ))
+            }
+        };
+        if created {
+            state.metrics.record_registrations(1);
+        }
+        if let Some(key) = idempotency_key {
+            state
+                .idempotency
+                .insert(key, request_hash, token.clone(), SystemTime::now());
+        }
+        (token, created)
+    };
+
+    // A dedup hit hands out a link that already existed
+    let status = if created {
+        http::StatusCode::CREATED
+    } else {
+        http::StatusCode::OK
+    };
+    created_response(&base_url, namespace.as_deref(), &token, format, status)
+}
+
+/// `alias` if it is a live link to `url` registered by `owner`, which makes registering
+/// the same alias and URL again answer with that link instead of `409`.
+async fn existing_alias(
+    store: &dyn AsyncStoreAccess,
+    alias: &str,
+    url: &Url,
+    owner: Option<&str>,
+) -> Option<Token> {
+    let record = store.link_info(alias).await.ok()?;
+    if record.deleted || record.url != *url || record.owner.as_deref() != owner {
+        return None;
+    }
+    Token::from_alias(alias).ok()
+}
+
+/// Body of the answer to a registration, picked from `Accept`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CreatedFormat {
+    Json,
+    /// The bare short URL.
+    PlainText,
+    /// An `<a>` linking to the short URL, ready to embed in a page.
+    Html,
+}
+
+impl CreatedFormat {
+    fn from_headers(headers: &http::HeaderMap) -> Self {
+        let accept = headers
+            .get(http::header::ACCEPT)
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or_default();
+        if error::accepts_plain_text(headers) {
+            CreatedFormat::PlainText
+        } else if accept.contains("text/html") && !accept.contains("application/json") {
+            CreatedFormat::Html
+        } else {
+            CreatedFormat::Json
+        }
+    }
+}
+
+/// The answer to a registration, in the requested `format`: `201 Created`, or `200`
+/// when the link already existed.
+fn created_response(
+    base_url: &Url,
+    namespace: Option<&str>,
+    token: &Token,
+    format: CreatedFormat,
+    status: http::StatusCode,
+) -> Result<Response, AppError> {
+    let path = match namespace {
+        Some(namespace) => format!("{namespace}/{token}"),
+        None => token.to_string(),
+    };
+    let short_url = base_url
+        .join(&path)
+        .map_err(AppError::internal)?
+        .to_string();
+    let location = [(http::header::LOCATION, short_url.clone())];
+
+    Ok(match format {
+        CreatedFormat::PlainText => (status, location, short_url).into_response(),
+        CreatedFormat::Html => {
+            let short_url = escape_html(&short_url);
+            (
+                status,
+                location,
+                Html(format!(r#"<a href="{short_url}">{short_url}</a>"#)),
+            )
+                .into_response()
+        }
+        CreatedFormat::Json => (
+            status,
+            location,
+            Json(RegisterResponse {
+                short_url,
+                token: token.to_string(),
+            }),
+        )
+            .into_response(),
+    })
+}
+
+#[utoipa::path(
+    post,
+    path = "/batch",
+    request_body(content = Vec<String>, description = "Targets to shorten, at most 100"),
+    responses(
+        (status = 200, description = "One outcome per target, in order", body = Vec<BatchItemResponse>),
+        (status = 400, description = "The body is not a JSON array of strings"),
+        (status = 401, description = "Missing or unknown API key"),
+        (status = 413, description = "Too many targets"),
+    )
+)]
+async fn register_batch(
+    State(state): State<SharedState>,
+    req: Request,
+) -> Result<Json<Vec<BatchItemResponse>>, AppError> {
+    let (base_url, max_url_length) = registration_limits(&state, req.headers()).await?;
+    let owner = state.read().await.request_owner(&req);
+    let body = read_body(req, MAX_BATCH_SIZE * max_url_length + JSON_BODY_SLACK)
+        .await
+        .map_err(body_error)?;
+    let inputs: Vec<String> = serde_json::from_slice(&body).map_err(AppError::bad_request)?;
+    if inputs.len() > MAX_BATCH_SIZE {
+        return Err(AppError::new(
+            http::StatusCode::PAYLOAD_TOO_LARGE,
+            format!("At most {MAX_BATCH_SIZE} URLs per batch"),
+        ));
+    }
+
+    let mut state = state.write().await;
+    let targets: Vec<Result<Url>> = inputs
+        .iter()
+        .map(|input| {
+            let url = Url::parse(input).map_err(|e| eyre!("Failed to parse URL: {}", e))?;
+            let url = state.normalization.apply(url);
+            state.validate_target(&url, &base_url)?;
+            Ok(url)
+        })
+        .collect();
+    let valid: Vec<Url> = targets
+        .iter()
+        .filter_map(|target| target.as_ref().ok().cloned())
+        .collect();
+    state.check_quota(owner.as_deref(), valid.len()).await?;
+    // Owned links are never shared, see `register_url`
+    let tokens = if state.dedup && owner.is_none() {
+        let mut tokens = Vec::with_capacity(valid.len());
+        let mut created = 0;
+        for url in valid {
+            let registered = state.store.register_or_get(url).await;
+            if matches!(registered, Ok((_, true))) {
+                created += 1;
+            }
+            tokens.push(registered.map(|(token, _)| token));
+        }
+        state.metrics.record_registrations(created);
+        tokens
+    } else {
+        let options = LinkOptions {
+            owner,
+            ..LinkOptions::default()
+        };
+        let tokens = state.store.register_batch(valid, options).await;
+        let created = tokens.iter().filter(|token| token.is_ok()).count();
+        state.metrics.record_registrations(created as u64);
+        tokens
+    };
+    let mut tokens = tokens.into_iter();
+
+    let items = inputs
+        .into_iter()
+        .zip(targets)
+        .map(|(url, target)| {
+            let short_url = target
+                .and_then(|_| {
+                    tokens
+                        .next()
+                        .unwrap_or_else(|| Err(eyre!("Missing batch result")))
+                })
+                .and_then(|token| Ok(base_url.join(token.as_str())?));
+            match short_url {
+                Ok(short_url) => BatchItemResponse {
+                    url,
+                    short_url: Some(short_url.to_string()),
+                    error: None,
+                },
+                Err(e) => BatchItemResponse {
+                    url,
+                    short_url: None,
+                    error: Some(e.to_string()),
+                },
+            }
+        })
+        .collect();
+
+    Ok(Json(items))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::StoreAccess;
+    use crate::token::TokenConfig;
+    use axum::http::HeaderMap;
+    use flate2::{write::GzEncoder, Compression};
+    use std::collections::HashMap;
+    use std::io::Write;
+    use std::str::FromStr;
+    use std::sync::Mutex;
+    use tower::ServiceExt;
+
+    impl PartialEq<http::StatusCode> for AppError {
+        fn eq(&self, status: &http::StatusCode) -> bool {
+            self.status == *status
+        }
+    }
+
+    // Mock store implementation
+    struct MockStore {
+        urls: Mutex<HashMap<String, Url>>,
+        expired: HashSet<String>,
+    }
+
+    impl MockStore {
+        fn new() -> Self {
+            Self {
+                urls: Mutex::new(HashMap::new()),
+                expired: HashSet::new(),
+            }
+        }
+
+        fn with_url(self, token: &str, url: Url) -> Self {
+            self.urls.lock().unwrap().insert(token.to_string(), url);
+            self
+        }
+
+        fn with_expired_url(mut self, token: &str, url: Url) -> Self {
+            self.expired.insert(token.to_string());
+            self.with_url(token, url)
+        }
+    }
+
+    impl StoreAccess for MockStore {
+        fn register_with_options(
+            &mut self,
+            url: Url,
+            alias: Option<&str>,
+            _ttl: Option<Duration>,
+            _options: LinkOptions,
+        ) -> Result<Token> {
+            let mut urls = self.urls.lock().unwrap();
+            let token = match alias {
+                Some(alias) if urls.contains_key(alias) => {
+                    return Err(StoreError::AliasTaken.into())
+                }
+                Some(alias) => Token::from_alias(alias)?,
+                None => Token::default(),
+            };
+            urls.insert(token.as_str().to_string(), url);
+            Ok(token)
+        }
+
+        fn resolve_token(&self, token: &str) -> Result<Url> {
+            if self.expired.contains(token) {
+                return Err(StoreError::Expired.into());
+            }
+            self.urls
+                .lock()
+                .unwrap()
+                .get(token)
+                .cloned()
+                .ok_or_else(|| eyre!("Token not found"))
+        }
+
+        fn link_info(&self, token: &str) -> Result<LinkRecord> {
+            let url = StoreAccess::resolve_token(self, token)?;
+            Ok(LinkRecord {
+                url,
+                created_at: UNIX_EPOCH,
+                expires_at: None,
+                hits: Default::default(),
+                password_hash: None,
+                deleted: false,
+                owner: None,
+                max_hits: None,
+                destinations: Vec::new(),
+                utm_params: Vec::new(),
+            })
+        }
+
+        fn set_password_hash(&mut self, token: &str, _hash: Option<String>) -> Result<()> {
+            StoreAccess::resolve_token(self, token).map(|_| ())
+        }
+
+        fn set_owner(&mut self, token: &str, _owner: Option<String>) -> Result<()> {
+            StoreAccess::resolve_token(self, token).map(|_| ())
+        }
+
+        fn set_max_hits(&mut self, token: &str, _max_hits: Option<u64>) -> Result<()> {
+            StoreAccess::resolve_token(self, token).map(|_| ())
+        }
+
+        fn set_destinations(&mut self, token: &str, _destinations: Vec<(Url, u32)>) -> Result<()> {
+            StoreAccess::resolve_token(self, token).map(|_| ())
+        }
+
+        fn set_utm_params(
+            &mut self,
+            token: &str,
+            _utm_params: Vec<(String, String)>,
+        ) -> Result<()> {
+            StoreAccess::resolve_token(self, token).map(|_| ())
+        }
+
+        fn clear(&mut self) -> Result<usize> {
+            let mut urls = self.urls.lock().unwrap();
+            let cleared = urls.len();
+            urls.clear();
+            Ok(cleared)
+        }
+
+        fn purge_expired(&mut self, _now: SystemTime) -> Result<usize> {
+            let mut urls = self.urls.lock().unwrap();
+            let purged = self.expired.drain().filter(|t| urls.remove(t).is_some());
+            Ok(purged.count())
+        }
+
+        fn update_url(&mut self, token: &str, new_url: Url) -> Result<()> {
+            let mut urls = self.urls.lock().unwrap();
+            let url = urls
+                .get_mut(token)
+                .ok_or_else(|| eyre!("Token not found"))?;
+            *url = new_url;
+            Ok(())
+        }
+
+        fn delete_token(&mut self, token: &str) -> Result<()> {
+            self.urls
+                .lock()
+                .unwrap()
+                .remove(token)
+                .map(|_| ())
+                .ok_or_else(|| eyre!("Token not found"))
+        }
+
+        fn restore_token(&mut self, token: &str) -> Result<()> {
+            StoreAccess::resolve_token(self, token).map(|_| ())
+        }
+
+        fn list(&self, offset: usize, limit: usize) -> Result<Vec<(Token, LinkRecord)>> {
+            let mut tokens: Vec<String> = self.urls.lock().unwrap().keys().cloned().collect();
+            tokens.sort();
+            tokens
+                .iter()
+                .skip(offset)
+                .take(limit)
+                .map(|token| {
+                    Ok((
+                        Token::from_alias(token)?,
+                        StoreAccess::link_info(self, token)?,
+                    ))
+                })
+                .collect()
+        }
+
+        fn record_click(&self, _token: &str, _click: ClickInfo) -> Result<()> {
+            Ok(())
+        }
+
+        fn clicks(&self, token: &str) -> Result<Vec<ClickInfo>> {
+            StoreAccess::resolve_token(self, token)?;
+            Ok(Vec::new())
+        }
+    }
+
+    async fn body_string(response: Response) -> String {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    async fn json_body(response: Response) -> serde_json::Value {
+        serde_json::from_str(&body_string(response).await).unwrap()
+    }
+
+    #[test]
+    fn test_extract_base_url() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-proto", "https".parse().unwrap());
+        headers.insert("x-forwarded-host", "example.com".parse().unwrap());
+
+        let mut req = Request::builder()
+            .uri("http://example.com")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        req.headers_mut().extend(headers);
+
+        let result = extract_base_url(req.headers()).unwrap();
+        assert_eq!(result.scheme(), "https");
+        assert_eq!(result.host_str().unwrap(), "example.com");
+    }
+
+    #[test]
+    fn test_extract_base_url_fallback() {
+        let req = Request::builder()
+            .uri("http://localhost:3000")
+            .body(axum::body::Body::empty())
+            .unwrap();
+
+        let result = extract_base_url(req.headers()).unwrap();
+        assert_eq!(result.scheme(), "http");
+        assert_eq!(result.host_str().unwrap(), "localhost");
+    }
+
+    #[tokio::test]
+    async fn test_extract_body_url() {
+        let url = "https://example.com";
+        let req = Request::builder()
+            .uri("http://localhost:3000")
+            .body(axum::body::Body::from(url))
+            .unwrap();
+
+        let result = extract_body_url(req, Config::default().max_url_length)
+            .await
+            .unwrap();
+        assert_eq!(result.to_string(), "https://example.com/");
+    }
+
+    fn query_register_request(uri: &str, body: &str) -> Request {
+        Request::builder()
+            .method("POST")
+            .uri(uri)
+            .header("host", "example.com")
+            .body(axum::body::Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_register_url_from_query() {
+        let state = Arc::new(RwLock::new(AppState::default()));
+
+        let response = register_url(
+            State(state.clone()),
+            query_register_request("/?url=https%3A%2F%2Ftarget.com%2Fa%3Fb%3Dc", ""),
+        )
+        .await
+        .unwrap();
+        let body = json_body(response).await;
+        let token = body["token"].as_str().unwrap().to_string();
+        let stored = state
+            .read()
+            .await
+            .store
+            .resolve_token(&token)
+            .await
+            .unwrap();
+        assert_eq!(stored.as_str(), "https://target.com/a?b=c");
+
+        let result = register_url(State(state), query_register_request("/", "")).await;
+        assert_eq!(result.unwrap_err(), http::StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_register_url_prefers_body_over_query() {
+        let state = Arc::new(RwLock::new(AppState::default()));
+
+        let response = register_url(
+            State(state.clone()),
+            query_register_request("/?url=https://query.com", "https://body.com"),
+        )
+        .await
+        .unwrap();
+        let body = json_body(response).await;
+        let token = body["token"].as_str().unwrap().to_string();
+        let stored = state
+            .read()
+            .await
+            .store
+            .resolve_token(&token)
+            .await
+            .unwrap();
+        assert_eq!(stored.as_str(), "https://body.com/");
+    }
+
+    #[tokio::test]
+    async fn test_resolve_url() {
+        let state = Arc::new(RwLock::new(AppState::default()));
+        let token = {
+            let mut state_guard = state.write().await;
+            state_guard
+                .store
+                .register_url(Url::from_str("https://example.com").unwrap())
+                .await
+                .unwrap()
+        };
+
+        let result = resolve_url(
+            State(state),
+            Path(token.as_str().to_string()),
+            Query(ResolveParams::default()),
+            HeaderMap::new(),
+        )
+        .await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_resolve_url_cache_control() {
+        for (redirect_kind, cache_control) in [
+            (RedirectKind::Permanent, "public, max-age=3600"),
+            (RedirectKind::SeeOther, "no-store"),
+        ] {
+            let config = Config {
+                redirect_kind,
+                permanent_cache_control: "public, max-age=3600".parse().unwrap(),
+                ..Default::default()
+            };
+            let mock_store =
+                MockStore::new().with_url("abc123", Url::parse("https://target.com").unwrap());
+            let state = AppState::with_config(Box::new(mock_store), config);
+
+            let response = resolve_url(
+                State(Arc::new(RwLock::new(state))),
+                Path("abc123".to_string()),
+                Query(ResolveParams::default()),
+                HeaderMap::new(),
+            )
+            .await
+            .unwrap();
+            assert_eq!(
+                response.headers()[http::header::CACHE_CONTROL],
+                cache_control,
+                "{redirect_kind:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resolve_url_redirect_kinds() {
+        for (kind, status) in [
+            (RedirectKind::SeeOther, http::StatusCode::SEE_OTHER),
+            (RedirectKind::Temporary, http::StatusCode::FOUND),
+            (RedirectKind::Permanent, http::StatusCode::MOVED_PERMANENTLY),
+            (
+                RedirectKind::TemporaryPreserveMethod,
+                http::StatusCode::TEMPORARY_REDIRECT,
+            ),
+        ] {
+            let mut state = AppState {
+                redirect_kind: kind,
+                ..Default::default()
+            };
+            let token = state
+                .store
+                .register_url(Url::parse("https://example.com/a?b=c").unwrap())
+                .await
+                .unwrap();
+
+            let response = resolve_url(
+                State(Arc::new(RwLock::new(state))),
+                Path(token.to_string()),
+                Query(ResolveParams::default()),
+                HeaderMap::new(),
+            )
+            .await
+            .unwrap();
+            assert_eq!(response.status(), status, "{kind:?}");
+            assert_eq!(
+                response.headers()[http::header::LOCATION],
+                "https://example.com/a?b=c"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_register_url() {
+        let state = Arc::new(RwLock::new(AppState::default()));
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-proto", "https".parse().unwrap());
+        headers.insert("x-forwarded-host", "example.com".parse().unwrap());
+
+        let mut req = Request::builder()
+            .uri("http://example.com")
+            .body(axum::body::Body::from("https://target.com"))
+            .unwrap();
+        req.headers_mut().extend(headers);
+
+        let result = register_url(State(state), req).await;
+        assert!(result.is_ok());
+        let body = json_body(result.unwrap()).await;
+        let short_url = body["short_url"].as_str().unwrap();
+        assert!(short_url.starts_with("https://example.com/"));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_url_with_mock_store() {
+        let mock_store =
+            MockStore::new().with_url("abc123", Url::parse("https://example.com").unwrap());
+        let state = Arc::new(RwLock::new(AppState::new(Box::new(mock_store))));
+
+        let result = resolve_url(
+            State(state),
+            Path("abc123".to_string()),
+            Query(ResolveParams::default()),
+            HeaderMap::new(),
+        )
+        .await;
+        assert!(result.is_ok());
+        let redirect = result.unwrap();
+        let response = redirect.into_response();
+        let headers = response.headers();
+        assert_eq!(headers.get("location").unwrap(), "https://example.com/");
+    }
+
+    #[tokio::test]
+    async fn test_resolve_url_not_found() {
+        let mock_store = MockStore::new();
+        let state = Arc::new(RwLock::new(AppState::new(Box::new(mock_store))));
+
+        let result = resolve_url(
+            State(state),
+            Path("nonexistent".to_string()),
+            Query(ResolveParams::default()),
+            HeaderMap::new(),
+        )
+        .await;
+        assert!(result.is_err());
+        assert_eq!(result.unwrap_err(), http::StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_register_url_with_mock_store() {
+        let mock_store = MockStore::new();
+        let state = Arc::new(RwLock::new(AppState::new(Box::new(mock_store))));
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-proto", "https".parse().unwrap());
+        headers.insert("x-forwarded-host", "example.com".parse().unwrap());
+
+        let mut req = Request::builder()
+            .uri("http://example.com")
+            .body(axum::body::Body::from("https://target.com"))
+            .unwrap();
+        req.headers_mut().extend(headers);
+
+        let result = register_url(State(state), req).await;
+        assert!(result.is_ok());
+        let body = json_body(result.unwrap()).await;
+        let short_url = body["short_url"].as_str().unwrap();
+        assert!(short_url.starts_with("https://example.com/"));
+    }
+
+    #[tokio::test]
+    async fn test_register_url_invalid_url() {
+        let mock_store = MockStore::new();
+        let state = Arc::new(RwLock::new(AppState::new(Box::new(mock_store))));
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-proto", "https".parse().unwrap());
+        headers.insert("x-forwarded-host", "example.com".parse().unwrap());
+
+        let mut req = Request::builder()
+            .uri("http://example.com")
+            .body(axum::body::Body::from("not-a-url"))
+            .unwrap();
+        req.headers_mut().extend(headers);
+
+        let result = register_url(State(state), req).await;
+        assert!(result.is_err());
+        assert_eq!(result.unwrap_err(), http::StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_delete_url() {
+        let mock_store =
+            MockStore::new().with_url("abc123", Url::parse("https://example.com").unwrap());
+        let state = Arc::new(RwLock::new(AppState::new(Box::new(mock_store))));
+
+        let result = delete_url(
+            State(state.clone()),
+            Path("abc123".to_string()),
+            HeaderMap::new(),
+        )
+        .await;
+        assert_eq!(result.unwrap(), http::StatusCode::NO_CONTENT);
+
+        let result = resolve_url(
+            State(state),
+            Path("abc123".to_string()),
+            Query(ResolveParams::default()),
+            HeaderMap::new(),
+        )
+        .await;
+        assert_eq!(result.unwrap_err(), http::StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_delete_and_restore_url() {
+        let mut store: Box<dyn AsyncStoreAccess> = Box::new(Store::default());
+        let token = store
+            .register_url(Url::parse("https://example.com").unwrap())
+            .await
+            .unwrap();
+        let app = app_router(store, Config::default());
+        let status = |method: &'static str, uri: String| {
+            let request = Request::builder()
+                .method(method)
+                .uri(uri)
+                .body(axum::body::Body::empty())
+                .unwrap();
+            let app = app.clone();
+            async move { app.oneshot(request).await.unwrap().status() }
+        };
+
+        assert_eq!(
+            status("DELETE", format!("/{token}")).await,
+            http::StatusCode::NO_CONTENT
+        );
+        assert_eq!(
+            status("GET", format!("/{token}")).await,
+            http::StatusCode::GONE
+        );
+        assert_eq!(
+            status("DELETE", format!("/{token}")).await,
+            http::StatusCode::GONE
+        );
+
+        assert_eq!(
+            status("POST", format!("/{token}/restore")).await,
+            http::StatusCode::NO_CONTENT
+        );
+        assert!(status("GET", format!("/{token}")).await.is_redirection());
+        assert_eq!(
+            status("POST", "/abc123/restore".to_string()).await,
+            http::StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn test_delete_url_not_found() {
+        let state = Arc::new(RwLock::new(AppState::default()));
+
+        let result = delete_url(State(state), Path("abc123".to_string()), HeaderMap::new()).await;
+        assert_eq!(result.unwrap_err(), http::StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_delete_url_of_another_key() {
+        let config = Config {
+            api_keys: HashSet::from(["alpha".to_string(), "beta".to_string()]),
+            ..Config::default()
+        };
+        let mut store: Box<dyn AsyncStoreAccess> = Box::new(Store::default());
+        let token = store
+            .register_url(Url::parse("https://example.com").unwrap())
+            .await
+            .unwrap();
+        store
+            .set_owner(token.as_str(), Some("alpha".to_string()))
+            .await
+            .unwrap();
+        let app = app_router(store, config);
+        let delete = |key: &'static str| {
+            let request = Request::builder()
+                .method("DELETE")
+                .uri(format!("/{token}"))
+                .header("authorization", format!("Bearer {key}"))
+                .body(axum::body::Body::empty())
+                .unwrap();
+            let app = app.clone();
+            async move { app.oneshot(request).await.unwrap().status() }
+        };
+
+        assert_eq!(delete("beta").await, http::StatusCode::FORBIDDEN);
+        assert_eq!(delete("alpha").await, http::StatusCode::NO_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn test_restore_url_of_another_key() {
+        let config = Config {
+            api_keys: HashSet::from(["alpha".to_string(), "beta".to_string()]),
+            ..Config::default()
+        };
+        let mut store: Box<dyn AsyncStoreAccess> = Box::new(Store::default());
+        let token = store
+            .register_url(Url::parse("https://example.com").unwrap())
+            .await
+            .unwrap();
+        store
+            .set_owner(token.as_str(), Some("alpha".to_string()))
+            .await
+            .unwrap();
+        store.delete_token(token.as_str()).await.unwrap();
+        let app = app_router(store, config);
+        let restore = |key: &'static str| {
+            let request = Request::builder()
+                .method("POST")
+                .uri(format!("/{token}/restore"))
+                .header("authorization", format!("Bearer {key}"))
+                .body(axum::body::Body::empty())
+                .unwrap();
+            let app = app.clone();
+            async move { app.oneshot(request).await.unwrap().status() }
+        };
+
+        assert_eq!(restore("beta").await, http::StatusCode::FORBIDDEN);
+        assert_eq!(restore("alpha").await, http::StatusCode::NO_CONTENT);
+    }
+
+    fn json_register_request(body: &str) -> Request {
+        let mut req = Request::builder()
+            .uri("http://example.com")
+            .header("host", "example.com")
+            .header("content-type", "application/json")
+            .body(axum::body::Body::from(body.to_string()))
+            .unwrap();
+        req.headers_mut()
+            .insert("x-forwarded-proto", "https".parse().unwrap());
+        req
+    }
+
+    #[tokio::test]
+    async fn test_register_url_with_alias() {
+        let state = Arc::new(RwLock::new(AppState::default()));
+        let req = json_register_request(r#"{"url": "https://target.com", "alias": "my-launch"}"#);
+
+        let result = register_url(State(state.clone()), req).await;
+        let body = json_body(result.unwrap()).await;
+        assert_eq!(body["short_url"], "https://example.com/my-launch");
+
+        let result = resolve_url(
+            State(state),
+            Path("my-launch".to_string()),
+            Query(ResolveParams::default()),
+            HeaderMap::new(),
+        )
+        .await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_register_url_with_taken_alias() {
+        let mock_store =
+            MockStore::new().with_url("my-launch", Url::parse("https://example.com").unwrap());
+        let state = Arc::new(RwLock::new(AppState::new(Box::new(mock_store))));
+        let req = json_register_request(r#"{"url": "https://target.com", "alias": "my-launch"}"#);
+
+        let result = register_url(State(state), req).await;
+        assert_eq!(result.unwrap_err(), http::StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn test_register_url_with_same_alias_and_url_is_idempotent() {
+        let state = Arc::new(RwLock::new(AppState::default()));
+        let body = r#"{"url": "https://target.com", "alias": "my-launch"}"#;
+
+        for status in [http::StatusCode::CREATED, http::StatusCode::OK] {
+            let response = register_url(State(state.clone()), json_register_request(body))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), status);
+            assert_eq!(json_body(response).await["token"], "my-launch");
+        }
+
+        // The same alias for another URL still conflicts
+        let req = json_register_request(r#"{"url": "https://other.com", "alias": "my-launch"}"#);
+        let result = register_url(State(state.clone()), req).await;
+        assert_eq!(result.unwrap_err(), http::StatusCode::CONFLICT);
+
+        // As does retrying once the alias was deleted
+        state
+            .write()
+            .await
+            .store
+            .delete_token("my-launch")
+            .await
+            .unwrap();
+        let result = register_url(State(state), json_register_request(body)).await;
+        assert_eq!(result.unwrap_err(), http::StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn test_register_url_enforces_min_alias_length() {
+        let state = Arc::new(RwLock::new(AppState::default()));
+
+        let req = json_register_request(r#"{"url": "https://target.com", "alias": "ab"}"#);
+        let result = register_url(State(state.clone()), req).await;
+        assert_eq!(result.unwrap_err(), http::StatusCode::BAD_REQUEST);
+
+        let req = json_register_request(r#"{"url": "https://target.com", "alias": "abc"}"#);
+        let response = register_url(State(state), req).await.unwrap();
+        assert_eq!(json_body(response).await["token"], "abc");
+    }
+
+    #[tokio::test]
+    async fn test_register_url_with_reserved_alias() {
+        let state = Arc::new(RwLock::new(AppState::default()));
+        let req = json_register_request(r#"{"url": "https://target.com", "alias": "metrics"}"#);
+
+        let result = register_url(State(state), req).await;
+        assert_eq!(result.unwrap_err(), http::StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn test_alias_availability() {
+        let mut store = Store::default();
+        StoreAccess::register_url_with_alias(
+            &mut store,
+            Url::parse("https://target.com").unwrap(),
+            "my-launch",
+        )
+        .unwrap();
+        let state = Arc::new(RwLock::new(AppState::new(Box::new(store))));
+        let check = |alias: &str| {
+            let state = state.clone();
+            let alias = alias.to_string();
+            async move {
+                alias_availability(State(state), Path(alias))
+                    .await
+                    .unwrap()
+                    .0
+            }
+        };
+
+        let availability = check("promo").await;
+        assert!(availability.available);
+        assert_eq!(availability.reason, None);
+
+        for (alias, reason) in [
+            ("my-launch", "Alias is already taken"),
+            ("metrics", "Alias is reserved"),
+            ("ab", "Alias must be at least 3 characters long"),
+            (
+                "my launch!",
+                "Alias may only contain letters, digits, '-' and '_'",
+            ),
+        ] {
+            let availability = check(alias).await;
+            assert!(!availability.available, "{alias}");
+            assert_eq!(availability.reason.as_deref(), Some(reason));
+        }
+        // Checking doesn't register anything
+        let state = state.read().await;
+        assert!(state.store.link_info("promo").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_register_url_conflicts_in_unique_mode() {
+        let store = Store::default().with_unique_urls(true);
+        let state = Arc::new(RwLock::new(AppState::new(Box::new(store))));
+
+        let response = register_url(State(state.clone()), register_request("https://target.com"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::CREATED);
+
+        // Normalizes to the URL registered above
+        let result = register_url(State(state), register_request("HTTPS://Target.com:443/")).await;
+        assert_eq!(result.unwrap_err(), http::StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn test_register_url_dedup_skips_per_link_options() {
+        let store = Store::default().with_dedup(true);
+        let state = Arc::new(RwLock::new(AppState {
+            dedup: true,
+            api_keys: HashSet::from(["alpha".to_string()]),
+            ..AppState::new(Box::new(store))
+        }));
+        let register = |req| {
+            let state = state.clone();
+            async move {
+                let response = register_url(State(state), req).await.unwrap();
+                json_body(response).await["token"]
+                    .as_str()
+                    .unwrap()
+                    .to_string()
+            }
+        };
+
+        let plain = register(register_request("https://target.com")).await;
+        assert_eq!(
+            register(register_request("https://target.com")).await,
+            plain
+        );
+
+        // Each of these gets a link of its own and leaves the plain one alone
+        let protected = register(json_register_request(
+            r#"{"url": "https://target.com", "password": "hunter2"}"#,
+        ))
+        .await;
+        let limited = register(json_register_request(
+            r#"{"url": "https://target.com", "max_hits": 3}"#,
+        ))
+        .await;
+        let mut keyed = register_request("https://target.com");
+        keyed
+            .headers_mut()
+            .insert("authorization", "Bearer alpha".parse().unwrap());
+        let owned = register(keyed).await;
+        for token in [&protected, &limited, &owned] {
+            assert_ne!(token, &plain);
+        }
+        let record = state.read().await.store.link_info(&plain).await.unwrap();
+        assert!(record.password_hash.is_none());
+        assert!(record.max_hits.is_none());
+        assert!(record.owner.is_none());
+        assert_eq!(
+            register(register_request("https://target.com")).await,
+            plain
+        );
+    }
+
+    #[tokio::test]
+    async fn test_register_url_dedup_hit_answers_ok() {
+        let state = Arc::new(RwLock::new(AppState {
+            dedup: true,
+            ..AppState::new(Box::new(Store::default().with_dedup(true)))
+        }));
+
+        let first = register_url(State(state.clone()), register_request("https://target.com"))
+            .await
+            .unwrap();
+        assert_eq!(first.status(), http::StatusCode::CREATED);
+        let first = json_body(first).await;
+        let second = register_url(State(state.clone()), register_request("https://target.com"))
+            .await
+            .unwrap();
+        assert_eq!(second.status(), http::StatusCode::OK);
+        assert_eq!(json_body(second).await, first);
+
+        let metrics = state.read().await.metrics.render();
+        assert!(metrics.contains("urls_registered_total 1\n"), "{metrics}");
+    }
+
+    #[tokio::test]
+    async fn test_register_url_replays_idempotency_key() {
+        let state = Arc::new(RwLock::new(AppState::default()));
+        let idempotent_request = || {
+            let mut req = register_request("https://target.com");
+            req.headers_mut()
+                .insert("idempotency-key", "retry-1".parse().unwrap());
+            req
+        };
+
+        let first = register_url(State(state.clone()), idempotent_request())
+            .await
+            .unwrap();
+        let second = register_url(State(state.clone()), idempotent_request())
+            .await
+            .unwrap();
+        assert_eq!(second.status(), http::StatusCode::CREATED);
+        assert_eq!(json_body(first).await, json_body(second).await);
+        assert_eq!(state.read().await.store.list(0, 10).await.unwrap().len(), 1);
+
+        // A different key registers anew, as the default store doesn't deduplicate
+        let mut req = register_request("https://target.com");
+        req.headers_mut()
+            .insert("idempotency-key", "retry-2".parse().unwrap());
+        register_url(State(state.clone()), req).await.unwrap();
+        assert_eq!(state.read().await.store.list(0, 10).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_register_url_rejects_idempotency_key_for_another_body() {
+        let state = Arc::new(RwLock::new(AppState::default()));
+        let idempotent_request = |target: &str| {
+            let mut req = register_request(target);
+            req.headers_mut()
+                .insert("idempotency-key", "retry-1".parse().unwrap());
+            req
+        };
+
+        register_url(
+            State(state.clone()),
+            idempotent_request("https://target.com"),
+        )
+        .await
+        .unwrap();
+        let result = register_url(
+            State(state.clone()),
+            idempotent_request("https://other.com"),
+        )
+        .await;
+        assert_eq!(result.unwrap_err(), http::StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(state.read().await.store.list(0, 10).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_namespaces_keep_tokens_apart() {
+        let mut state = AppState::default();
+        for namespace in ["docs", "blog"] {
+            state
+                .namespaces
+                .insert(namespace.to_string(), Box::new(Store::default()));
+        }
+        let state = Arc::new(RwLock::new(state));
+
+        for (namespace, target) in [
+            ("docs", "https://docs.rs"),
+            ("blog", "https://blog.rust-lang.org"),
+        ] {
+            let req = json_register_request(&format!(
+                r#"{{"url": "{target}", "alias": "launch", "namespace": "{namespace}"}}"#
+            ));
+            let body = json_body(register_url(State(state.clone()), req).await.unwrap()).await;
+            assert_eq!(
+                body["short_url"],
+                format!("https://example.com/{namespace}/launch")
+            );
+        }
+        // The default namespace is untouched
+        assert!(state.read().await.store.link_info("launch").await.is_err());
+
+        let app = routes(state.clone(), cors_layer(&[]));
+        for (namespace, target) in [
+            ("docs", "https://docs.rs/"),
+            ("blog", "https://blog.rust-lang.org/"),
+        ] {
+            let response = app
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .uri(format!("/{namespace}/launch"))
+                        .body(axum::body::Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), http::StatusCode::SEE_OTHER);
+            assert_eq!(response.headers()[http::header::LOCATION], target);
+        }
+
+        let req = json_register_request(r#"{"url": "https://target.com", "namespace": "wiki"}"#);
+        let result = register_url(State(state), req).await;
+        assert_eq!(result.unwrap_err(), http::StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_register_url_with_destinations() {
+        let state = Arc::new(RwLock::new(AppState::default()));
+        let req = json_register_request(
+            r#"{"url": "https://target.com", "destinations": [
+                {"url": "https://a.com", "weight": 1},
+                {"url": "https://b.com", "weight": 1}
+            ]}"#,
+        );
+        let body = json_body(register_url(State(state.clone()), req).await.unwrap()).await;
+        let token = body["token"].as_str().unwrap().to_string();
+
+        let mut seen = HashSet::new();
+        for _ in 0..50 {
+            let response = resolve_url(
+                State(state.clone()),
+                Path(token.clone()),
+                Query(ResolveParams::default()),
+                HeaderMap::new(),
+            )
+            .await
+            .unwrap();
+            seen.insert(response.headers()[http::header::LOCATION].clone());
+        }
+        assert_eq!(seen.len(), 2);
+        assert!(seen
+            .iter()
+            .all(|l| l == "https://a.com/" || l == "https://b.com/"));
+
+        let req = json_register_request(
+            r#"{"url": "https://target.com", "destinations": [{"url": "https://a.com", "weight": 0}]}"#,
+        );
+        let result = register_url(State(state.clone()), req).await;
+        assert_eq!(result.unwrap_err(), http::StatusCode::BAD_REQUEST);
+
+        let req = json_register_request(
+            r#"{"url": "https://target.com", "destinations": [{"url": "ftp://a.com", "weight": 1}]}"#,
+        );
+        let result = register_url(State(state), req).await;
+        assert_eq!(result.unwrap_err(), http::StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_register_url_with_utm() {
+        let state = Arc::new(RwLock::new(AppState::default()));
+        let req = json_register_request(
+            r#"{"url": "https://target.com/page?ref=home#top", "utm": {"utm_source": "foo"}}"#,
+        );
+        let body = json_body(register_url(State(state.clone()), req).await.unwrap()).await;
+        let token = body["token"].as_str().unwrap().to_string();
+
+        let response = resolve_url(
+            State(state.clone()),
+            Path(token.clone()),
+            Query(ResolveParams::default()),
+            HeaderMap::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            response.headers()[http::header::LOCATION],
+            "https://target.com/page?ref=home&utm_source=foo#top"
+        );
+        // Stored untouched, so the tags can't pile up
+        let record = state.read().await.store.link_info(&token).await.unwrap();
+        assert_eq!(record.url.as_str(), "https://target.com/page?ref=home#top");
+
+        let req =
+            json_register_request(r#"{"url": "https://target.com", "utm": {"source": "foo"}}"#);
+        let result = register_url(State(state), req).await;
+        assert_eq!(result.unwrap_err(), http::StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_head_redirect_includes_utm() {
+        let state = Arc::new(RwLock::new(AppState::default()));
+        let req = json_register_request(
+            r#"{"url": "https://target.com/page", "utm": {"utm_source": "foo"}}"#,
+        );
+        let body = json_body(register_url(State(state.clone()), req).await.unwrap()).await;
+        let token = body["token"].as_str().unwrap().to_string();
+
+        let head = peek_url(
+            State(state.clone()),
+            Path(token.clone()),
+            Query(ResolveParams::default()),
+            HeaderMap::new(),
+        )
+        .await
+        .unwrap();
+        let get = resolve_url(
+            State(state),
+            Path(token),
+            Query(ResolveParams::default()),
+            HeaderMap::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            head.headers()[http::header::LOCATION],
+            "https://target.com/page?utm_source=foo"
+        );
+        assert_eq!(
+            head.headers()[http::header::LOCATION],
+            get.headers()[http::header::LOCATION]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fragment_survives_round_trip() {
+        let state = Arc::new(RwLock::new(AppState::default()));
+        let req = json_register_request(r#"{"url": "https://target.com/page#section"}"#);
+        let body = json_body(register_url(State(state.clone()), req).await.unwrap()).await;
+        let token = body["token"].as_str().unwrap().to_string();
+
+        let response = resolve_url(
+            State(state),
+            Path(token),
+            Query(ResolveParams::default()),
+            HeaderMap::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            response.headers()[http::header::LOCATION],
+            "https://target.com/page#section"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_register_url_with_max_hits() {
+        let state = Arc::new(RwLock::new(AppState::default()));
+        let req = json_register_request(r#"{"url": "https://target.com", "max_hits": 1}"#);
+        let body = json_body(register_url(State(state.clone()), req).await.unwrap()).await;
+        let token = body["token"].as_str().unwrap().to_string();
+
+        let resolve = || {
+            resolve_url(
+                State(state.clone()),
+                Path(token.clone()),
+                Query(ResolveParams::default()),
+                HeaderMap::new(),
+            )
+        };
+        assert_eq!(
+            resolve().await.unwrap().status(),
+            http::StatusCode::SEE_OTHER
+        );
+        assert_eq!(resolve().await.unwrap_err(), http::StatusCode::GONE);
+
+        let req = json_register_request(r#"{"url": "https://target.com", "max_hits": 0}"#);
+        let result = register_url(State(state), req).await;
+        assert_eq!(result.unwrap_err(), http::StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_register_url_with_invalid_alias() {
+        let state = Arc::new(RwLock::new(AppState::default()));
+        let req = json_register_request(r#"{"url": "https://target.com", "alias": "my launch!"}"#);
+
+        let result = register_url(State(state), req).await;
+        assert_eq!(result.unwrap_err(), http::StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_register_url_json_without_alias() {
+        let state = Arc::new(RwLock::new(AppState::default()));
+        let req = json_register_request(r#"{"url": "https://target.com"}"#);
+
+        let result = register_url(State(state), req).await;
+        let body = json_body(result.unwrap()).await;
+        assert!(body["short_url"]
+            .as_str()
+            .unwrap()
+            .starts_with("https://example.com/"));
+    }
+
+    #[tokio::test]
+    async fn test_register_url_json_and_raw_body_store_same_target() {
+        let state = Arc::new(RwLock::new(AppState::default()));
+
+        let raw = register_url(State(state.clone()), register_request("https://target.com"))
+            .await
+            .unwrap();
+        let json = register_url(
+            State(state.clone()),
+            json_register_request(r#"{"url": "https://target.com"}"#),
+        )
+        .await
+        .unwrap();
+        assert_eq!(raw.status(), json.status());
+
+        let state = state.read().await;
+        for response in [raw, json] {
+            let body = json_body(response).await;
+            let token = body["token"].as_str().unwrap();
+            let info = state.store.link_info(token).await.unwrap();
+            assert_eq!(info.url.as_str(), "https://target.com/");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_extract_body_url_from_json() {
+        let req = Request::builder()
+            .uri("http://localhost:3000")
+            .header("content-type", "application/json; charset=utf-8")
+            .body(axum::body::Body::from(r#"{"url": "https://example.com"}"#))
+            .unwrap();
+
+        let result = extract_body_url(req, Config::default().max_url_length)
+            .await
+            .unwrap();
+        assert_eq!(result.to_string(), "https://example.com/");
+    }
+
+    fn ttl_register_request(expires_in: &str) -> Request {
+        Request::builder()
+            .uri("http://example.com")
+            .header("host", "example.com")
+            .header("x-expires-in", expires_in)
+            .body(axum::body::Body::from("https://target.com"))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_register_url_with_ttl() {
+        let state = Arc::new(RwLock::new(AppState::default()));
+
+        let response = register_url(State(state.clone()), ttl_register_request("60"))
+            .await
+            .unwrap();
+        let body = json_body(response).await;
+        let token = body["token"].as_str().unwrap().to_string();
+
+        let result = resolve_url(
+            State(state),
+            Path(token),
+            Query(ResolveParams::default()),
+            HeaderMap::new(),
+        )
+        .await;
+        assert!(result.is_ok());
+    }
+
+    fn expires_at_register_request(expires_at: &str) -> Request {
+        Request::builder()
+            .uri("http://example.com")
+            .header("host", "example.com")
+            .header("x-expires-at", expires_at)
+            .body(axum::body::Body::from("https://target.com"))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_register_url_with_expires_at() {
+        let expires_at = SystemTime::now() + Duration::from_secs(3600);
+        let state = Arc::new(RwLock::new(AppState::default()));
+
+        for header in [rfc3339(expires_at), epoch_secs(expires_at).to_string()] {
+            let response = register_url(State(state.clone()), expires_at_register_request(&header))
+                .await
+                .unwrap();
+            let body = json_body(response).await;
+            let token = body["token"].as_str().unwrap();
+
+            let info = state.read().await.store.link_info(token).await.unwrap();
+            let stored = info.expires_at.unwrap();
+            let drift = stored
+                .duration_since(expires_at)
+                .unwrap_or_else(|e| e.duration());
+            assert!(drift < Duration::from_secs(5), "{header}: {drift:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_register_url_with_past_expires_at() {
+        let state = Arc::new(RwLock::new(AppState::default()));
+
+        for header in ["2020-01-01T00:00:00Z", "1577836800", "tomorrow"] {
+            let result =
+                register_url(State(state.clone()), expires_at_register_request(header)).await;
+            assert_eq!(result.unwrap_err(), http::StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_register_url_with_invalid_ttl() {
+        let state = Arc::new(RwLock::new(AppState::default()));
+
+        let result = register_url(State(state), ttl_register_request("soon")).await;
+        assert_eq!(result.unwrap_err(), http::StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_resolve_url_expired() {
+        let mock_store =
+            MockStore::new().with_expired_url("abc123", Url::parse("https://example.com").unwrap());
+        let state = Arc::new(RwLock::new(AppState::new(Box::new(mock_store))));
+
+        let result = resolve_url(
+            State(state),
+            Path("abc123".to_string()),
+            Query(ResolveParams::default()),
+            HeaderMap::new(),
+        )
+        .await;
+        assert_eq!(result.unwrap_err(), http::StatusCode::GONE);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_resolves() {
+        let mock_store =
+            MockStore::new().with_url("abc123", Url::parse("https://example.com").unwrap());
+        let state = Arc::new(RwLock::new(AppState::new(Box::new(mock_store))));
+
+        let handles: Vec<_> = (0..16)
+            .map(|_| {
+                tokio::spawn(resolve_url(
+                    State(state.clone()),
+                    Path("abc123".to_string()),
+                    Query(ResolveParams::default()),
+                    HeaderMap::new(),
+                ))
+            })
+            .collect();
+
+        for handle in handles {
+            assert!(handle.await.unwrap().is_ok());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_resolves_count_every_hit() {
+        let mut store = Store::default();
+        let token =
+            StoreAccess::register_url(&mut store, Url::parse("https://example.com").unwrap())
+                .unwrap();
+        let state = Arc::new(RwLock::new(AppState::new(Box::new(store))));
+
+        // Held throughout: resolves only need to share the lock, never to take it exclusively
+        let reader = state.read().await;
+        let handles: Vec<_> = (0..64)
+            .map(|_| {
+                tokio::spawn(resolve_url(
+                    State(state.clone()),
+                    Path(token.to_string()),
+                    Query(ResolveParams::default()),
+                    HeaderMap::new(),
+                ))
+            })
+            .collect();
+        for handle in handles {
+            assert!(handle.await.unwrap().is_ok());
+        }
+
+        assert_eq!(reader.store.hit_count(token.as_str()).await.unwrap(), 64);
+    }
+
+    #[tokio::test]
+    async fn test_panicking_handler_does_not_lock_out_others() {
+        let mock_store =
+            MockStore::new().with_url("abc123", Url::parse("https://example.com").unwrap());
+        let state = Arc::new(RwLock::new(AppState::new(Box::new(mock_store))));
+
+        let panicked = tokio::spawn({
+            let state = state.clone();
+            async move {
+                let _guard = state.write().await;
+                panic!("handler panicked while holding the state lock");
+            }
+        })
+        .await;
+        assert!(panicked.unwrap_err().is_panic());
+
+        let result = resolve_url(
+            State(state.clone()),
+            Path("abc123".to_string()),
+            Query(ResolveParams::default()),
+            HeaderMap::new(),
+        )
+        .await;
+        assert!(result.is_ok());
+        let result = register_url(State(state), register_request("https://target.com")).await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_register_url_returns_json() {
+        let state = Arc::new(RwLock::new(AppState::default()));
+        let req = Request::builder()
+            .uri("http://example.com")
+            .header("host", "example.com")
+            .body(axum::body::Body::from("https://target.com"))
+            .unwrap();
+
+        let response = register_url(State(state), req).await.unwrap();
+        assert_eq!(
+            response.headers().get("content-type").unwrap(),
+            "application/json"
+        );
+        let body = json_body(response).await;
+        let token = body["token"].as_str().unwrap();
+        assert_eq!(body["short_url"], format!("http://example.com/{token}"));
+    }
+
+    #[tokio::test]
+    async fn test_register_url_returns_created_with_location() {
+        let state = Arc::new(RwLock::new(AppState::default()));
+
+        let response = register_url(State(state), register_request("https://target.com"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::CREATED);
+        let location = response.headers()[http::header::LOCATION]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let body = json_body(response).await;
+        assert_eq!(body["short_url"], location);
+    }
+
+    #[tokio::test]
+    async fn test_register_url_returns_plain_text() {
+        let state = Arc::new(RwLock::new(AppState::default()));
+        let req = Request::builder()
+            .uri("http://example.com")
+            .header("host", "example.com")
+            .header("accept", "text/plain")
+            .body(axum::body::Body::from("https://target.com"))
+            .unwrap();
+
+        let response = register_url(State(state), req).await.unwrap();
+        assert!(response
+            .headers()
+            .get("content-type")
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .starts_with("text/plain"));
+        assert!(body_string(response)
+            .await
+            .starts_with("http://example.com/"));
+    }
+
+    #[tokio::test]
+    async fn test_register_url_returns_html_anchor() {
+        let state = Arc::new(RwLock::new(AppState::default()));
+        let req = Request::builder()
+            .uri("http://example.com")
+            .header("host", "example.com")
+            .header("accept", "text/html")
+            .body(axum::body::Body::from("https://target.com"))
+            .unwrap();
+
+        let response = register_url(State(state), req).await.unwrap();
+        assert_eq!(response.status(), http::StatusCode::CREATED);
+        assert!(response
+            .headers()
+            .get("content-type")
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .starts_with("text/html"));
+        let short_url = response
+            .headers()
+            .get("location")
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert!(short_url.starts_with("http://example.com/"));
+        let escaped = escape_html(&short_url);
+        assert_eq!(
+            body_string(response).await,
+            format!(r#"<a href="{escaped}">{escaped}</a>"#)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_register_url_html_anchor_escapes_host() {
+        let state = Arc::new(RwLock::new(AppState::default()));
+        // The short URL is built from the client's Host header
+        let req = Request::builder()
+            .uri("/")
+            .header("host", r#"evil.com"'&"#)
+            .header("accept", "text/html")
+            .body(axum::body::Body::from("https://target.com"))
+            .unwrap();
+
+        let response = register_url(State(state), req).await.unwrap();
+        let body = body_string(response).await;
+        assert!(body.contains("evil.com&quot;&#39;&amp;/"), "{body}");
+        assert!(!body.contains(r#"evil.com"'"#), "{body}");
+    }
+
+    #[test]
+    fn test_created_format_from_headers() {
+        let format = |accept: &str| {
+            let mut headers = http::HeaderMap::new();
+            headers.insert("accept", accept.parse().unwrap());
+            CreatedFormat::from_headers(&headers)
+        };
+        assert_eq!(format("text/html"), CreatedFormat::Html);
+        assert_eq!(format("text/plain"), CreatedFormat::PlainText);
+        assert_eq!(format("text/html, application/json"), CreatedFormat::Json);
+        assert_eq!(format("*/*"), CreatedFormat::Json);
+        assert_eq!(
+            CreatedFormat::from_headers(&http::HeaderMap::new()),
+            CreatedFormat::Json
+        );
+    }
+
+    #[tokio::test]
+    async fn test_link_info() {
+        let state = Arc::new(RwLock::new(AppState::default()));
+        let token = state
+            .write()
+            .await
+            .store
+            .register_url(Url::parse("https://example.com").unwrap())
+            .await
+            .unwrap();
+
+        let info = json_body(info_of(&state, token.as_str()).await.unwrap()).await;
+        assert_eq!(info["url"], "https://example.com/");
+        assert_eq!(info["hits"], 0);
+        assert!(info.get("token_encodings").is_none());
+        let created_at: SystemTime =
+            DateTime::parse_from_rfc3339(info["created_at"].as_str().unwrap())
+                .unwrap()
+                .into();
+        let age = SystemTime::now().duration_since(created_at).unwrap();
+        assert!(age < Duration::from_secs(5));
+
+        let _ = resolve_url(
+            State(state.clone()),
+            Path(token.to_string()),
+            Query(ResolveParams::default()),
+            HeaderMap::new(),
+        )
+        .await
+        .unwrap();
+        info_of(&state, token.as_str()).await.unwrap();
+        let info = json_body(info_of(&state, token.as_str()).await.unwrap()).await;
+        assert_eq!(info["hits"], 1);
+    }
+
+    /// `GET /{token}/info` without debug output.
+    async fn info_of(state: &SharedState, token: &str) -> Result<Response, AppError> {
+        link_info(
+            State(state.clone()),
+            Path(token.to_string()),
+            Query(InfoParams::default()),
+            Query(ResolveParams::default()),
+            HeaderMap::new(),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_link_info_debug_encodings() {
+        let state = Arc::new(RwLock::new(AppState {
+            api_keys: HashSet::from(["secret".to_string()]),
+            ..AppState::default()
+        }));
+        state
+            .write()
+            .await
+            .store
+            .register_url_with_alias(Url::parse("https://example.com").unwrap(), "Z9x")
+            .await
+            .unwrap();
+        let debug_info = |authorization: Option<&'static str>| {
+            let mut headers = HeaderMap::new();
+            if let Some(authorization) = authorization {
+                headers.insert("authorization", authorization.parse().unwrap());
+            }
+            link_info(
+                State(state.clone()),
+                Path("Z9x".to_string()),
+                Query(InfoParams { debug: true }),
+                Query(ResolveParams::default()),
+                headers,
+            )
+        };
+
+        let response = debug_info(None).await.unwrap();
+        assert_eq!(response.status(), http::StatusCode::UNAUTHORIZED);
+
+        let info = json_body(debug_info(Some("Bearer secret")).await.unwrap()).await;
+        assert_eq!(info["token_encodings"]["hex"], "5a3978");
+        // Z, 9 and x are digits 51, 61 and 23
+        assert_eq!(
+            info["token_encodings"]["base62"],
+            (51 * 62 * 62 + 61 * 62 + 23).to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_link_preview() {
+        let mut state = AppState::default();
+        let token = state
+            .store
+            .register_url(Url::parse("https://example.com/a?b=1&c=<2>").unwrap())
+            .await
+            .unwrap();
+        let state = Arc::new(RwLock::new(state));
+
+        let Html(body) = link_preview(
+            State(state.clone()),
+            Path(token.to_string()),
+            Query(ResolveParams::default()),
+            HeaderMap::new(),
+        )
+        .await
+        .unwrap();
+        assert!(body.contains(r#"<a href="https://example.com/a?b=1&amp;c=%3C2%3E""#));
+        assert_eq!(
+            state
+                .read()
+                .await
+                .store
+                .hit_count(token.as_str())
+                .await
+                .unwrap(),
+            0
+        );
+
+        let result = link_preview(
+            State(state),
+            Path("missing".to_string()),
+            Query(ResolveParams::default()),
+            HeaderMap::new(),
+        )
+        .await;
+        assert_eq!(result.unwrap_err(), http::StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_link_preview_of_protected_link_needs_password() {
+        let mut state = AppState::default();
+        let token = state
+            .store
+            .register_url(Url::parse("https://example.com").unwrap())
+            .await
+            .unwrap();
+        state
+            .store
+            .set_password_hash(token.as_str(), Some(password::hash("hunter2").unwrap()))
+            .await
+            .unwrap();
+        let state = Arc::new(RwLock::new(state));
+
+        let result = link_preview(
+            State(state.clone()),
+            Path(token.to_string()),
+            Query(ResolveParams::default()),
+            HeaderMap::new(),
+        )
+        .await;
+        assert_eq!(result.unwrap_err(), http::StatusCode::UNAUTHORIZED);
+
+        let result = link_preview(
+            State(state),
+            Path(token.to_string()),
+            Query(ResolveParams {
+                pw: Some("hunter2".to_string()),
+            }),
+            HeaderMap::new(),
+        )
+        .await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_link_info_of_protected_link_needs_password() {
+        let mut store: Box<dyn AsyncStoreAccess> = Box::new(Store::default());
+        let token = store
+            .register_url(Url::parse("https://secret.example.com").unwrap())
+            .await
+            .unwrap();
+        store
+            .set_password_hash(token.as_str(), Some(password::hash("hunter2").unwrap()))
+            .await
+            .unwrap();
+        let app = app_router(store, Config::default());
+        let get = |uri: String| {
+            let app = app.clone();
+            async move {
+                let request = Request::builder()
+                    .uri(uri)
+                    .body(axum::body::Body::empty())
+                    .unwrap();
+                app.oneshot(request).await.unwrap()
+            }
+        };
+
+        for path in ["info", "stats"] {
+            let response = get(format!("/{token}/{path}")).await;
+            assert_eq!(response.status(), http::StatusCode::UNAUTHORIZED, "{path}");
+            assert!(!body_string(response).await.contains("secret.example.com"));
+        }
+        let response = get(format!("/{token}.json")).await;
+        assert_eq!(response.status(), http::StatusCode::UNAUTHORIZED);
+        assert!(!body_string(response).await.contains("secret.example.com"));
+
+        for uri in [
+            format!("/{token}/info?pw=hunter2"),
+            format!("/{token}.json?pw=hunter2"),
+        ] {
+            let response = get(uri).await;
+            assert_eq!(response.status(), http::StatusCode::OK);
+            let body = json_body(response).await;
+            assert_eq!(body["url"], "https://secret.example.com/");
+        }
+        let response = get(format!("/{token}/stats?pw=hunter2")).await;
+        assert_eq!(response.status(), http::StatusCode::OK);
+    }
+
+    #[test]
+    fn test_escape_html() {
+        assert_eq!(
+            escape_html(r#"<a href="x">'&'</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;&#39;&amp;&#39;&lt;/a&gt;"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resolve_url_malformed_token() {
+        let state = Arc::new(RwLock::new(AppState::default()));
+        let resolve = |token: String| {
+            resolve_url(
+                State(state.clone()),
+                Path(token),
+                Query(ResolveParams::default()),
+                HeaderMap::new(),
+            )
+        };
+
+        let result = resolve("a".repeat(40)).await;
+        assert_eq!(result.unwrap_err(), http::StatusCode::BAD_REQUEST);
+        let result = resolve("abc$123".to_string()).await;
+        assert_eq!(result.unwrap_err(), http::StatusCode::BAD_REQUEST);
+
+        let result = resolve("abc123".to_string()).await;
+        assert_eq!(result.unwrap_err(), http::StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn test_rfc3339() {
+        let time = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        assert_eq!(rfc3339(time), "2023-11-14T22:13:20Z");
+    }
+
+    #[tokio::test]
+    async fn test_link_info_not_found() {
+        let state = Arc::new(RwLock::new(AppState::default()));
+
+        let result = info_of(&state, "abc123").await;
+        assert_eq!(result.unwrap_err(), http::StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_link_stats() {
+        let state = Arc::new(RwLock::new(AppState::default()));
+        let token = state
+            .write()
+            .await
+            .store
+            .register_url(Url::parse("https://example.com").unwrap())
+            .await
+            .unwrap();
+
+        for _ in 0..3 {
+            let _ = resolve_url(
+                State(state.clone()),
+                Path(token.to_string()),
+                Query(ResolveParams::default()),
+                HeaderMap::new(),
+            )
+            .await
+            .unwrap();
+        }
+
+        let Json(stats) = link_stats(
+            State(state),
+            Path(token.to_string()),
+            Query(ResolveParams::default()),
+            HeaderMap::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(stats.hits, 3);
+        assert!(DateTime::parse_from_rfc3339(&stats.created_at).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_link_stats_not_found() {
+        let state = Arc::new(RwLock::new(AppState::default()));
+
+        let result = link_stats(
+            State(state),
+            Path("abc123".to_string()),
+            Query(ResolveParams::default()),
+            HeaderMap::new(),
+        )
+        .await;
+        assert_eq!(result.unwrap_err(), http::StatusCode::NOT_FOUND);
+    }
+
+    fn register_request(target: &str) -> Request {
+        Request::builder()
+            .uri("http://example.com")
+            .header("host", "example.com")
+            .body(axum::body::Body::from(target.to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_register_url_rejects_invalid_utf8() {
+        let state = Arc::new(RwLock::new(AppState::default()));
+        let req = Request::builder()
+            .uri("http://example.com")
+            .header("host", "example.com")
+            .body(axum::body::Body::from(b"https://target.com/\xff".to_vec()))
+            .unwrap();
+
+        let err = register_url(State(state), req).await.unwrap_err();
+        assert_eq!(err, http::StatusCode::BAD_REQUEST);
+        assert_eq!(err.message, "Body is not valid UTF-8");
+    }
+
+    #[tokio::test]
+    async fn test_register_url_stores_idn_host_as_punycode() {
+        let state = Arc::new(RwLock::new(AppState::default()));
+        let response = register_url(State(state.clone()), register_request("http://例え.jp"))
+            .await
+            .unwrap();
+        let token = json_body(response).await["token"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        let response = resolve_url(
+            State(state),
+            Path(token),
+            Query(ResolveParams::default()),
+            HeaderMap::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            response.headers()[http::header::LOCATION],
+            "http://xn--r8jz45g.jp/"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_register_url_rejects_disallowed_scheme() {
+        let state = Arc::new(RwLock::new(AppState::default()));
+
+        let result = register_url(State(state), register_request("javascript:alert(1)")).await;
+        assert_eq!(result.unwrap_err(), http::StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_register_url_accepts_allowed_scheme() {
+        let state = Arc::new(RwLock::new(AppState::default()));
+
+        let result = register_url(State(state), register_request("https://target.com")).await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_register_url_with_custom_allowed_schemes() {
+        let state = Arc::new(RwLock::new(AppState {
+            allowed_schemes: vec!["ftp".to_string()],
+            ..Default::default()
+        }));
+
+        let result = register_url(
+            State(state.clone()),
+            register_request("ftp://files.example.com"),
+        )
+        .await;
+        assert!(result.is_ok());
+
+        let result = register_url(State(state), register_request("https://target.com")).await;
+        assert_eq!(result.unwrap_err(), http::StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_register_url_blocks_private_hosts() {
+        let state = Arc::new(RwLock::new(AppState {
+            block_private_hosts: true,
+            ..Default::default()
+        }));
+
+        for target in [
+            "http://127.0.0.1",
+            "http://169.254.169.254/latest/meta-data/",
+            "http://10.1.2.3:8080/",
+            "http://localhost:8080/",
+            "http://[::1]/",
+            "http://[::ffff:192.168.0.1]/",
+        ] {
+            let result = register_url(State(state.clone()), register_request(target)).await;
+            assert_eq!(
+                result.unwrap_err(),
+                http::StatusCode::BAD_REQUEST,
+                "{target}"
+            );
+        }
+
+        let result = register_url(State(state), register_request("http://93.184.215.14/")).await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_register_url_enforces_domain_allowlist() {
+        let state = Arc::new(RwLock::new(AppState {
+            domain_allowlist: vec!["*.mycompany.com".to_string(), "partner.org".to_string()],
+            ..Default::default()
+        }));
+
+        for target in [
+            "https://wiki.mycompany.com/page",
+            "https://a.b.MyCompany.com./",
+            "https://partner.org/",
+        ] {
+            let result = register_url(State(state.clone()), register_request(target)).await;
+            assert_eq!(
+                result.unwrap().status(),
+                http::StatusCode::CREATED,
+                "{target}"
+            );
+        }
+        for target in [
+            "https://mycompany.com/",
+            "https://evilmycompany.com/",
+            "https://mycompany.com.evil.net/",
+            "https://sub.partner.org/",
+            "https://other.net/",
+        ] {
+            let result = register_url(State(state.clone()), register_request(target)).await;
+            assert_eq!(result.unwrap_err(), http::StatusCode::FORBIDDEN, "{target}");
+        }
+    }
+
+    #[test]
+    fn test_is_allowed_domain() {
+        let allowlist = ["*.example.com".to_string(), "10.0.0.1".to_string()];
+        assert!(is_allowed_domain("docs.example.com", &allowlist));
+        assert!(is_allowed_domain("10.0.0.1", &allowlist));
+        assert!(!is_allowed_domain("example.com", &allowlist));
+        assert!(!is_allowed_domain(".example.com", &allowlist));
+        assert!(!is_allowed_domain("10.0.0.10", &allowlist));
+        assert!(!is_allowed_domain("", &allowlist));
+    }
+
+    #[tokio::test]
+    async fn test_register_url_allows_private_hosts_by_default() {
+        let state = Arc::new(RwLock::new(AppState::default()));
+
+        let result = register_url(State(state), register_request("http://127.0.0.1")).await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_shorten_matches_root_registration() {
+        let app = app_router(Box::new(Store::default()), Config::default());
+        let register = |uri: &'static str| {
+            let app = app.clone();
+            async move {
+                let request = Request::builder()
+                    .method("POST")
+                    .uri(uri)
+                    .header("host", "example.com")
+                    .body(axum::body::Body::from("https://target.com"))
+                    .unwrap();
+                let response = app.oneshot(request).await.unwrap();
+                assert_eq!(response.status(), http::StatusCode::CREATED);
+                json_body(response).await
+            }
+        };
+
+        let root = register("/").await;
+        let shorten = register("/shorten").await;
+        for body in [&root, &shorten] {
+            let token = body["token"].as_str().unwrap();
+            assert_eq!(token.len(), Config::default().token_length);
+            assert_eq!(body["short_url"], format!("http://example.com/{token}"));
+        }
+
+        // Not a token
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/shorten")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    #[tokio::test]
+    async fn test_method_not_allowed() {
+        let app = app_router(Box::new(MockStore::new()), Config::default());
+        let request = |method: &str, uri: &str| {
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .body(axum::body::Body::empty())
+                .unwrap()
+        };
+        let allowed = |response: &Response| -> Vec<String> {
+            response.headers()[http::header::ALLOW]
+                .to_str()
+                .unwrap()
+                .split(',')
+                .map(|method| method.trim().to_string())
+                .collect()
+        };
+
+        let response = app
+            .clone()
+            .oneshot(request("POST", "/abc123"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::METHOD_NOT_ALLOWED);
+        let methods = allowed(&response);
+        assert!(methods.contains(&"GET".to_string()));
+        assert!(!methods.contains(&"POST".to_string()));
+        assert_eq!(
+            json_body(response).await,
+            serde_json::json!({"error": "Method POST not allowed here"})
+        );
+
+        let response = app
+            .clone()
+            .oneshot(request("POST", "/abc123/info"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(allowed(&response), ["GET", "HEAD"]);
+
+        let response = app.clone().oneshot(request("DELETE", "/")).await.unwrap();
+        assert_eq!(response.status(), http::StatusCode::METHOD_NOT_ALLOWED);
+        assert!(allowed(&response).contains(&"POST".to_string()));
+
+        let response = app.oneshot(request("GET", "/shorten")).await.unwrap();
+        assert_eq!(allowed(&response), ["POST"]);
+    }
+
+    #[tokio::test]
+    async fn test_index() {
+        let app = app_router(Box::new(MockStore::new()), Config::default());
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::OK);
+        assert!(response.headers()[http::header::CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .starts_with("text/html"));
+        assert!(body_string(response).await.contains("POST /"));
+    }
+
+    #[tokio::test]
+    async fn test_index_redirects_to_root_redirect() {
+        let app = app_router(
+            Box::new(Store::default()),
+            Config {
+                root_redirect: Some(Url::parse("https://mycompany.com/").unwrap()),
+                ..Config::default()
+            },
+        );
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::FOUND);
+        assert_eq!(
+            response.headers()[http::header::LOCATION],
+            "https://mycompany.com/"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_requests_pass_through_trace_layer() {
+        let subscriber = tracing_subscriber::fmt().with_test_writer().finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+        let mut store: Box<dyn AsyncStoreAccess> = Box::new(Store::default());
+        let token = store
+            .register_url(Url::parse("https://example.com").unwrap())
+            .await
+            .unwrap();
+        let app = app_router(store, Config::default());
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri(format!("/{token}"))
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert!(response.status().is_redirection());
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/missing")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::NOT_FOUND);
+    }
+
+    fn cross_origin_register_request(origin: &str) -> Request {
+        Request::builder()
+            .method("POST")
+            .uri("/")
+            .header("host", "example.com")
+            .header("origin", origin)
+            .body(axum::body::Body::from("https://target.com"))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_cors_allows_any_origin_by_default() {
+        let app = app_router(Box::new(MockStore::new()), Config::default());
+
+        let response = app
+            .oneshot(cross_origin_register_request("https://app.example.org"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::CREATED);
+        assert_eq!(
+            response.headers()[http::header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "*"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cors_restricted_to_allowlist() {
+        let config = Config {
+            cors_origins: vec!["https://app.example.org".to_string()],
+            ..Default::default()
+        };
+        let app = app_router(Box::new(MockStore::new()), config);
+
+        let response = app
+            .clone()
+            .oneshot(cross_origin_register_request("https://app.example.org"))
+            .await
+            .unwrap();
+        assert_eq!(
+            response.headers()[http::header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://app.example.org"
+        );
+
+        let response = app
+            .oneshot(cross_origin_register_request("https://evil.example.net"))
+            .await
+            .unwrap();
+        assert!(!response
+            .headers()
+            .contains_key(http::header::ACCESS_CONTROL_ALLOW_ORIGIN));
+    }
+
+    #[tokio::test]
+    async fn test_cors_preflight() {
+        let app = app_router(Box::new(MockStore::new()), Config::default());
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("OPTIONS")
+                    .uri("/")
+                    .header("origin", "https://app.example.org")
+                    .header("access-control-request-method", "POST")
+                    .header("access-control-request-headers", "authorization")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::OK);
+        assert_eq!(
+            response.headers()[http::header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "*"
+        );
+        assert!(response
+            .headers()
+            .contains_key(http::header::ACCESS_CONTROL_ALLOW_METHODS));
+    }
+
+    #[tokio::test]
+    async fn test_healthz() {
+        let app = app_router(Box::new(MockStore::new()), Config::default());
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/healthz")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::OK);
+        assert_eq!(body_string(response).await, "ok");
+    }
+
+    #[tokio::test]
+    async fn test_list_links_is_compressed() {
+        let mock_store = MockStore::new()
+            .with_url("abc123", Url::parse("https://first.com").unwrap())
+            .with_url("def456", Url::parse("https://second.com").unwrap());
+        let config = Config {
+            api_keys: HashSet::from(["secret".to_string()]),
+            ..Config::default()
+        };
+        let app = app_router(Box::new(mock_store), config);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/admin/links")
+                    .header("authorization", "Bearer secret")
+                    .header("accept-encoding", "gzip")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::OK);
+        assert_eq!(response.headers()[http::header::CONTENT_ENCODING], "gzip");
+    }
+
+    #[tokio::test]
+    async fn test_openapi() {
+        let app = app_router(Box::new(Store::default()), Config::default());
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/openapi.json")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::OK);
+        let body = json_body(response).await;
+        assert!(body["openapi"].as_str().unwrap().starts_with("3."));
+        assert!(body["paths"]["/{token}"]["get"].is_object());
+        assert!(body["paths"]["/"]["post"].is_object());
+        assert!(body["components"]["schemas"]["RegisterResponse"].is_object());
+    }
+
+    #[tokio::test]
+    async fn test_save_snapshot() {
+        let mut store = Store::default();
+        let token =
+            StoreAccess::register_url(&mut store, Url::parse("https://example.com").unwrap())
+                .unwrap();
+        let config = Config {
+            sweep_interval: None,
+            ..Config::default()
+        };
+        let (_, app) = create_namespaced_router(Box::new(store), HashMap::new(), config);
+
+        let path = std::env::temp_dir().join(format!("shortener-app-{}.json", std::process::id()));
+        app.save_snapshot(&path).await.unwrap();
+        let store = snapshot::load(&path, TokenConfig::default()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let links = StoreAccess::list(&store, 0, usize::MAX).unwrap();
+        assert_eq!(links.len(), 1);
+        assert_eq!(links[0].0, token);
+    }
+
+    #[tokio::test]
+    async fn test_sweep_expired() {
+        let mock_store = MockStore::new()
+            .with_url("abc123", Url::parse("https://live.com").unwrap())
+            .with_expired_url("def456", Url::parse("https://expired.com").unwrap());
+        let state = Arc::new(RwLock::new(AppState::new(Box::new(mock_store))));
+
+        assert_eq!(sweep_expired(&state).await.unwrap(), 1);
+
+        let state = state.read().await;
+        let err = state.store.link_info("def456").await.unwrap_err();
+        assert_eq!(err.downcast_ref::<StoreError>(), None);
+        assert!(state.store.link_info("abc123").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_errors_follow_accept_header() {
+        let app = app_router(Box::new(MockStore::new()), Config::default());
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/abc123")
+                    .header("accept", "text/plain")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::NOT_FOUND);
+        assert_eq!(body_string(response).await, "Token not found");
+    }
+
+    #[tokio::test]
+    async fn test_json_suffix_returns_link_info() {
+        let mock_store =
+            MockStore::new().with_url("abc123", Url::parse("https://target.com").unwrap());
+        let app = app_router(Box::new(mock_store), Config::default());
+        let get = |uri: &str| {
+            Request::builder()
+                .uri(uri)
+                .body(axum::body::Body::empty())
+                .unwrap()
+        };
+
+        let response = app.clone().oneshot(get("/abc123.json")).await.unwrap();
+        assert_eq!(response.status(), http::StatusCode::OK);
+        assert_eq!(json_body(response).await["url"], "https://target.com/");
+
+        let response = app.oneshot(get("/abc123")).await.unwrap();
+        assert_eq!(response.status(), http::StatusCode::SEE_OTHER);
+        assert_eq!(response.headers()["location"], "https://target.com/");
+    }
+
+    #[tokio::test]
+    async fn test_head_redirects_without_counting_a_hit() {
+        let mut store = Store::default();
+        let token =
+            StoreAccess::register_url(&mut store, Url::parse("https://example.com").unwrap())
+                .unwrap();
+        let app = app_router(Box::new(store), Config::default());
+        let request = |method, uri: String| {
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .body(axum::body::Body::empty())
+                .unwrap()
+        };
+
+        let response = app
+            .clone()
+            .oneshot(request(http::Method::HEAD, format!("/{token}")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::SEE_OTHER);
+        assert_eq!(response.headers()["location"], "https://example.com/");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(body.is_empty());
+
+        let response = app
+            .clone()
+            .oneshot(request(http::Method::HEAD, "/unknown".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::NOT_FOUND);
+
+        let response = app
+            .oneshot(request(http::Method::GET, format!("/{token}/stats")))
+            .await
+            .unwrap();
+        assert_eq!(json_body(response).await["hits"], 0);
+    }
+
+    #[tokio::test]
+    async fn test_redirect_dry_run() {
+        let mock_store =
+            MockStore::new().with_url("abc123", Url::parse("https://example.com").unwrap());
+        let app = app_router(Box::new(mock_store), Config::default());
+        let get = |uri: &str| {
+            Request::builder()
+                .uri(uri)
+                .body(axum::body::Body::empty())
+                .unwrap()
+        };
+
+        let response = app
+            .clone()
+            .oneshot(get("/abc123/redirect?dry_run=true"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::OK);
+        assert!(response.headers().get("location").is_none());
+        let body = json_body(response).await;
+        assert_eq!(body["url"], "https://example.com/");
+        assert_eq!(body["status"], 303);
+
+        let response = app.oneshot(get("/abc123/redirect")).await.unwrap();
+        assert_eq!(response.status(), http::StatusCode::SEE_OTHER);
+        assert_eq!(response.headers()["location"], "https://example.com/");
+    }
+
+    #[tokio::test]
+    async fn test_not_found_behaviors() {
+        let get = |uri: &str| {
+            Request::builder()
+                .uri(uri)
+                .body(axum::body::Body::empty())
+                .unwrap()
+        };
+        let app = |not_found_behavior| {
+            let config = Config {
+                not_found_behavior,
+                ..Default::default()
+            };
+            app_router(Box::new(MockStore::new()), config)
+        };
+
+        let response = app(NotFoundBehavior::Status)
+            .oneshot(get("/unknown"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::NOT_FOUND);
+        assert_eq!(json_body(response).await["error"], "Token not found");
+
+        let home = Url::parse("https://example.com/").unwrap();
+        let response = app(NotFoundBehavior::Redirect(home))
+            .oneshot(get("/unknown"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::FOUND);
+        assert_eq!(response.headers()["location"], "https://example.com/");
+
+        let page = "<h1>Nothing here</h1>".to_string();
+        let response = app(NotFoundBehavior::Page(page))
+            .oneshot(get("/unknown"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::NOT_FOUND);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"<h1>Nothing here</h1>");
+    }
+
+    #[tokio::test]
+    async fn test_expired_behaviors() {
+        let get = |uri: &str| {
+            Request::builder()
+                .uri(uri)
+                .body(axum::body::Body::empty())
+                .unwrap()
+        };
+        let app = |expired_behavior| {
+            let config = Config {
+                expired_behavior,
+                ..Default::default()
+            };
+            let store = MockStore::new()
+                .with_expired_url("abc123", Url::parse("https://target.com/").unwrap());
+            app_router(Box::new(store), config)
+        };
+
+        let response = app(ExpiredBehavior::Gone)
+            .oneshot(get("/abc123"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::GONE);
+
+        let response = app(ExpiredBehavior::NotFound)
+            .oneshot(get("/abc123"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::NOT_FOUND);
+
+        let expired = Url::parse("https://example.com/expired").unwrap();
+        let response = app(ExpiredBehavior::Redirect(expired))
+            .oneshot(get("/abc123"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::FOUND);
+        assert_eq!(
+            response.headers()["location"],
+            "https://example.com/expired"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resolve_url_with_interstitial() {
+        let mock_store =
+            MockStore::new().with_url("abc123", Url::parse("https://target.com/?a=1&b=2").unwrap());
+        let state = Arc::new(RwLock::new(AppState {
+            interstitial_seconds: Some(5),
+            ..AppState::new(Box::new(mock_store))
+        }));
+
+        let response = resolve_url(
+            State(state),
+            Path("abc123".to_string()),
+            Query(ResolveParams::default()),
+            HeaderMap::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), http::StatusCode::OK);
+        assert!(response.headers().get("location").is_none());
+        assert!(response.headers()["content-type"]
+            .to_str()
+            .unwrap()
+            .starts_with("text/html"));
+        let body = body_string(response).await;
+        assert!(body.contains(
+            r#"<meta http-equiv="refresh" content="5; url=https://target.com/?a=1&amp;b=2">"#
+        ));
+        assert!(body.contains("<code>https://target.com/?a=1&amp;b=2</code>"));
+    }
+
+    #[tokio::test]
+    async fn test_favicon() {
+        let app = app_router(Box::new(MockStore::new()), Config::default());
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/favicon.ico")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::NO_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn test_register_url_rejects_self_reference() {
+        let state = Arc::new(RwLock::new(AppState::default()));
+
+        let result =
+            register_url(State(state), register_request("http://example.com/abc123")).await;
+        assert_eq!(result.unwrap_err(), http::StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_register_url_rejects_self_reference_via_forwarded_host() {
+        let state = Arc::new(RwLock::new(AppState::default()));
+        let mut req = register_request("https://short.example/abc123");
+        req.headers_mut()
+            .insert("x-forwarded-host", "short.example".parse().unwrap());
+
+        let result = register_url(State(state), req).await;
+        assert_eq!(result.unwrap_err(), http::StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_register_url_too_long() {
+        let state = Arc::new(RwLock::new(AppState::default()));
+        let target = format!("https://target.com/{}", "a".repeat(2048));
+
+        let result = register_url(State(state), register_request(&target)).await;
+        assert_eq!(result.unwrap_err(), http::StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    /// Body that never ends and declares no length, like a slow-drip upload.
+    struct EndlessBody;
+
+    impl axum::body::HttpBody for EndlessBody {
+        type Data = Bytes;
+        type Error = std::convert::Infallible;
+
+        fn poll_frame(
+            self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<Option<Result<http_body::Frame<Bytes>, Self::Error>>> {
+            let chunk = Bytes::from_static(&[b'a'; 1024]);
+            std::task::Poll::Ready(Some(Ok(http_body::Frame::data(chunk))))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_read_body_stops_at_limit() {
+        let req = Request::builder()
+            .uri("http://example.com")
+            .body(axum::body::Body::new(EndlessBody))
+            .unwrap();
+
+        let err = read_body(req, 4096).await.unwrap_err();
+        assert!(err.downcast_ref::<PayloadTooLarge>().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_register_url_too_long_declared_length() {
+        let state = Arc::new(RwLock::new(AppState {
+            max_url_length: 16,
+            ..Default::default()
+        }));
+        let mut req = register_request("https://target.com/long");
+        req.headers_mut()
+            .insert("content-length", "23".parse().unwrap());
+
+        let result = register_url(State(state), req).await;
+        assert_eq!(result.unwrap_err(), http::StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn test_register_url_json_too_long() {
+        let state = Arc::new(RwLock::new(AppState {
+            max_url_length: 16,
+            ..Default::default()
+        }));
+        let req = json_register_request(r#"{"url": "https://target.com/long"}"#);
+
+        let result = register_url(State(state), req).await;
+        assert_eq!(result.unwrap_err(), http::StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn test_register_batch() {
+        let state = Arc::new(RwLock::new(AppState::default()));
+        let req = json_register_request(
+            r#"["https://a.com", "not-a-url", "javascript:alert(1)", "https://b.com"]"#,
+        );
+
+        let Json(items) = register_batch(State(state.clone()), req).await.unwrap();
+        assert_eq!(items.len(), 4);
+        assert_eq!(items[1].url, "not-a-url");
+        assert!(items[1].short_url.is_none() && items[1].error.is_some());
+        assert!(items[2].short_url.is_none() && items[2].error.is_some());
+
+        for (item, target) in [(&items[0], "https://a.com/"), (&items[3], "https://b.com/")] {
+            let short_url = item.short_url.as_deref().unwrap();
+            assert!(item.error.is_none());
+            let token = short_url.strip_prefix("https://example.com/").unwrap();
+            let resolved = state.read().await.store.resolve_token(token).await.unwrap();
+            assert_eq!(resolved.as_str(), target);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_register_batch_owned_by_api_key() {
+        let state = Arc::new(RwLock::new(AppState {
+            api_keys: HashSet::from(["alpha".to_string()]),
+            ..AppState::default()
+        }));
+        let mut req = json_register_request(r#"["https://a.com", "https://b.com"]"#);
+        req.headers_mut()
+            .insert("authorization", "Bearer alpha".parse().unwrap());
+
+        let Json(items) = register_batch(State(state.clone()), req).await.unwrap();
+        for item in items {
+            let short_url = item.short_url.unwrap();
+            let token = short_url.strip_prefix("https://example.com/").unwrap();
+            let owner = state.read().await.store.owner(token).await.unwrap();
+            assert_eq!(owner.as_deref(), Some("alpha"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_register_batch_dedup() {
+        let state = Arc::new(RwLock::new(AppState {
+            dedup: true,
+            ..AppState::new(Box::new(Store::default().with_dedup(true)))
+        }));
+        let req = json_register_request(r#"["https://a.com", "https://a.com"]"#);
+
+        let Json(items) = register_batch(State(state.clone()), req).await.unwrap();
+        assert!(items[0].short_url.is_some());
+        assert_eq!(items[0].short_url, items[1].short_url);
+        let metrics = state.read().await.metrics.render();
+        assert!(metrics.contains("urls_registered_total 1\n"), "{metrics}");
+    }
+
+    #[tokio::test]
+    async fn test_register_batch_rejects_non_array_body() {
+        let state = Arc::new(RwLock::new(AppState::default()));
+        let req = json_register_request(r#"{"url": "https://a.com"}"#);
+
+        let result = register_batch(State(state), req).await;
+        assert_eq!(result.unwrap_err(), http::StatusCode::BAD_REQUEST);
+    }
+
+    fn request_headers() -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("host", "example.com".parse().unwrap());
+        headers
+    }
+
+    #[tokio::test]
+    async fn test_link_qr() {
+        let mock_store =
+            MockStore::new().with_url("abc123", Url::parse("https://target.com").unwrap());
+        let state = Arc::new(RwLock::new(AppState::new(Box::new(mock_store))));
+
+        let response = link_qr(State(state), Path("abc123".to_string()), request_headers())
+            .await
+            .unwrap();
+        assert_eq!(response.headers().get("content-type").unwrap(), "image/png");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(!body.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_link_qr_svg() {
+        let app = app_router(
+            Box::new(
+                MockStore::new().with_url("abc123", Url::parse("https://target.com").unwrap()),
+            ),
+            Config::default(),
+        );
+        let svg = |uri: &'static str| {
+            let app = app.clone();
+            async move {
+                let request = Request::builder()
+                    .uri(uri)
+                    .header("host", "example.com")
+                    .body(axum::body::Body::empty())
+                    .unwrap();
+                let response = app.oneshot(request).await.unwrap();
+                assert_eq!(response.status(), http::StatusCode::OK);
+                assert_eq!(
+                    response.headers().get("content-type").unwrap(),
+                    "image/svg+xml"
+                );
+                body_string(response).await
+            }
+        };
+
+        let high = svg("/abc123/qr.svg?ecc=H&size=512").await;
+        assert!(high.starts_with("<svg"));
+        let expected = qr::render_svg("http://example.com/abc123", 512, qrcode::EcLevel::H);
+        assert_eq!(high, expected.unwrap());
+        let low = qr::render_svg("http://example.com/abc123", 512, qrcode::EcLevel::L);
+        assert_ne!(high, low.unwrap());
+
+        // Unusable values fall back to the defaults instead of failing
+        let fallback = svg("/abc123/qr.svg?ecc=Z&size=huge").await;
+        let expected = qr::render_svg(
+            "http://example.com/abc123",
+            qr::DEFAULT_SVG_SIZE,
+            qrcode::EcLevel::M,
+        );
+        assert_eq!(fallback, expected.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_link_qr_not_found() {
+        let state = Arc::new(RwLock::new(AppState::new(Box::new(MockStore::new()))));
+
+        let result = link_qr(State(state), Path("abc123".to_string()), request_headers()).await;
+        assert_eq!(result.unwrap_err(), http::StatusCode::NOT_FOUND);
+    }
+
+    /// The router `main` serves, over `store` alone.
+    fn app_router(store: Box<dyn AsyncStoreAccess>, config: Config) -> Router {
+        create_namespaced_router(store, HashMap::new(), config).0
+    }
+
+    fn keyed_router() -> Router {
+        app_router(
+            Box::new(MockStore::new()),
+            Config {
+                api_keys: HashSet::from(["secret".to_string()]),
+                ..Config::default()
+            },
+        )
+    }
+
+    fn authorized_register_request(authorization: Option<&str>) -> Request {
+        let mut builder = Request::builder()
+            .method("POST")
+            .uri("/")
+            .header("host", "example.com");
+        if let Some(authorization) = authorization {
+            builder = builder.header("authorization", authorization);
+        }
+        builder
+            .body(axum::body::Body::from("https://target.com"))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_register_url_with_valid_api_key() {
+        let response = keyed_router()
+            .oneshot(authorized_register_request(Some("Bearer secret")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn test_register_url_without_api_key() {
+        for authorization in [None, Some("Bearer wrong"), Some("secret")] {
+            let response = keyed_router()
+                .oneshot(authorized_register_request(authorization))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), http::StatusCode::UNAUTHORIZED);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shorten_needs_api_key() {
+        for (authorization, status) in [
+            (None, http::StatusCode::UNAUTHORIZED),
+            (Some("Bearer wrong"), http::StatusCode::UNAUTHORIZED),
+            (Some("Bearer secret"), http::StatusCode::CREATED),
+        ] {
+            let mut request = authorized_register_request(authorization);
+            *request.uri_mut() = "/shorten".parse().unwrap();
+            let response = keyed_router().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), status);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_register_url_enforces_link_quota() {
+        let app = app_router(
+            Box::new(Store::default()),
+            Config {
+                api_keys: HashSet::from(["secret".to_string(), "other".to_string()]),
+                link_quota: Some(2),
+                ..Config::default()
+            },
+        );
+
+        for _ in 0..2 {
+            let response = app
+                .clone()
+                .oneshot(authorized_register_request(Some("Bearer secret")))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), http::StatusCode::CREATED);
+        }
+        let response = app
+            .clone()
+            .oneshot(authorized_register_request(Some("Bearer secret")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::TOO_MANY_REQUESTS);
+
+        // Quotas are per key
+        let response = app
+            .clone()
+            .oneshot(authorized_register_request(Some("Bearer other")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::CREATED);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/admin/usage")
+                    .header("authorization", "Bearer secret")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = json_body(response).await;
+        assert_eq!(body["quota"], 2);
+        assert_eq!(body["links"]["secret"], 2);
+        assert_eq!(body["links"]["other"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_list_own_links() {
+        let app = app_router(
+            Box::new(Store::default()),
+            Config {
+                api_keys: HashSet::from(["secret".to_string(), "other".to_string()]),
+                ..Config::default()
+            },
+        );
+        let list = |key: &'static str| {
+            let app = app.clone();
+            async move {
+                let response = app
+                    .oneshot(
+                        Request::builder()
+                            .uri("/links")
+                            .header("authorization", format!("Bearer {key}"))
+                            .body(axum::body::Body::empty())
+                            .unwrap(),
+                    )
+                    .await
+                    .unwrap();
+                assert_eq!(response.status(), http::StatusCode::OK);
+                let mut tokens: Vec<String> = json_body(response)
+                    .await
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|link| link["token"].as_str().unwrap().to_string())
+                    .collect();
+                tokens.sort();
+                tokens
+            }
+        };
+
+        let mut registered = HashMap::from([("secret", Vec::new()), ("other", Vec::new())]);
+        for (key, count) in [("secret", 2), ("other", 1)] {
+            for _ in 0..count {
+                let response = app
+                    .clone()
+                    .oneshot(authorized_register_request(Some(&format!("Bearer {key}"))))
+                    .await
+                    .unwrap();
+                assert_eq!(response.status(), http::StatusCode::CREATED);
+                let body = json_body(response).await;
+                let token = body["token"].as_str().unwrap().to_string();
+                registered.get_mut(key).unwrap().push(token);
+            }
+        }
+        for (key, mut tokens) in registered {
+            tokens.sort();
+            assert_eq!(list(key).await, tokens);
+        }
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/links")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_store_stats() {
+        let state = Arc::new(RwLock::new(AppState::default()));
+        let tokens = {
+            let mut state = state.write().await;
+            let mut tokens = Vec::new();
+            for url in ["https://a.com", "https://b.com"] {
+                let url = Url::parse(url).unwrap();
+                tokens.push(state.store.register_url(url).await.unwrap());
+            }
+            let url = Url::parse("https://expired.com").unwrap();
+            state
+                .store
+                .register_url_with_ttl(url, Duration::ZERO)
+                .await
+                .unwrap();
+            tokens
+        };
+        for token in [&tokens[0], &tokens[0], &tokens[1]] {
+            resolve_url(
+                State(state.clone()),
+                Path(token.to_string()),
+                Query(ResolveParams::default()),
+                HeaderMap::new(),
+            )
+            .await
+            .unwrap();
+        }
+
+        let Json(stats) = store_stats(State(state)).await.unwrap();
+        assert_eq!(stats.total_links, 3);
+        assert_eq!(stats.total_redirects, 3);
+        assert_eq!(stats.expired_count, 1);
+    }
+
+    #[tokio::test]
+    async fn test_top_links() {
+        let state = Arc::new(RwLock::new(AppState::default()));
+        let tokens = {
+            let mut state = state.write().await;
+            let mut tokens = Vec::new();
+            for url in ["https://a.com", "https://b.com", "https://c.com"] {
+                let url = Url::parse(url).unwrap();
+                tokens.push(state.store.register_url(url).await.unwrap());
+            }
+            tokens
+        };
+        for (token, hits) in tokens.iter().zip([1, 3, 2]) {
+            for _ in 0..hits {
+                resolve_url(
+                    State(state.clone()),
+                    Path(token.to_string()),
+                    Query(ResolveParams::default()),
+                    HeaderMap::new(),
+                )
+                .await
+                .unwrap();
+            }
+        }
+
+        let Json(top) = top_links(State(state.clone()), Query(TopParams { limit: 2 }))
+            .await
+            .unwrap();
+        let top: Vec<_> = top
+            .iter()
+            .map(|link| (link.url.as_str(), link.hits))
+            .collect();
+        assert_eq!(top, [("https://b.com/", 3), ("https://c.com/", 2)]);
+
+        let Json(top) = top_links(
+            State(state),
+            Query(TopParams {
+                limit: TopParams::DEFAULT_LIMIT,
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(top.len(), 3);
+        assert_eq!(top[2].token, tokens[0].to_string());
+    }
+
+    #[tokio::test]
+    async fn test_top_links_needs_admin_key() {
+        let app = admin_router(Box::new(Store::default()));
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/admin/top")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_resolve_url_needs_no_api_key() {
+        let app = app_router(
+            Box::new(
+                MockStore::new().with_url("abc123", Url::parse("https://target.com").unwrap()),
+            ),
+            Config {
+                api_keys: HashSet::from(["secret".to_string()]),
+                ..Config::default()
+            },
+        );
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/abc123")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert!(response.status().is_redirection());
+    }
+
+    #[tokio::test]
+    async fn test_register_url_normalizes_target() {
+        let state = Arc::new(RwLock::new(AppState {
+            normalization: Normalization {
+                strip_trailing_slash: true,
+                sort_query: true,
+            },
+            ..Default::default()
+        }));
+
+        let mut stored = Vec::new();
+        for target in [
+            "https://Target.com/docs/?b=1&a=2",
+            "https://target.com:443/docs?a=2&b=1",
+        ] {
+            let response = register_url(State(state.clone()), register_request(target))
+                .await
+                .unwrap();
+            let token = json_body(response).await["token"]
+                .as_str()
+                .unwrap()
+                .to_string();
+            stored.push(
+                state
+                    .read()
+                    .await
+                    .store
+                    .resolve_token(&token)
+                    .await
+                    .unwrap(),
+            );
+        }
+
+        assert_eq!(stored[0], stored[1]);
+        assert_eq!(stored[0].as_str(), "https://target.com/docs?a=2&b=1");
+    }
 
-impl Default for AppState {
-    fn default() -> Self {
-        Self {
-            store: Box::new(Store::default()),
+    fn list_request(query: &str, authorization: Option<&str>) -> Request {
+        let mut builder = Request::builder().uri(format!("/admin/links{query}"));
+        if let Some(authorization) = authorization {
+            builder = builder.header("authorization", authorization);
         }
+        builder.body(axum::body::Body::empty()).unwrap()
     }
-}
 
-// Helpers
-fn extract_base_url(req: &Request) -> Result<Url> {
-    let headers = req.headers();
+    fn list_router(store: MockStore) -> Router {
+        app_router(
+            Box::new(store),
+            Config {
+                api_keys: HashSet::from(["secret".to_string()]),
+                ..Config::default()
+            },
+        )
+    }
 
-    // Check for forwarded protocol (https/http)
-    let proto = headers
-        .get("x-forwarded-proto")
-        .and_then(|h| h.to_str().ok())
-        .unwrap_or("http");
+    #[tokio::test]
+    async fn test_list_links() {
+        let store = ["aaa111", "bbb222", "ccc333"]
+            .into_iter()
+            .fold(MockStore::new(), |store, token| {
+                store.with_url(token, Url::parse("https://target.com").unwrap())
+            });
+        let app = list_router(store);
 
-    // Check for forwarded host
-    let host = headers
-        .get("x-forwarded-host")
-        .or_else(|| headers.get("host"))
-        .and_then(|h| h.to_str().ok())
-        .unwrap_or("localhost");
+        let response = app
+            .clone()
+            .oneshot(list_request("?offset=1&limit=1", Some("Bearer secret")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::OK);
+        assert_eq!(
+            json_body(response).await,
+            serde_json::json!([
+                {"token": "bbb222", "url": "https://target.com/", "created_at": "1970-01-01T00:00:00Z"}
+            ])
+        );
 
-    Url::parse(&format!("{}://{}", proto, host))
-        .map_err(|e| eyre!("Failed to parse base URL: {}", e))
-}
+        let response = app
+            .oneshot(list_request("?offset=3", Some("Bearer secret")))
+            .await
+            .unwrap();
+        assert_eq!(json_body(response).await, serde_json::json!([]));
+    }
 
-// Routes
-async fn extract_body_url(req: Request) -> Result<Url> {
-    let body = axum::body::to_bytes(req.into_body(), usize::MAX).await?;
-    let str = std::str::from_utf8(&body)?;
-    Url::parse(str).map_err(|e| eyre!("Failed to parse URL: {}", e))
-}
+    #[tokio::test]
+    async fn test_clear_links() {
+        let app = admin_router(Box::new(Store::default()));
+        let mut tokens = Vec::new();
+        for i in 0..3 {
+            let response = app
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .method("POST")
+                        .uri("/")
+                        .header("host", "example.com")
+                        .header("authorization", "Bearer secret")
+                        .body(axum::body::Body::from(format!("https://example{i}.com")))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            let body = json_body(response).await;
+            tokens.push(body["token"].as_str().unwrap().to_string());
+        }
 
-async fn resolve_url(
-    State(state): State<Arc<Mutex<AppState>>>,
-    Path(token): Path<String>,
-) -> Result<Redirect, http::StatusCode> {
-    let state = state.lock().map_err(|_| http::StatusCode::LOCKED)?;
-    let url = state
-        .store
-        .resolve_token(&token)
-        .map_err(|_| http::StatusCode::NOT_FOUND)
-        .map(|u| u.to_string())?;
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("DELETE")
+                    .uri("/admin/links")
+                    .header("authorization", "Bearer secret")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::OK);
+        assert_eq!(json_body(response).await, serde_json::json!({"deleted": 3}));
 
-    Ok(Redirect::to(&url))
-}
+        let response = app
+            .clone()
+            .oneshot(list_request("", Some("Bearer secret")))
+            .await
+            .unwrap();
+        assert_eq!(json_body(response).await, serde_json::json!([]));
+        for token in tokens {
+            let response = app
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .uri(format!("/{token}"))
+                        .body(axum::body::Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), http::StatusCode::NOT_FOUND);
+        }
+    }
 
-async fn register_url(
-    State(state): State<Arc<Mutex<AppState>>>,
-    req: Request,
-) -> Result<String, http::StatusCode> {
-    let base_url = extract_base_url(&req).map_err(|_| http::StatusCode::INTERNAL_SERVER_ERROR)?;
-    let target_url = extract_body_url(req)
-        .await
-        .map_err(|_| http::StatusCode::BAD_REQUEST)?;
+    #[tokio::test]
+    async fn test_delete_matching_links() {
+        let mut store = Store::default();
+        for alias in ["promo-a", "promo-b", "keep"] {
+            let url = Url::parse(&format!("https://{alias}.com")).unwrap();
+            StoreAccess::register_url_with_alias(&mut store, url, alias).unwrap();
+        }
+        let app = admin_router(Box::new(store));
+        let delete = |query: String| {
+            Request::builder()
+                .method("DELETE")
+                .uri(format!("/admin/links/matching{query}"))
+                .header("authorization", "Bearer secret")
+                .body(axum::body::Body::empty())
+                .unwrap()
+        };
 
-    let token = {
-        let mut state = state.lock().map_err(|_| http::StatusCode::LOCKED)?;
-        state
-            .store
-            .register_url(target_url)
-            .map_err(|_| http::StatusCode::INTERNAL_SERVER_ERROR)?
-    };
+        let response = app
+            .clone()
+            .oneshot(delete("?prefix=promo-".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(json_body(response).await, serde_json::json!({"deleted": 2}));
 
-    let resolved = base_url
-        .join(token.as_str())
-        .map_err(|_| http::StatusCode::INTERNAL_SERVER_ERROR)?;
-    Ok(resolved.to_string())
-}
+        let later = SystemTime::now() + Duration::from_secs(60);
+        let cutoff = later.duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let response = app
+            .clone()
+            .oneshot(delete(format!("?created_before={cutoff}")))
+            .await
+            .unwrap();
+        assert_eq!(json_body(response).await, serde_json::json!({"deleted": 1}));
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::token::Token;
-    use axum::http::HeaderMap;
-    use axum::response::IntoResponse;
-    use std::collections::HashMap;
-    use std::str::FromStr;
-    use std::sync::Mutex;
+        let response = app.oneshot(delete(String::new())).await.unwrap();
+        assert_eq!(response.status(), http::StatusCode::BAD_REQUEST);
+    }
 
-    // Mock store implementation
-    struct MockStore {
-        urls: Mutex<HashMap<String, Url>>,
+    #[tokio::test]
+    async fn test_clear_links_requires_api_key() {
+        let response = list_router(MockStore::new())
+            .oneshot(
+                Request::builder()
+                    .method("DELETE")
+                    .uri("/admin/links")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::UNAUTHORIZED);
     }
 
-    impl MockStore {
-        fn new() -> Self {
-            Self {
-                urls: Mutex::new(HashMap::new()),
-            }
+    #[tokio::test]
+    async fn test_list_links_empty_store() {
+        let response = list_router(MockStore::new())
+            .oneshot(list_request("", Some("Bearer secret")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::OK);
+        assert_eq!(json_body(response).await, serde_json::json!([]));
+    }
+
+    #[tokio::test]
+    async fn test_list_links_requires_api_key() {
+        let response = list_router(MockStore::new())
+            .oneshot(list_request("", None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::UNAUTHORIZED);
+
+        // Unlike registration, listing stays closed when no keys are configured
+        let response = app_router(Box::new(MockStore::new()), Config::default())
+            .oneshot(list_request("", None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::UNAUTHORIZED);
+    }
+
+    fn admin_router(store: Box<dyn AsyncStoreAccess>) -> Router {
+        let config = Config {
+            api_keys: HashSet::from(["secret".to_string()]),
+            ..Config::default()
+        };
+        app_router(store, config)
+    }
+
+    fn import_request(body: String) -> Request {
+        Request::builder()
+            .method("POST")
+            .uri("/admin/import")
+            .header("authorization", "Bearer secret")
+            .body(axum::body::Body::from(body))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_export_import_round_trip() {
+        let mut store: Box<dyn AsyncStoreAccess> = Box::new(Store::default());
+        let mut tokens = vec![store
+            .register_url_with_alias(Url::parse("https://alias.com").unwrap(), "launch")
+            .await
+            .unwrap()];
+        for i in 0..3 {
+            let url = Url::parse(&format!("https://example{i}.com")).unwrap();
+            tokens.push(store.register_url(url).await.unwrap());
         }
 
-        fn with_url(self, token: &str, url: Url) -> Self {
-            self.urls.lock().unwrap().insert(token.to_string(), url);
-            self
+        let response = admin_router(store)
+            .oneshot(
+                Request::builder()
+                    .uri("/admin/export")
+                    .header("authorization", "Bearer secret")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::OK);
+        assert_eq!(
+            response.headers()[http::header::CONTENT_TYPE],
+            NDJSON_CONTENT_TYPE
+        );
+        let export = body_string(response).await;
+        assert_eq!(export.lines().count(), tokens.len());
+
+        // Import into an empty store, then every token should resolve again
+        let app = admin_router(Box::new(Store::default()));
+        let response = app.clone().oneshot(import_request(export)).await.unwrap();
+        assert_eq!(response.status(), http::StatusCode::OK);
+        assert_eq!(
+            json_body(response).await,
+            serde_json::json!({"imported": 4, "skipped": []})
+        );
+
+        for token in &tokens {
+            let response = app
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .uri(format!("/{token}"))
+                        .body(axum::body::Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert!(response.status().is_redirection(), "{token}");
         }
     }
 
-    impl StoreAccess for MockStore {
-        fn register_url(&mut self, url: Url) -> Result<Token> {
-            let token = Token::default();
-            self.urls
-                .lock()
-                .unwrap()
-                .insert(token.as_str().to_string(), url);
-            Ok(token)
-        }
+    #[tokio::test]
+    async fn test_import_reports_collisions() {
+        let mut store: Box<dyn AsyncStoreAccess> = Box::new(Store::default());
+        store
+            .register_url_with_alias(Url::parse("https://original.com").unwrap(), "launch")
+            .await
+            .unwrap();
+        let app = admin_router(store);
 
-        fn resolve_token(&self, token: &str) -> Result<Url> {
-            self.urls
-                .lock()
+        let body = concat!(
+            r#"{"token":"launch","url":"https://other.com/"}"#,
+            "\n\n",
+            r#"{"token":"fresh1","url":"https://fresh.com/"}"#,
+            "\n",
+        );
+        let response = app.oneshot(import_request(body.to_string())).await.unwrap();
+        let body = json_body(response).await;
+        assert_eq!(body["imported"], 1);
+        assert_eq!(body["skipped"][0]["token"], "launch");
+    }
+
+    #[tokio::test]
+    async fn test_import_gzipped() {
+        let body: String = (0..50)
+            .map(|i| format!("{{\"token\":\"link{i}\",\"url\":\"https://site{i}.com/\"}}\n"))
+            .collect();
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(body.as_bytes()).unwrap();
+        let gzipped = encoder.finish().unwrap();
+        let request = |encoding: &str| {
+            Request::builder()
+                .method("POST")
+                .uri("/admin/import")
+                .header("authorization", "Bearer secret")
+                .header("content-encoding", encoding)
+                .body(axum::body::Body::from(gzipped.clone()))
                 .unwrap()
-                .get(token)
-                .cloned()
-                .ok_or_else(|| eyre!("Token not found"))
+        };
+        let state = Arc::new(RwLock::new(AppState {
+            api_keys: HashSet::from(["secret".to_string()]),
+            ..AppState::default()
+        }));
+        let app = routes(state.clone(), cors_layer(&[]));
+
+        let response = app.clone().oneshot(request("br")).await.unwrap();
+        assert_eq!(response.status(), http::StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        let response = app.oneshot(request("gzip")).await.unwrap();
+        let body = json_body(response).await;
+        assert_eq!(body["imported"], 50);
+        assert_eq!(body["skipped"], serde_json::json!([]));
+        let state = state.read().await;
+        for i in 0..50 {
+            let record = state.store.link_info(&format!("link{i}")).await.unwrap();
+            assert_eq!(record.url.as_str(), format!("https://site{i}.com/"));
         }
     }
 
-    #[test]
-    fn test_extract_base_url() {
-        let mut headers = HeaderMap::new();
-        headers.insert("x-forwarded-proto", "https".parse().unwrap());
-        headers.insert("x-forwarded-host", "example.com".parse().unwrap());
-
-        let mut req = Request::builder()
-            .uri("http://example.com")
-            .body(axum::body::Body::empty())
+    #[tokio::test]
+    async fn test_import_rejects_malformed_lines() {
+        let response = admin_router(Box::new(Store::default()))
+            .oneshot(import_request("not json\n".to_string()))
+            .await
             .unwrap();
-        req.headers_mut().extend(headers);
+        assert_eq!(response.status(), http::StatusCode::BAD_REQUEST);
+    }
 
-        let result = extract_base_url(&req).unwrap();
-        assert_eq!(result.scheme(), "https");
-        assert_eq!(result.host_str().unwrap(), "example.com");
+    #[tokio::test]
+    async fn test_export_requires_api_key() {
+        let response = admin_router(Box::new(Store::default()))
+            .oneshot(
+                Request::builder()
+                    .uri("/admin/export")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::UNAUTHORIZED);
     }
 
-    #[test]
-    fn test_extract_base_url_fallback() {
-        let req = Request::builder()
-            .uri("http://localhost:3000")
-            .body(axum::body::Body::empty())
+    #[tokio::test]
+    async fn test_metrics_count_registrations_and_redirects() {
+        let app = app_router(Box::new(MockStore::new()), Config::default());
+        let metric = |body: &str, name: &str| -> u64 {
+            body.lines()
+                .find_map(|line| line.strip_prefix(&format!("{name} ")))
+                .unwrap()
+                .parse()
+                .unwrap()
+        };
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/")
+                    .header("host", "example.com")
+                    .body(axum::body::Body::from("https://target.com"))
+                    .unwrap(),
+            )
+            .await
             .unwrap();
+        let token = json_body(response).await["token"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        for uri in [format!("/{token}"), "/unknown".to_string()] {
+            app.clone()
+                .oneshot(
+                    Request::builder()
+                        .uri(uri)
+                        .body(axum::body::Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+        }
 
-        let result = extract_base_url(&req).unwrap();
-        assert_eq!(result.scheme(), "http");
-        assert_eq!(result.host_str().unwrap(), "localhost");
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/metrics")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            response.headers().get("content-type").unwrap(),
+            Metrics::CONTENT_TYPE
+        );
+        let body = body_string(response).await;
+        assert_eq!(metric(&body, "urls_registered_total"), 1);
+        assert_eq!(metric(&body, "redirects_total"), 1);
+        assert_eq!(metric(&body, "redirects_not_found_total"), 1);
     }
 
     #[tokio::test]
-    async fn test_extract_body_url() {
-        let url = "https://example.com";
-        let req = Request::builder()
-            .uri("http://localhost:3000")
-            .body(axum::body::Body::from(url))
+    async fn test_resolve_url_records_click() {
+        let app = app_router(
+            Box::new(Store::default()),
+            Config {
+                record_clicks: true,
+                api_keys: HashSet::from(["secret".to_string()]),
+                ..Config::default()
+            },
+        );
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/")
+                    .header("host", "example.com")
+                    .header("authorization", "Bearer secret")
+                    .body(axum::body::Body::from("https://target.com"))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let token = json_body(response).await["token"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        app.clone()
+            .oneshot(
+                Request::builder()
+                    .uri(format!("/{token}"))
+                    .header("user-agent", "curl/8.0")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
             .unwrap();
 
-        let result = extract_body_url(req).await.unwrap();
-        assert_eq!(result.to_string(), "https://example.com/");
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri(format!("/{token}/clicks"))
+                    .header("authorization", "Bearer secret")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::OK);
+        let clicks = json_body(response).await;
+        assert_eq!(clicks.as_array().unwrap().len(), 1);
+        assert_eq!(clicks[0]["user_agent"], "curl/8.0");
+        assert_eq!(clicks[0]["referer"], serde_json::Value::Null);
     }
 
     #[tokio::test]
-    async fn test_resolve_url() {
-        let state = Arc::new(Mutex::new(AppState::default()));
-        let token = {
-            let mut state_guard = state.lock().unwrap();
-            state_guard
-                .store
-                .register_url(Url::from_str("https://example.com").unwrap())
+    async fn test_resolve_url_with_trailing_slash() {
+        let app = app_router(
+            Box::new(
+                MockStore::new().with_url("abc123", Url::parse("https://target.com").unwrap()),
+            ),
+            Config::default(),
+        );
+
+        let status = |uri: &'static str| {
+            let app = app.clone();
+            async move {
+                app.oneshot(
+                    Request::builder()
+                        .uri(uri)
+                        .body(axum::body::Body::empty())
+                        .unwrap(),
+                )
+                .await
                 .unwrap()
+                .status()
+            }
         };
-
-        let result = resolve_url(State(state), Path(token.as_str().to_string())).await;
-        assert!(result.is_ok());
+        assert_eq!(status("/abc123/").await, http::StatusCode::SEE_OTHER);
+        assert_eq!(status("/abc123//").await, http::StatusCode::NOT_FOUND);
     }
 
     #[tokio::test]
-    async fn test_register_url() {
-        let state = Arc::new(Mutex::new(AppState::default()));
-        let mut headers = HeaderMap::new();
-        headers.insert("x-forwarded-proto", "https".parse().unwrap());
-        headers.insert("x-forwarded-host", "example.com".parse().unwrap());
-
-        let mut req = Request::builder()
+    async fn test_register_url_with_base_url_override() {
+        let state = Arc::new(RwLock::new(AppState {
+            base_url: Some(Url::parse("https://sho.rt/s/").unwrap()),
+            ..Default::default()
+        }));
+        let req = Request::builder()
             .uri("http://example.com")
+            .header("host", "localhost")
+            .header("x-forwarded-host", "internal.proxy")
             .body(axum::body::Body::from("https://target.com"))
             .unwrap();
-        req.headers_mut().extend(headers);
 
-        let result = register_url(State(state), req).await;
-        assert!(result.is_ok());
-        let short_url = result.unwrap();
-        assert!(short_url.starts_with("https://example.com/"));
+        let response = register_url(State(state), req).await.unwrap();
+        let body = json_body(response).await;
+        let token = body["token"].as_str().unwrap();
+        assert_eq!(body["short_url"], format!("https://sho.rt/s/{token}"));
     }
 
     #[tokio::test]
-    async fn test_resolve_url_with_mock_store() {
-        let mock_store =
-            MockStore::new().with_url("abc123", Url::parse("https://example.com").unwrap());
-        let state = Arc::new(Mutex::new(AppState {
-            store: Box::new(mock_store),
-        }));
+    async fn test_forwarded_headers_need_trusted_proxy() {
+        for (trusted_proxy, short_url_prefix) in [
+            (true, "https://spoofed.example/"),
+            (false, "http://example.com/"),
+        ] {
+            let app = app_router(
+                Box::new(Store::default()),
+                Config {
+                    trusted_proxy,
+                    ..Config::default()
+                },
+            );
+            let response = app
+                .oneshot(
+                    Request::builder()
+                        .method("POST")
+                        .uri("/")
+                        .header("host", "example.com")
+                        .header("x-forwarded-proto", "https")
+                        .header("x-forwarded-host", "spoofed.example")
+                        .body(axum::body::Body::from("https://target.com"))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
 
-        let result = resolve_url(State(state), Path("abc123".to_string())).await;
-        assert!(result.is_ok());
-        let redirect = result.unwrap();
-        let response = redirect.into_response();
-        let headers = response.headers();
-        assert_eq!(headers.get("location").unwrap(), "https://example.com/");
+            assert_eq!(response.status(), http::StatusCode::CREATED);
+            let body = json_body(response).await;
+            let short_url = body["short_url"].as_str().unwrap();
+            assert!(short_url.starts_with(short_url_prefix), "{short_url}");
+        }
     }
 
     #[tokio::test]
-    async fn test_resolve_url_not_found() {
-        let mock_store = MockStore::new();
-        let state = Arc::new(Mutex::new(AppState {
-            store: Box::new(mock_store),
-        }));
+    async fn test_invalid_url_error_body() {
+        let response = app_router(Box::new(MockStore::new()), Config::default())
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/")
+                    .header("host", "example.com")
+                    .body(axum::body::Body::from("not-a-url"))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
 
-        let result = resolve_url(State(state), Path("nonexistent".to_string())).await;
-        assert!(result.is_err());
-        assert_eq!(result.unwrap_err(), http::StatusCode::NOT_FOUND);
+        assert_eq!(response.status(), http::StatusCode::BAD_REQUEST);
+        let body = json_body(response).await;
+        assert!(body["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("Failed to parse URL"));
+    }
+
+    fn update_request(target: &str) -> Request {
+        Request::builder()
+            .method("PUT")
+            .uri("http://example.com")
+            .header("host", "example.com")
+            .body(axum::body::Body::from(target.to_string()))
+            .unwrap()
     }
 
     #[tokio::test]
-    async fn test_register_url_with_mock_store() {
-        let mock_store = MockStore::new();
-        let state = Arc::new(Mutex::new(AppState {
-            store: Box::new(mock_store),
-        }));
+    async fn test_update_url() {
+        let mock_store =
+            MockStore::new().with_url("abc123", Url::parse("https://target.com").unwrap());
+        let state = Arc::new(RwLock::new(AppState::new(Box::new(mock_store))));
 
-        let mut headers = HeaderMap::new();
-        headers.insert("x-forwarded-proto", "https".parse().unwrap());
-        headers.insert("x-forwarded-host", "example.com".parse().unwrap());
+        let result = update_url(
+            State(state.clone()),
+            Path("abc123".to_string()),
+            update_request("https://new-target.com"),
+        )
+        .await;
+        assert_eq!(result.unwrap(), http::StatusCode::NO_CONTENT);
 
-        let mut req = Request::builder()
+        let redirect = resolve_url(
+            State(state),
+            Path("abc123".to_string()),
+            Query(ResolveParams::default()),
+            HeaderMap::new(),
+        )
+        .await
+        .unwrap()
+        .into_response();
+        assert_eq!(
+            redirect.headers().get("location").unwrap(),
+            "https://new-target.com/"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_update_url_with_json_body() {
+        let mock_store =
+            MockStore::new().with_url("abc123", Url::parse("https://target.com").unwrap());
+        let state = Arc::new(RwLock::new(AppState::new(Box::new(mock_store))));
+
+        let req = Request::builder()
+            .method("PUT")
             .uri("http://example.com")
-            .body(axum::body::Body::from("https://target.com"))
+            .header("host", "example.com")
+            .header("content-type", "application/json")
+            .body(axum::body::Body::from(
+                r#"{"url": "https://new-target.com"}"#,
+            ))
             .unwrap();
-        req.headers_mut().extend(headers);
+        let result = update_url(State(state.clone()), Path("abc123".to_string()), req).await;
+        assert_eq!(result.unwrap(), http::StatusCode::NO_CONTENT);
 
-        let result = register_url(State(state), req).await;
-        assert!(result.is_ok());
-        let short_url = result.unwrap();
-        assert!(short_url.starts_with("https://example.com/"));
+        let info = state.read().await.store.link_info("abc123").await.unwrap();
+        assert_eq!(info.url.as_str(), "https://new-target.com/");
     }
 
     #[tokio::test]
-    async fn test_register_url_invalid_url() {
-        let mock_store = MockStore::new();
-        let state = Arc::new(Mutex::new(AppState {
-            store: Box::new(mock_store),
-        }));
+    async fn test_update_url_not_found() {
+        let state = Arc::new(RwLock::new(AppState::new(Box::new(MockStore::new()))));
 
-        let mut headers = HeaderMap::new();
-        headers.insert("x-forwarded-proto", "https".parse().unwrap());
-        headers.insert("x-forwarded-host", "example.com".parse().unwrap());
+        let result = update_url(
+            State(state),
+            Path("abc123".to_string()),
+            update_request("https://new-target.com"),
+        )
+        .await;
+        assert_eq!(result.unwrap_err(), http::StatusCode::NOT_FOUND);
+    }
 
-        let mut req = Request::builder()
-            .uri("http://example.com")
-            .body(axum::body::Body::from("not-a-url"))
+    #[tokio::test]
+    async fn test_update_url_of_another_key() {
+        let mut state = AppState {
+            api_keys: HashSet::from(["alpha".to_string(), "beta".to_string()]),
+            ..AppState::default()
+        };
+        let token = state
+            .store
+            .register_url(Url::parse("https://target.com").unwrap())
+            .await
             .unwrap();
-        req.headers_mut().extend(headers);
+        state
+            .store
+            .set_owner(token.as_str(), Some("alpha".to_string()))
+            .await
+            .unwrap();
+        let state = Arc::new(RwLock::new(state));
+        let update = |key: &str| {
+            let mut req = update_request("https://new-target.com");
+            req.headers_mut()
+                .insert("authorization", format!("Bearer {key}").parse().unwrap());
+            update_url(State(state.clone()), Path(token.to_string()), req)
+        };
 
-        let result = register_url(State(state), req).await;
-        assert!(result.is_err());
-        assert_eq!(result.unwrap_err(), http::StatusCode::BAD_REQUEST);
+        assert_eq!(
+            update("beta").await.unwrap_err(),
+            http::StatusCode::FORBIDDEN
+        );
+        let info = state
+            .read()
+            .await
+            .store
+            .link_info(token.as_str())
+            .await
+            .unwrap();
+        assert_eq!(info.url.as_str(), "https://target.com/");
+        assert_eq!(update("alpha").await.unwrap(), http::StatusCode::NO_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn test_update_url_requires_api_key() {
+        let response = keyed_router()
+            .oneshot(
+                Request::builder()
+                    .method("PUT")
+                    .uri("/abc123")
+                    .body(axum::body::Body::from("https://new-target.com"))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_delete_url_requires_api_key() {
+        let response = keyed_router()
+            .oneshot(
+                Request::builder()
+                    .method("DELETE")
+                    .uri("/abc123")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_restore_url_requires_api_key() {
+        let response = keyed_router()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/abc123/restore")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_resolve_password_protected_url() {
+        let state = Arc::new(RwLock::new(AppState::default()));
+        let req = json_register_request(r#"{"url": "https://target.com", "password": "hunter2"}"#);
+        let response = register_url(State(state.clone()), req).await.unwrap();
+        let token = json_body(response).await["token"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        let resolve = |pw: Option<&str>, header: Option<&str>| {
+            let mut headers = HeaderMap::new();
+            if let Some(header) = header {
+                headers.insert(PASSWORD_HEADER, header.parse().unwrap());
+            }
+            resolve_url(
+                State(state.clone()),
+                Path(token.clone()),
+                Query(ResolveParams {
+                    pw: pw.map(String::from),
+                }),
+                headers,
+            )
+        };
+        assert_eq!(
+            resolve(None, None).await.unwrap_err(),
+            http::StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            resolve(Some("wrong"), None).await.unwrap_err(),
+            http::StatusCode::UNAUTHORIZED
+        );
+        assert!(resolve(Some("hunter2"), None).await.is_ok());
+        assert!(resolve(None, Some("hunter2")).await.is_ok());
+        assert_eq!(state.read().await.store.hit_count(&token).await.unwrap(), 2);
     }
 }
@@ -0,0 +1,119 @@
+//! JSON file format of stored links, used to save the in-memory store on shutdown
+//! and by `JsonFileStore` on every change.
+//!
+//! Click logs are left out; they are capped and only of interest while the process runs.
+
+use crate::store::{LinkRecord, Store};
+use crate::token::{Token, TokenConfig};
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+/// On-disk form of one link, its token next to the fields of its `LinkRecord`.
+#[derive(Debug, Serialize, Deserialize)]
+struct SavedLink {
+    token: Token,
+    #[serde(flatten)]
+    record: LinkRecord,
+}
+
+/// Writes `json`, as made by `to_json`, to `path`, replacing the file in one step so a
+/// crash never leaves half of it.
+pub fn save(path: &Path, json: &[u8]) -> Result<()> {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".tmp");
+    fs::write(&partial, json)
+        .wrap_err_with(|| format!("Failed to write {}", Path::new(&partial).display()))?;
+    fs::rename(&partial, path).wrap_err_with(|| format!("Failed to replace {}", path.display()))
+}
+
+/// Reads the store `save` wrote; a missing file is an empty store.
+pub fn load(path: &Path, token_config: TokenConfig) -> Result<Store> {
+    let json = match fs::read(path) {
+        Ok(json) => json,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Store::new(token_config)),
+        Err(e) => return Err(eyre!("Failed to read {}: {e}", path.display())),
+    };
+    Store::from_json(&json, token_config)
+        .wrap_err_with(|| format!("Invalid snapshot in {}", path.display()))
+}
+
+/// The file contents `save` writes.
+pub fn to_json(links: &[(Token, LinkRecord)]) -> Result<Vec<u8>> {
+    let saved: Vec<SavedLink> = links
+        .iter()
+        .map(|(token, record)| SavedLink {
+            token: token.clone(),
+            record: record.clone(),
+        })
+        .collect();
+    Ok(serde_json::to_vec(&saved)?)
+}
+
+/// Parses what `to_json` wrote.
+pub fn from_json(json: &[u8]) -> Result<Vec<(Token, LinkRecord)>> {
+    let saved: Vec<SavedLink> = serde_json::from_slice(json)?;
+    Ok(saved
+        .into_iter()
+        .map(|link| (link.token, link.record))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::StoreAccess;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::time::{Duration, SystemTime};
+    use url::Url;
+
+    fn snapshot_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("shortener-{}-{name}.json", std::process::id()))
+    }
+
+    #[test]
+    fn test_save_load_round_trip() -> Result<()> {
+        let path = snapshot_path("round-trip");
+        let record = LinkRecord {
+            url: Url::parse("https://example.com")?,
+            created_at: SystemTime::UNIX_EPOCH + Duration::from_secs(1_000),
+            expires_at: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(2_000)),
+            hits: AtomicU64::new(7),
+            password_hash: Some("$argon2id$hash".to_string()),
+            deleted: true,
+            owner: Some("alpha".to_string()),
+            max_hits: Some(10),
+            destinations: vec![(Url::parse("https://b.example.com")?, 2)],
+            utm_params: vec![("utm_source".to_string(), "news".to_string())],
+        };
+        save(
+            &path,
+            &to_json(&[(Token::from_alias("launch")?, record.clone())])?,
+        )?;
+
+        let loaded = load(&path, TokenConfig::default())?.list(0, usize::MAX)?;
+        fs::remove_file(&path)?;
+        assert_eq!(loaded.len(), 1);
+        let (token, loaded) = &loaded[0];
+        assert_eq!(token.as_str(), "launch");
+        assert_eq!(loaded.url, record.url);
+        assert_eq!(loaded.created_at, record.created_at);
+        assert_eq!(loaded.expires_at, record.expires_at);
+        assert_eq!(loaded.hits.load(Ordering::Relaxed), 7);
+        assert_eq!(loaded.password_hash, record.password_hash);
+        assert!(loaded.deleted);
+        assert_eq!(loaded.owner, record.owner);
+        assert_eq!(loaded.max_hits, Some(10));
+        assert_eq!(loaded.destinations, record.destinations);
+        assert_eq!(loaded.utm_params, record.utm_params);
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_missing_file() -> Result<()> {
+        let store = load(&snapshot_path("missing"), TokenConfig::default())?;
+        assert!(store.list(0, usize::MAX)?.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,1043 @@
+use crate::clock::{Clock, SystemClock};
+use crate::store::{
+    self, ClickInfo, LinkOptions, LinkRecord, StoreAccess, StoreError, StoreStats, MAX_CLICKS,
+    MAX_TOKEN_ATTEMPTS,
+};
+use crate::token::{Token, TokenConfig, TokenStrategy};
+use color_eyre::eyre::{eyre, Result};
+use rusqlite::{params, Connection, ErrorCode, OptionalExtension, TransactionBehavior};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::AtomicU64;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use url::Url;
+
+/// `StoreAccess` backed by a SQLite database so links survive restarts.
+pub struct SqliteStore {
+    conn: Mutex<Connection>,
+    /// Shape of freshly generated tokens.
+    pub token_config: TokenConfig,
+    /// Refuse a second live link to the same URL with `StoreError::DuplicateUrl`.
+    pub unique_urls: bool,
+    clock: Arc<dyn Clock>,
+}
+
+impl SqliteStore {
+    /// Opens (or creates) the database at `path`. Use `":memory:"` for a throwaway store.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS links (
+                token TEXT PRIMARY KEY,
+                url TEXT NOT NULL,
+                created_at INTEGER NOT NULL,
+                expires_at INTEGER,
+                hits INTEGER NOT NULL DEFAULT 0,
+                password_hash TEXT,
+                deleted INTEGER NOT NULL DEFAULT 0,
+                owner TEXT,
+                max_hits INTEGER,
+                destinations TEXT,
+                utm_params TEXT
+            );
+            CREATE TABLE IF NOT EXISTS clicks (
+                token TEXT NOT NULL,
+                at INTEGER NOT NULL,
+                referer TEXT,
+                user_agent TEXT
+            );
+            CREATE INDEX IF NOT EXISTS clicks_token ON clicks (token);
+            CREATE TABLE IF NOT EXISTS sequence (
+                id INTEGER PRIMARY KEY CHECK (id = 0),
+                next INTEGER NOT NULL
+            );",
+        )?;
+        // Databases created by older versions lack the later columns
+        add_column_if_missing(&conn, "password_hash", "TEXT")?;
+        add_column_if_missing(&conn, "deleted", "INTEGER NOT NULL DEFAULT 0")?;
+        add_column_if_missing(&conn, "owner", "TEXT")?;
+        add_column_if_missing(&conn, "max_hits", "INTEGER")?;
+        add_column_if_missing(&conn, "destinations", "TEXT")?;
+        add_column_if_missing(&conn, "utm_params", "TEXT")?;
+
+        Ok(Self {
+            conn: Mutex::new(conn),
+            token_config: TokenConfig::default(),
+            unique_urls: false,
+            clock: Arc::new(SystemClock),
+        })
+    }
+
+    /// Ignores poisoning, since SQLite rolls back any statement a panic interrupted.
+    fn connection(&self) -> MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn insert(
+        &self,
+        conn: &Connection,
+        token: &Token,
+        url: &Url,
+        expires_at: Option<SystemTime>,
+        options: &LinkOptions,
+    ) -> Result<()> {
+        let created_at = to_secs(self.clock.now())?;
+        let expires_at = expires_at.map(to_secs).transpose()?;
+        if self.unique_urls {
+            let taken: bool = conn.query_row(
+                "SELECT EXISTS (SELECT 1 FROM links WHERE url = ?1 AND deleted = 0
+                 AND (expires_at IS NULL OR expires_at > ?2)
+                 AND (max_hits IS NULL OR hits < max_hits))",
+                params![url.as_str(), created_at],
+                |row| row.get(0),
+            )?;
+            if taken {
+                return Err(StoreError::DuplicateUrl.into());
+            }
+        }
+        let inserted = conn.execute(
+            "INSERT INTO links (token, url, created_at, expires_at, password_hash, owner, max_hits,
+                                destinations, utm_params)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![
+                token.as_str(),
+                url.as_str(),
+                created_at,
+                expires_at,
+                options.password_hash,
+                options.owner,
+                options.max_hits.map(|max_hits| max_hits as i64),
+                to_json_column(&options.destinations)?,
+                to_json_column(&options.utm_params)?
+            ],
+        );
+        match inserted {
+            Ok(_) => Ok(()),
+            Err(e) if e.sqlite_error_code() == Some(ErrorCode::ConstraintViolation) => {
+                Err(StoreError::AliasTaken.into())
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Next candidate token under `token_config.strategy`.
+    fn generate_token(&self, conn: &Connection) -> Result<Token> {
+        match self.token_config.strategy {
+            TokenStrategy::Random => Ok(Token::generate(&self.token_config)?),
+            TokenStrategy::Sequential => {
+                let n: i64 = conn.query_row(
+                    "INSERT INTO sequence (id, next) VALUES (0, 1)
+                     ON CONFLICT (id) DO UPDATE SET next = next + 1
+                     RETURNING next - 1",
+                    (),
+                    |row| row.get(0),
+                )?;
+                Ok(Token::from_sequence(n as u64, self.token_config.charset))
+            }
+        }
+    }
+
+    /// Inserts under a freshly generated token, retrying while it is already taken.
+    fn insert_fresh(
+        &self,
+        conn: &Connection,
+        url: &Url,
+        expires_at: Option<SystemTime>,
+        options: &LinkOptions,
+    ) -> Result<Token> {
+        for _ in 0..MAX_TOKEN_ATTEMPTS {
+            let token = self.generate_token(conn)?;
+            let token_str = token.as_str();
+            if self.token_config.is_reserved(token_str) || self.token_config.is_blocked(token_str) {
+                continue;
+            }
+            match self.insert(conn, &token, url, expires_at, options) {
+                Err(e) if e.downcast_ref::<StoreError>() == Some(&StoreError::AliasTaken) => {
+                    tracing::warn!("Token collision on {token}, retrying");
+                }
+                result => return result.map(|_| token),
+            }
+        }
+        Err(eyre!(
+            "Failed to generate an unused token after {MAX_TOKEN_ATTEMPTS} attempts"
+        ))
+    }
+
+    fn live_record(&self, conn: &Connection, token: &str) -> Result<LinkRecord> {
+        let (
+            url,
+            created_at,
+            expires_at,
+            hits,
+            password_hash,
+            deleted,
+            owner,
+            max_hits,
+            destinations,
+            utm_params,
+        ) = conn
+            .query_row(
+                "SELECT url, created_at, expires_at, hits, password_hash, deleted, owner, max_hits,
+                        destinations, utm_params
+                 FROM links WHERE token = ?1",
+                params![token],
+                |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, i64>(1)?,
+                        row.get::<_, Option<i64>>(2)?,
+                        row.get::<_, i64>(3)?,
+                        row.get::<_, Option<String>>(4)?,
+                        row.get::<_, bool>(5)?,
+                        row.get::<_, Option<String>>(6)?,
+                        row.get::<_, Option<i64>>(7)?,
+                        row.get::<_, Option<String>>(8)?,
+                        row.get::<_, Option<String>>(9)?,
+                    ))
+                },
+            )
+            .optional()?
+            .ok_or_else(|| eyre!("Token not found"))?;
+
+        let record = LinkRecord {
+            url: Url::parse(&url)?,
+            created_at: from_secs(created_at),
+            expires_at: expires_at.map(from_secs),
+            hits: AtomicU64::new(hits as u64),
+            password_hash,
+            deleted,
+            owner,
+            max_hits: max_hits.map(|max_hits| max_hits as u64),
+            destinations: parse_json_column(destinations)?,
+            utm_params: parse_json_column(utm_params)?,
+        };
+        if record.deleted {
+            return Err(StoreError::Deleted.into());
+        }
+        if record.is_expired(self.clock.now()) {
+            return Err(StoreError::Expired.into());
+        }
+        if record.is_exhausted() {
+            return Err(StoreError::Exhausted.into());
+        }
+        Ok(record)
+    }
+}
+
+fn add_column_if_missing(conn: &Connection, column: &str, definition: &str) -> Result<()> {
+    let exists: bool = conn.query_row(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info('links') WHERE name = ?1",
+        params![column],
+        |row| row.get(0),
+    )?;
+    if !exists {
+        conn.execute(
+            &format!("ALTER TABLE links ADD COLUMN {column} {definition}"),
+            (),
+        )?;
+    }
+    Ok(())
+}
+
+/// Reads a column holding a JSON array or `NULL`: `destinations` of `[url, weight]` pairs,
+/// or `utm_params` of `[name, value]` pairs.
+fn parse_json_column<T: DeserializeOwned>(json: Option<String>) -> Result<Vec<T>> {
+    match json {
+        Some(json) => Ok(serde_json::from_str(&json)?),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// The column `parse_json_column` reads `values` back from, `NULL` when empty.
+fn to_json_column<T: Serialize>(values: &[T]) -> Result<Option<String>> {
+    if values.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(serde_json::to_string(values)?))
+}
+
+fn to_secs(time: SystemTime) -> Result<i64> {
+    Ok(time.duration_since(UNIX_EPOCH)?.as_secs() as i64)
+}
+
+fn from_secs(secs: i64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs as u64)
+}
+
+impl StoreAccess for SqliteStore {
+    fn register_with_options(
+        &mut self,
+        url: Url,
+        alias: Option<&str>,
+        ttl: Option<Duration>,
+        options: LinkOptions,
+    ) -> Result<Token> {
+        let expires_at = ttl.map(|ttl| self.clock.now() + ttl);
+        let conn = self.connection();
+        let token = match alias {
+            Some(alias) => {
+                let token = Token::from_alias(alias)?;
+                if self.token_config.is_reserved(token.as_str()) {
+                    return Err(StoreError::AliasReserved.into());
+                }
+                self.insert(&conn, &token, &url, expires_at, &options)?;
+                token
+            }
+            None => self.insert_fresh(&conn, &url, expires_at, &options)?,
+        };
+
+        store::log_registration(&token, alias, ttl);
+        Ok(token)
+    }
+
+    /// One immediate transaction, so other processes sharing the database can't register
+    /// the URL between the lookup and the insert either.
+    fn register_or_get(&mut self, url: Url) -> Result<(Token, bool)> {
+        let mut conn = self.connection();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let existing: Option<String> = tx
+            .query_row(
+                "SELECT token FROM links WHERE url = ?1 AND expires_at IS NULL AND deleted = 0
+                 AND password_hash IS NULL AND owner IS NULL AND max_hits IS NULL
+                 AND destinations IS NULL AND utm_params IS NULL
+                 ORDER BY created_at, token LIMIT 1",
+                params![url.as_str()],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if let Some(token) = existing {
+            return Ok((Token::parse_any(&token, &self.token_config)?, false));
+        }
+        let token = self.insert_fresh(&tx, &url, None, &LinkOptions::default())?;
+        tx.commit()?;
+
+        tracing::info!("Registered a new token: {token}");
+        Ok((token, true))
+    }
+
+    fn check_alias(&self, alias: &str) -> Result<()> {
+        let token = Token::from_alias(alias)?;
+        if self.token_config.is_reserved(token.as_str()) {
+            return Err(StoreError::AliasReserved.into());
+        }
+        let taken: bool = self.connection().query_row(
+            "SELECT EXISTS (SELECT 1 FROM links WHERE token = ?1)",
+            params![token.as_str()],
+            |row| row.get(0),
+        )?;
+        if taken {
+            return Err(StoreError::AliasTaken.into());
+        }
+        Ok(())
+    }
+
+    fn resolve_token(&self, token: &str) -> Result<Url> {
+        let conn = self.connection();
+        let record = self.live_record(&conn, token)?;
+        let counted = conn.execute(
+            "UPDATE links SET hits = hits + 1
+             WHERE token = ?1 AND (max_hits IS NULL OR hits < max_hits)",
+            params![token],
+        )?;
+        if counted == 0 {
+            return Err(StoreError::Exhausted.into());
+        }
+        Ok(record.redirect_url(&mut rand::rng()))
+    }
+
+    fn link_info(&self, token: &str) -> Result<LinkRecord> {
+        let conn = self.connection();
+        self.live_record(&conn, token)
+    }
+
+    fn owner(&self, token: &str) -> Result<Option<String>> {
+        let owner: Option<Option<String>> = self
+            .connection()
+            .query_row(
+                "SELECT owner FROM links WHERE token = ?1",
+                params![token],
+                |row| row.get(0),
+            )
+            .optional()?;
+        owner.ok_or_else(|| eyre!("Token not found"))
+    }
+
+    fn delete_token(&mut self, token: &str) -> Result<()> {
+        let conn = self.connection();
+        let deleted: Option<bool> = conn
+            .query_row(
+                "SELECT deleted FROM links WHERE token = ?1",
+                params![token],
+                |row| row.get(0),
+            )
+            .optional()?;
+        match deleted {
+            None => return Err(eyre!("Token not found")),
+            Some(true) => return Err(StoreError::Deleted.into()),
+            Some(false) => {}
+        }
+        conn.execute(
+            "UPDATE links SET deleted = 1 WHERE token = ?1",
+            params![token],
+        )?;
+
+        tracing::info!("Deleted token: {token}");
+        Ok(())
+    }
+
+    fn restore_token(&mut self, token: &str) -> Result<()> {
+        let conn = self.connection();
+        let restored = conn.execute(
+            "UPDATE links SET deleted = 0 WHERE token = ?1",
+            params![token],
+        )?;
+        if restored == 0 {
+            return Err(eyre!("Token not found"));
+        }
+
+        tracing::info!("Restored token: {token}");
+        Ok(())
+    }
+
+    fn update_url(&mut self, token: &str, new_url: Url) -> Result<()> {
+        let conn = self.connection();
+        self.live_record(&conn, token)?;
+        conn.execute(
+            "UPDATE links SET url = ?1 WHERE token = ?2",
+            params![new_url.as_str(), token],
+        )?;
+
+        tracing::info!("Updated token: {token}");
+        Ok(())
+    }
+
+    fn set_password_hash(&mut self, token: &str, password_hash: Option<String>) -> Result<()> {
+        let conn = self.connection();
+        self.live_record(&conn, token)?;
+        conn.execute(
+            "UPDATE links SET password_hash = ?1 WHERE token = ?2",
+            params![password_hash, token],
+        )?;
+        Ok(())
+    }
+
+    fn set_owner(&mut self, token: &str, owner: Option<String>) -> Result<()> {
+        let conn = self.connection();
+        self.live_record(&conn, token)?;
+        conn.execute(
+            "UPDATE links SET owner = ?1 WHERE token = ?2",
+            params![owner, token],
+        )?;
+        Ok(())
+    }
+
+    fn set_max_hits(&mut self, token: &str, max_hits: Option<u64>) -> Result<()> {
+        let conn = self.connection();
+        self.live_record(&conn, token)?;
+        conn.execute(
+            "UPDATE links SET max_hits = ?1 WHERE token = ?2",
+            params![max_hits.map(|max_hits| max_hits as i64), token],
+        )?;
+        Ok(())
+    }
+
+    fn set_destinations(&mut self, token: &str, destinations: Vec<(Url, u32)>) -> Result<()> {
+        let conn = self.connection();
+        self.live_record(&conn, token)?;
+        conn.execute(
+            "UPDATE links SET destinations = ?1 WHERE token = ?2",
+            params![to_json_column(&destinations)?, token],
+        )?;
+        Ok(())
+    }
+
+    fn set_utm_params(&mut self, token: &str, utm_params: Vec<(String, String)>) -> Result<()> {
+        let conn = self.connection();
+        self.live_record(&conn, token)?;
+        conn.execute(
+            "UPDATE links SET utm_params = ?1 WHERE token = ?2",
+            params![to_json_column(&utm_params)?, token],
+        )?;
+        Ok(())
+    }
+
+    fn purge_expired(&mut self, now: SystemTime) -> Result<usize> {
+        let now = to_secs(now)?;
+        let mut conn = self.connection();
+        let tx = conn.transaction()?;
+        tx.execute(
+            "DELETE FROM clicks WHERE token IN
+             (SELECT token FROM links WHERE expires_at <= ?1 OR hits >= max_hits)",
+            params![now],
+        )?;
+        let purged = tx.execute(
+            "DELETE FROM links WHERE expires_at <= ?1 OR hits >= max_hits",
+            params![now],
+        )?;
+        tx.commit()?;
+        Ok(purged)
+    }
+
+    fn clear(&mut self) -> Result<usize> {
+        let mut conn = self.connection();
+        let tx = conn.transaction()?;
+        tx.execute("DELETE FROM clicks", ())?;
+        tx.execute("DELETE FROM sequence", ())?;
+        let cleared = tx.execute("DELETE FROM links", ())?;
+        tx.commit()?;
+
+        tracing::info!("Cleared {cleared} links");
+        Ok(cleared)
+    }
+
+    fn usage(&self, now: SystemTime) -> Result<HashMap<String, usize>> {
+        let conn = self.connection();
+        let mut stmt = conn.prepare(
+            "SELECT owner, COUNT(*) FROM links
+             WHERE owner IS NOT NULL AND deleted = 0
+               AND (expires_at IS NULL OR expires_at > ?1)
+               AND (max_hits IS NULL OR hits < max_hits)
+             GROUP BY owner",
+        )?;
+        let rows = stmt.query_map(params![to_secs(now)?], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)? as usize))
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn stats(&self, now: SystemTime) -> Result<StoreStats> {
+        let conn = self.connection();
+        let (links, hits, expired) = conn.query_row(
+            "SELECT COALESCE(SUM(deleted = 0), 0), COALESCE(SUM(hits), 0),
+                    COALESCE(SUM(deleted = 0 AND (expires_at <= ?1 OR hits >= max_hits)), 0)
+             FROM links",
+            params![to_secs(now)?],
+            |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, i64>(2)?,
+                ))
+            },
+        )?;
+        Ok(StoreStats {
+            links: links as usize,
+            hits: hits as u64,
+            expired: expired as usize,
+        })
+    }
+
+    fn list(&self, offset: usize, limit: usize) -> Result<Vec<(Token, LinkRecord)>> {
+        let conn = self.connection();
+        let mut stmt = conn.prepare(
+            "SELECT token, url, created_at, expires_at, hits, password_hash, deleted, owner,
+                    max_hits, destinations, utm_params
+             FROM links ORDER BY created_at, token LIMIT ?1 OFFSET ?2",
+        )?;
+        let rows = stmt.query_map(params![limit as i64, offset as i64], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, i64>(2)?,
+                row.get::<_, Option<i64>>(3)?,
+                row.get::<_, i64>(4)?,
+                row.get::<_, Option<String>>(5)?,
+                row.get::<_, bool>(6)?,
+                row.get::<_, Option<String>>(7)?,
+                row.get::<_, Option<i64>>(8)?,
+                row.get::<_, Option<String>>(9)?,
+                row.get::<_, Option<String>>(10)?,
+            ))
+        })?;
+
+        rows.map(|row| {
+            let (
+                token,
+                url,
+                created_at,
+                expires_at,
+                hits,
+                password_hash,
+                deleted,
+                owner,
+                max_hits,
+                destinations,
+                utm_params,
+            ) = row?;
+            let record = LinkRecord {
+                url: Url::parse(&url)?,
+                created_at: from_secs(created_at),
+                expires_at: expires_at.map(from_secs),
+                hits: AtomicU64::new(hits as u64),
+                password_hash,
+                deleted,
+                owner,
+                max_hits: max_hits.map(|max_hits| max_hits as u64),
+                destinations: parse_json_column(destinations)?,
+                utm_params: parse_json_column(utm_params)?,
+            };
+            Ok((Token::parse_any(&token, &self.token_config)?, record))
+        })
+        .collect()
+    }
+
+    fn record_click(&self, token: &str, click: ClickInfo) -> Result<()> {
+        let conn = self.connection();
+        conn.execute(
+            "INSERT INTO clicks (token, at, referer, user_agent) VALUES (?1, ?2, ?3, ?4)",
+            params![token, to_secs(click.at)?, click.referer, click.user_agent],
+        )?;
+        conn.execute(
+            "DELETE FROM clicks WHERE token = ?1 AND rowid NOT IN (
+                SELECT rowid FROM clicks WHERE token = ?1 ORDER BY rowid DESC LIMIT ?2
+            )",
+            params![token, MAX_CLICKS as i64],
+        )?;
+        Ok(())
+    }
+
+    fn clicks(&self, token: &str) -> Result<Vec<ClickInfo>> {
+        let conn = self.connection();
+        self.live_record(&conn, token)?;
+        let mut stmt = conn.prepare(
+            "SELECT at, referer, user_agent FROM clicks WHERE token = ?1 ORDER BY rowid DESC",
+        )?;
+        let clicks = stmt.query_map(params![token], |row| {
+            Ok(ClickInfo {
+                at: from_secs(row.get(0)?),
+                referer: row.get(1)?,
+                user_agent: row.get(2)?,
+            })
+        })?;
+        Ok(clicks.collect::<Result<_, _>>()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    #[test]
+    fn test_register_and_resolve() -> Result<()> {
+        let mut store = SqliteStore::open(":memory:")?;
+        let url = Url::parse("https://example.com")?;
+        let token = store.register_url(url.clone())?;
+
+        assert_eq!(store.resolve_token(token.as_str())?, url);
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_nonexistent_token() -> Result<()> {
+        let store = SqliteStore::open(":memory:")?;
+        assert!(store.resolve_token("123456").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_register_taken_alias() -> Result<()> {
+        let mut store = SqliteStore::open(":memory:")?;
+        store.register_url_with_alias(Url::parse("https://example1.com")?, "my-launch")?;
+
+        let err = store
+            .register_url_with_alias(Url::parse("https://example2.com")?, "my-launch")
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<StoreError>(),
+            Some(&StoreError::AliasTaken)
+        );
+        assert_eq!(
+            store.resolve_token("my-launch")?,
+            Url::parse("https://example1.com")?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_register_reserved_alias() -> Result<()> {
+        let mut store = SqliteStore::open(":memory:")?;
+
+        let err = store
+            .register_url_with_alias(Url::parse("https://example.com")?, "admin")
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<StoreError>(),
+            Some(&StoreError::AliasReserved)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_expired_token() -> Result<()> {
+        let clock = Arc::new(MockClock::new(UNIX_EPOCH + Duration::from_secs(1_000)));
+        let mut store = SqliteStore {
+            clock: clock.clone(),
+            ..SqliteStore::open(":memory:")?
+        };
+        let token = store
+            .register_url_with_ttl(Url::parse("https://example.com")?, Duration::from_secs(60))?;
+        assert!(store.resolve_token(token.as_str()).is_ok());
+
+        clock.advance(Duration::from_secs(60));
+        let err = store.resolve_token(token.as_str()).unwrap_err();
+        assert_eq!(err.downcast_ref::<StoreError>(), Some(&StoreError::Expired));
+        Ok(())
+    }
+
+    #[test]
+    fn test_purge_expired() -> Result<()> {
+        let clock = Arc::new(MockClock::new(UNIX_EPOCH + Duration::from_secs(1_000)));
+        let mut store = SqliteStore {
+            clock: clock.clone(),
+            ..SqliteStore::open(":memory:")?
+        };
+        let expiring = store
+            .register_url_with_ttl(Url::parse("https://example1.com")?, Duration::from_secs(60))?;
+        let permanent = store.register_url(Url::parse("https://example2.com")?)?;
+
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(store.purge_expired(clock.now())?, 1);
+        assert!(store.link_info(expiring.as_str()).is_err());
+        assert!(store.link_info(permanent.as_str()).is_ok());
+        assert_eq!(store.list(0, 10)?.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_hits() -> Result<()> {
+        let mut store = SqliteStore::open(":memory:")?;
+        let token = store.register_url(Url::parse("https://example.com")?)?;
+        store.set_max_hits(token.as_str(), Some(1))?;
+
+        store.resolve_token(token.as_str())?;
+        let err = store.resolve_token(token.as_str()).unwrap_err();
+        assert_eq!(err.downcast_ref(), Some(&StoreError::Exhausted));
+        let (_, record) = store.list(0, 10)?.remove(0);
+        assert_eq!(record.hits.into_inner(), 1);
+
+        assert_eq!(store.purge_expired(SystemTime::now())?, 1);
+        assert!(store.list(0, 10)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_stats() -> Result<()> {
+        let mut store = SqliteStore::open(":memory:")?;
+        let token = store.register_url(Url::parse("https://example.com")?)?;
+        let deleted = store.register_url(Url::parse("https://deleted.com")?)?;
+        store.set_max_hits(token.as_str(), Some(1))?;
+        store.resolve_token(token.as_str())?;
+        store.delete_token(deleted.as_str())?;
+
+        let stats = store.stats(SystemTime::now())?;
+        assert_eq!(
+            stats,
+            StoreStats {
+                links: 1,
+                hits: 1,
+                expired: 1,
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_destinations() -> Result<()> {
+        let mut store = SqliteStore::open(":memory:")?;
+        let token = store.register_url(Url::parse("https://example.com")?)?;
+        let other = Url::parse("https://other.com")?;
+        store.set_destinations(token.as_str(), vec![(other.clone(), 1)])?;
+
+        assert_eq!(store.resolve_token(token.as_str())?, other);
+        let (_, record) = store.list(0, 10)?.remove(0);
+        assert_eq!(record.destinations, vec![(other, 1)]);
+
+        store.set_destinations(token.as_str(), Vec::new())?;
+        assert_eq!(
+            store.resolve_token(token.as_str())?,
+            Url::parse("https://example.com")?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_utm_params() -> Result<()> {
+        let mut store = SqliteStore::open(":memory:")?;
+        let token = store.register_url(Url::parse("https://example.com/?ref=home")?)?;
+        let utm_params = vec![("utm_source".to_string(), "foo".to_string())];
+        store.set_utm_params(token.as_str(), utm_params.clone())?;
+
+        assert_eq!(
+            store.resolve_token(token.as_str())?.as_str(),
+            "https://example.com/?ref=home&utm_source=foo"
+        );
+        let (_, record) = store.list(0, 10)?.remove(0);
+        assert_eq!(record.utm_params, utm_params);
+        Ok(())
+    }
+
+    #[test]
+    fn test_register_or_get() -> Result<()> {
+        let mut store = SqliteStore::open(":memory:")?;
+        let url = Url::parse("https://example.com")?;
+
+        let (token, created) = store.register_or_get(url.clone())?;
+        assert!(created);
+        assert_eq!(store.register_or_get(url.clone())?, (token.clone(), false));
+
+        store.delete_token(token.as_str())?;
+        let (fresh, created) = store.register_or_get(url.clone())?;
+        assert!(created);
+        assert_ne!(fresh, token);
+
+        // Owned links aren't handed to other callers
+        store.set_owner(fresh.as_str(), Some("alpha".into()))?;
+        assert!(store.register_or_get(url)?.1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_unique_urls() -> Result<()> {
+        let mut store = SqliteStore {
+            unique_urls: true,
+            ..SqliteStore::open(":memory:")?
+        };
+        let url = Url::parse("https://example.com")?;
+        store.register_url(url.clone())?;
+
+        let err = store.register_url_with_alias(url, "launch").unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<StoreError>(),
+            Some(&StoreError::DuplicateUrl)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_clear() -> Result<()> {
+        let mut store = SqliteStore::open(":memory:")?;
+        let token = store.register_url(Url::parse("https://example1.com")?)?;
+        store.register_url(Url::parse("https://example2.com")?)?;
+
+        assert_eq!(store.clear()?, 2);
+        assert!(store.list(0, 10)?.is_empty());
+        assert!(store.resolve_token(token.as_str()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_link_info_counts_resolves() -> Result<()> {
+        let mut store = SqliteStore::open(":memory:")?;
+        let url = Url::parse("https://example.com")?;
+        let token = store.register_url(url.clone())?;
+
+        store.resolve_token(token.as_str())?;
+        store.resolve_token(token.as_str())?;
+        let record = store.link_info(token.as_str())?;
+        assert_eq!(record.url, url);
+        assert_eq!(record.hits.into_inner(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_delete_token() -> Result<()> {
+        let mut store = SqliteStore::open(":memory:")?;
+        let token = store.register_url(Url::parse("https://example.com")?)?;
+
+        store.delete_token(token.as_str())?;
+        assert!(store.resolve_token(token.as_str()).is_err());
+        assert!(store.delete_token(token.as_str()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_restore_token() -> Result<()> {
+        let mut store = SqliteStore::open(":memory:")?;
+        let url = Url::parse("https://example.com")?;
+        let token = store.register_url(url.clone())?;
+
+        store.delete_token(token.as_str())?;
+        let err = store.resolve_token(token.as_str()).unwrap_err();
+        assert_eq!(err.downcast_ref::<StoreError>(), Some(&StoreError::Deleted));
+        assert!(store.list(0, 10)?[0].1.deleted);
+
+        store.restore_token(token.as_str())?;
+        assert_eq!(store.resolve_token(token.as_str())?, url);
+        assert!(store.restore_token("abc123").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_adds_missing_columns() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("shortener-{}.db", Token::default()));
+        Connection::open(&path)?.execute_batch(
+            "CREATE TABLE links (
+                token TEXT PRIMARY KEY,
+                url TEXT NOT NULL,
+                created_at INTEGER NOT NULL,
+                expires_at INTEGER,
+                hits INTEGER NOT NULL DEFAULT 0
+            );
+            INSERT INTO links (token, url, created_at) VALUES ('abc123', 'https://example.com/', 0);",
+        )?;
+
+        let mut store = SqliteStore::open(&path)?;
+        let resolved = store.resolve_token("abc123");
+        let deleted = store.delete_token("abc123");
+        std::fs::remove_file(&path)?;
+
+        assert_eq!(resolved?.as_str(), "https://example.com/");
+        deleted
+    }
+
+    #[test]
+    fn test_links_survive_reopen() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("shortener-{}.db", Token::default()));
+        let url = Url::parse("https://example.com")?;
+
+        let token = SqliteStore::open(&path)?.register_url(url.clone())?;
+        let resolved = SqliteStore::open(&path)?.resolve_token(token.as_str())?;
+        std::fs::remove_file(&path)?;
+
+        assert_eq!(resolved, url);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sequential_tokens_survive_reopen() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("shortener-{}.db", Token::default()));
+        let open = || -> Result<SqliteStore> {
+            let mut store = SqliteStore::open(&path)?;
+            store.token_config.strategy = TokenStrategy::Sequential;
+            Ok(store)
+        };
+
+        let mut store = open()?;
+        let first = store.register_url(Url::parse("https://example1.com")?)?;
+        store.register_url_with_alias(Url::parse("https://alias.com")?, "b")?;
+        let second = store.register_url(Url::parse("https://example2.com")?)?;
+        drop(store);
+        let third = open()?.register_url(Url::parse("https://example3.com")?)?;
+        std::fs::remove_file(&path)?;
+
+        assert_eq!(
+            [first.as_str(), second.as_str(), third.as_str()],
+            ["a", "c", "d"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_list_pages_in_creation_order() -> Result<()> {
+        let clock = Arc::new(MockClock::new(UNIX_EPOCH));
+        let mut store = SqliteStore {
+            clock: clock.clone(),
+            ..SqliteStore::open(":memory:")?
+        };
+        let mut tokens = Vec::new();
+        for i in 0..3 {
+            tokens.push(store.register_url(Url::parse(&format!("https://example{i}.com"))?)?);
+            clock.advance(Duration::from_secs(1));
+        }
+
+        let page: Vec<Token> = store.list(1, 5)?.into_iter().map(|(t, _)| t).collect();
+        assert_eq!(page, tokens[1..]);
+        assert!(store.list(3, 5)?.is_empty());
+        assert!(SqliteStore::open(":memory:")?.list(0, 5)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_click_log_is_bounded() -> Result<()> {
+        let mut store = SqliteStore::open(":memory:")?;
+        let token = store.register_url(Url::parse("https://example.com")?)?;
+        for i in 0..MAX_CLICKS + 1 {
+            let click = ClickInfo {
+                at: from_secs(i as i64),
+                referer: Some("https://referrer.com/".to_string()),
+                user_agent: None,
+            };
+            store.record_click(token.as_str(), click)?;
+        }
+
+        let clicks = store.clicks(token.as_str())?;
+        assert_eq!(clicks.len(), MAX_CLICKS);
+        assert_eq!(clicks[0].at, from_secs(MAX_CLICKS as i64));
+        assert_eq!(clicks[MAX_CLICKS - 1].at, from_secs(1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_url() -> Result<()> {
+        let mut store = SqliteStore::open(":memory:")?;
+        let token = store.register_url(Url::parse("https://example1.com")?)?;
+        let new_url = Url::parse("https://example2.com")?;
+
+        store.update_url(token.as_str(), new_url.clone())?;
+        assert_eq!(store.resolve_token(token.as_str())?, new_url);
+        assert!(store.update_url("abc123", new_url).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_password_hash() -> Result<()> {
+        let mut store = SqliteStore::open(":memory:")?;
+        let token = store.register_url(Url::parse("https://example.com")?)?;
+
+        store.set_password_hash(token.as_str(), Some("$argon2id$hash".to_string()))?;
+        assert_eq!(
+            store.link_info(token.as_str())?.password_hash.as_deref(),
+            Some("$argon2id$hash")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_register_with_options() -> Result<()> {
+        let mut store = SqliteStore::open(":memory:")?;
+        let options = LinkOptions {
+            password_hash: Some("$argon2id$hash".to_string()),
+            owner: Some("alpha".to_string()),
+            max_hits: Some(3),
+            destinations: vec![(Url::parse("https://example.org")?, 1)],
+            utm_params: vec![("utm_source".to_string(), "newsletter".to_string())],
+        };
+        let token =
+            store.register_with_options(Url::parse("https://example.com")?, None, None, options)?;
+
+        assert_eq!(
+            store.link_info(token.as_str())?.password_hash.as_deref(),
+            Some("$argon2id$hash")
+        );
+        assert_eq!(store.owner(token.as_str())?.as_deref(), Some("alpha"));
+        let record = store.link_info(token.as_str())?;
+        assert_eq!(record.max_hits, Some(3));
+        assert_eq!(record.destinations.len(), 1);
+        assert_eq!(record.utm_params.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_usage() -> Result<()> {
+        let mut store = SqliteStore::open(":memory:")?;
+        for (url, owner) in [
+            ("https://example1.com", Some("alpha")),
+            ("https://example2.com", Some("alpha")),
+            ("https://example3.com", None),
+        ] {
+            let token = store.register_url(Url::parse(url)?)?;
+            store.set_owner(token.as_str(), owner.map(String::from))?;
+        }
+        let deleted = store.register_url(Url::parse("https://example4.com")?)?;
+        store.set_owner(deleted.as_str(), Some("alpha".to_string()))?;
+        store.delete_token(deleted.as_str())?;
+
+        let usage = store.usage(SystemTime::now())?;
+        assert_eq!(usage, HashMap::from([("alpha".to_string(), 2)]));
+        Ok(())
+    }
+}
@@ -1,39 +1,912 @@
-use crate::token::Token;
+use crate::clock::{Clock, SystemClock};
+use crate::token::{Token, TokenConfig, TokenError, TokenStrategy};
 use color_eyre::eyre::{eyre, Result};
-use std::collections::HashMap;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{self, Display};
+use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, SystemTime};
 use url::Url;
 
-#[derive(Default)]
+/// How many fresh tokens a store tries before giving up on a registration.
+pub const MAX_TOKEN_ATTEMPTS: usize = 10;
+
+/// Share of a token length's keyspace in use before new tokens get one character longer.
+const MAX_KEYSPACE_UTILIZATION: f64 = 0.5;
+
+/// Clicks kept per token; older ones are dropped as new ones arrive.
+pub const MAX_CLICKS: usize = 100;
+
+type TokenGenerator = Box<dyn FnMut(&TokenConfig) -> Result<Token, TokenError> + Send + Sync>;
+
+/// Selects links for `StoreAccess::delete_matching`.
+pub type LinkPredicate<'a> = dyn Fn(&Token, &LinkRecord) -> bool + Send + Sync + 'a;
+
+/// Failures callers may want to tell apart from generic errors.
+#[derive(Debug, PartialEq, Eq)]
+pub enum StoreError {
+    AliasTaken,
+    /// The alias is one of `TokenConfig::reserved`.
+    AliasReserved,
+    /// Another live link already points at the URL, see `Store::with_unique_urls`.
+    DuplicateUrl,
+    Expired,
+    /// Tombstoned by `delete_token`, see `restore_token`.
+    Deleted,
+    /// Followed `LinkRecord::max_hits` times already.
+    Exhausted,
+}
+
+impl Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::AliasTaken => write!(f, "Alias is already taken"),
+            StoreError::AliasReserved => write!(f, "Alias is reserved"),
+            StoreError::DuplicateUrl => write!(f, "URL is already shortened"),
+            StoreError::Expired => write!(f, "Link has expired"),
+            StoreError::Deleted => write!(f, "Link has been deleted"),
+            StoreError::Exhausted => write!(f, "Link has reached its hit limit"),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+/// Everything the store knows about a short link.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LinkRecord {
+    pub url: Url,
+    pub created_at: SystemTime,
+    pub expires_at: Option<SystemTime>,
+    /// Bumped on every successful resolve, hence atomic. Serialized as a plain number.
+    pub hits: AtomicU64,
+    /// PHC hash of the password required to follow the link, never the plaintext.
+    pub password_hash: Option<String>,
+    /// Tombstone left by `delete_token`; the record stays so the token isn't reissued.
+    pub deleted: bool,
+    /// API key the link was registered with, counted against its quota.
+    pub owner: Option<String>,
+    /// Resolves allowed in total, after which the link is gone; `None` is unlimited.
+    pub max_hits: Option<u64>,
+    /// Targets each resolve picks from in proportion to their weight, in place of `url`;
+    /// empty for a plain link. Missing from links saved before it existed.
+    #[serde(default)]
+    pub destinations: Vec<(Url, u32)>,
+    /// Query parameters such as `utm_source` added to the target on every resolve.
+    /// Missing from links saved before it existed.
+    #[serde(default)]
+    pub utm_params: Vec<(String, String)>,
+}
+
+impl Clone for LinkRecord {
+    fn clone(&self) -> Self {
+        Self {
+            url: self.url.clone(),
+            created_at: self.created_at,
+            expires_at: self.expires_at,
+            hits: AtomicU64::new(self.hits.load(Ordering::Relaxed)),
+            password_hash: self.password_hash.clone(),
+            deleted: self.deleted,
+            owner: self.owner.clone(),
+            max_hits: self.max_hits,
+            destinations: self.destinations.clone(),
+            utm_params: self.utm_params.clone(),
+        }
+    }
+}
+
+impl LinkRecord {
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+
+    pub fn is_exhausted(&self) -> bool {
+        self.max_hits
+            .is_some_and(|max_hits| self.hits.load(Ordering::Relaxed) >= max_hits)
+    }
+
+    /// Whether `register_or_get` may hand the link out for its URL: live, permanent, and
+    /// without an owner or per-link settings the next caller didn't ask for.
+    pub fn is_shareable(&self) -> bool {
+        !self.deleted
+            && self.expires_at.is_none()
+            && self.password_hash.is_none()
+            && self.owner.is_none()
+            && self.max_hits.is_none()
+            && self.destinations.is_empty()
+            && self.utm_params.is_empty()
+    }
+
+    /// Target of one resolve: a weighted pick among `destinations`, or `url` if there are none.
+    pub fn pick_url(&self, rng: &mut impl Rng) -> &Url {
+        let total: u64 = self.destinations.iter().map(|(_, w)| u64::from(*w)).sum();
+        if total == 0 {
+            return &self.url;
+        }
+        let mut roll = rng.random_range(0..total);
+        for (url, weight) in &self.destinations {
+            match roll.checked_sub(u64::from(*weight)) {
+                Some(rest) => roll = rest,
+                None => return url,
+            }
+        }
+        unreachable!("roll is below the total weight")
+    }
+
+    /// Where one resolve redirects: `pick_url` with `utm_params` merged into its query.
+    /// Parameters the target already carries keep their value.
+    pub fn redirect_url(&self, rng: &mut impl Rng) -> Url {
+        let mut url = self.pick_url(rng).clone();
+        let missing: Vec<_> = self
+            .utm_params
+            .iter()
+            .filter(|(name, _)| {
+                !url.query_pairs()
+                    .any(|(present, _)| present == name.as_str())
+            })
+            .collect();
+        if !missing.is_empty() {
+            url.query_pairs_mut().extend_pairs(missing);
+        }
+        url
+    }
+
+    /// Counts a resolve, failing without counting once `max_hits` is reached.
+    /// Check and increment are one atomic step, so concurrent resolves can't overshoot.
+    pub fn record_hit(&self) -> Result<(), StoreError> {
+        match self.max_hits {
+            None => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+            }
+            Some(max_hits) => {
+                self.hits
+                    .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |hits| {
+                        (hits < max_hits).then_some(hits + 1)
+                    })
+                    .map_err(|_| StoreError::Exhausted)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Settings of a new link, see `StoreAccess::register_with_options`. The default is a
+/// plain link.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LinkOptions {
+    /// See `LinkRecord::password_hash`.
+    pub password_hash: Option<String>,
+    /// See `LinkRecord::owner`.
+    pub owner: Option<String>,
+    /// See `LinkRecord::max_hits`.
+    pub max_hits: Option<u64>,
+    /// See `LinkRecord::destinations`.
+    pub destinations: Vec<(Url, u32)>,
+    /// See `LinkRecord::utm_params`.
+    pub utm_params: Vec<(String, String)>,
+}
+
+/// Logs a registration made by `StoreAccess::register_with_options`.
+pub fn log_registration(token: &Token, alias: Option<&str>, ttl: Option<Duration>) {
+    match (alias, ttl) {
+        (Some(_), _) => tracing::info!("Registered a new alias: {token}"),
+        (None, Some(ttl)) => tracing::info!("Registered a new token: {token}, expires in {ttl:?}"),
+        (None, None) => tracing::info!("Registered a new token: {token}"),
+    }
+}
+
+/// Aggregate counts over a store, see `StoreAccess::stats`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StoreStats {
+    /// Links that aren't deleted, including expired ones not purged yet.
+    pub links: usize,
+    /// Hits of every stored link, deleted ones included.
+    pub hits: u64,
+    /// Links counted in `links` that expired or used up their `max_hits`.
+    pub expired: usize,
+}
+
+impl StoreStats {
+    fn add(&mut self, record: &LinkRecord, now: SystemTime) {
+        self.hits += record.hits.load(Ordering::Relaxed);
+        if record.deleted {
+            return;
+        }
+        self.links += 1;
+        if record.is_expired(now) || record.is_exhausted() {
+            self.expired += 1;
+        }
+    }
+}
+
+/// One resolve of a short link, as seen in the request headers.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClickInfo {
+    pub at: SystemTime,
+    pub referer: Option<String>,
+    pub user_agent: Option<String>,
+}
+
 pub struct Store {
-    items: HashMap<Token, Url>,
+    items: HashMap<Token, LinkRecord>,
+    /// Reverse index of shareable links, only maintained when `dedup` is on.
+    tokens_by_url: HashMap<Url, Token>,
+    /// Look up `register_or_get` in `tokens_by_url` rather than walking every link.
+    dedup: bool,
+    /// Refuse a second live link to the same URL instead; `dedup` takes precedence.
+    unique_urls: bool,
+    /// Match tokens regardless of case; every token is then stored lowercased.
+    case_insensitive: bool,
+    token_config: TokenConfig,
+    token_generator: TokenGenerator,
+    clock: Arc<dyn Clock>,
+    /// Behind its own lock since clicks are recorded while resolving through `&self`.
+    click_log: Mutex<HashMap<Token, VecDeque<ClickInfo>>>,
+    /// Counter behind `TokenStrategy::Sequential` tokens.
+    next_sequence: u64,
+}
+
+impl Default for Store {
+    fn default() -> Self {
+        Self::new(TokenConfig::default())
+    }
+}
+
+impl Store {
+    pub fn new(token_config: TokenConfig) -> Self {
+        Self {
+            items: HashMap::new(),
+            tokens_by_url: HashMap::new(),
+            dedup: false,
+            unique_urls: false,
+            case_insensitive: false,
+            token_config,
+            token_generator: Box::new(Token::generate),
+            clock: Arc::new(SystemClock),
+            click_log: Mutex::new(HashMap::new()),
+            next_sequence: 0,
+        }
+    }
+
+    /// Fails registrations with `StoreError::DuplicateUrl` when a live link already points at
+    /// the same URL. Targets are compared after normalization, so this also catches
+    /// spellings that canonicalize to an existing link.
+    pub fn with_unique_urls(mut self, unique_urls: bool) -> Self {
+        self.unique_urls = unique_urls;
+        self
+    }
+
+    /// Keeps an index of shareable links by URL, so `register_or_get` finds the existing
+    /// link without walking the store. Links already in the store are indexed too.
+    pub fn with_dedup(mut self, dedup: bool) -> Self {
+        self.dedup = dedup;
+        let links = mem::take(&mut self.items).into_iter().collect();
+        self.tokens_by_url.clear();
+        self.with_links(links)
+    }
+
+    /// Matches tokens regardless of case by storing and looking them up lowercased.
+    /// Links already in the store are re-keyed; ones differing only in case collapse into one.
+    pub fn with_case_insensitive_tokens(mut self, case_insensitive: bool) -> Self {
+        self.case_insensitive = case_insensitive;
+        let links = mem::take(&mut self.items).into_iter().collect();
+        self.tokens_by_url.clear();
+        self.with_links(links)
+    }
+
+    /// A store holding the links `StoreAccess::to_json` wrote. Settings such as `dedup`
+    /// are not part of them.
+    pub fn from_json(json: &[u8], token_config: TokenConfig) -> Result<Self> {
+        Ok(Self::new(token_config).with_links(crate::snapshot::from_json(json)?))
+    }
+
+    /// Starts out with `links` as they are, e.g. read back by `snapshot::load`.
+    pub fn with_links(mut self, links: Vec<(Token, LinkRecord)>) -> Self {
+        self.insert_links(links);
+        self
+    }
+
+    /// Replaces every link with `links`, e.g. to undo changes that couldn't be saved.
+    /// Click logs are kept.
+    #[cfg(feature = "json")]
+    pub fn reset_links(&mut self, links: Vec<(Token, LinkRecord)>) {
+        self.items.clear();
+        self.tokens_by_url.clear();
+        self.insert_links(links);
+    }
+
+    fn insert_links(&mut self, links: Vec<(Token, LinkRecord)>) {
+        for (token, record) in links {
+            let token = self.canonical(token);
+            if self.dedup && record.is_shareable() {
+                self.tokens_by_url.insert(record.url.clone(), token.clone());
+            }
+            self.items.insert(token, record);
+        }
+    }
+
+    fn check_unique(&self, url: &Url) -> Result<()> {
+        if !self.unique_urls {
+            return Ok(());
+        }
+        let now = self.clock.now();
+        let taken = self.items.values().any(|record| {
+            &record.url == url
+                && !record.deleted
+                && !record.is_expired(now)
+                && !record.is_exhausted()
+        });
+        if taken {
+            return Err(StoreError::DuplicateUrl.into());
+        }
+        Ok(())
+    }
+
+    /// Generates a token not already in use; existing links are never overwritten.
+    fn fresh_token(&mut self) -> Result<Token> {
+        if self.token_config.strategy == TokenStrategy::Sequential {
+            return Ok(self.next_sequential_token());
+        }
+        for _ in 0..MAX_TOKEN_ATTEMPTS {
+            let config = self.grown_token_config();
+            let token = (self.token_generator)(&config)?;
+            let token = self.canonical(token);
+            if self.token_config.is_reserved(token.as_str()) {
+                continue;
+            }
+            if !self.items.contains_key(&token) {
+                return Ok(token);
+            }
+            tracing::warn!("Token collision on {token}, retrying");
+        }
+        Err(eyre!(
+            "Failed to generate an unused token after {MAX_TOKEN_ATTEMPTS} attempts"
+        ))
+    }
+
+    /// Advances the counter past values already taken, e.g. by an alias, reserved or blocked.
+    fn next_sequential_token(&mut self) -> Token {
+        loop {
+            let token = Token::from_sequence(self.next_sequence, self.token_config.charset);
+            let token = self.canonical(token);
+            self.next_sequence += 1;
+            if !self.items.contains_key(&token)
+                && !self.token_config.is_reserved(token.as_str())
+                && !self.token_config.is_blocked(token.as_str())
+            {
+                return token;
+            }
+        }
+    }
+
+    fn insert(
+        &mut self,
+        token: Token,
+        url: Url,
+        expires_at: Option<SystemTime>,
+        options: LinkOptions,
+    ) {
+        let record = LinkRecord {
+            url,
+            created_at: self.clock.now(),
+            expires_at,
+            hits: AtomicU64::new(0),
+            password_hash: options.password_hash,
+            deleted: false,
+            owner: options.owner,
+            max_hits: options.max_hits,
+            destinations: options.destinations,
+            utm_params: options.utm_params,
+        };
+        self.items.insert(token, record);
+    }
+
+    /// Applies `change` to a live link, then drops it from the dedup index unless it is
+    /// still shareable.
+    fn update_live<T>(
+        &mut self,
+        token: &str,
+        change: impl FnOnce(&mut LinkRecord) -> T,
+    ) -> Result<T> {
+        self.live_record(token)?;
+        let token = self.parse_token(token)?;
+        let record = self
+            .items
+            .get_mut(&token)
+            .ok_or_else(|| eyre!("Token not found"))?;
+        let changed = change(record);
+        if !record.is_shareable() && self.tokens_by_url.get(&record.url) == Some(&token) {
+            self.tokens_by_url.remove(&record.url);
+        }
+        Ok(changed)
+    }
+
+    // Expired records are left in place since lookups only borrow the store.
+    fn live_record(&self, token: &str) -> Result<&LinkRecord> {
+        let token = self.parse_token(token)?;
+        let record = self
+            .items
+            .get(&token)
+            .ok_or_else(|| eyre!("Token not found"))?;
+        if record.deleted {
+            return Err(StoreError::Deleted.into());
+        }
+        if record.is_expired(self.clock.now()) {
+            return Err(StoreError::Expired.into());
+        }
+        if record.is_exhausted() {
+            return Err(StoreError::Exhausted.into());
+        }
+        Ok(record)
+    }
+
+    /// `token_config` lengthened until the links stored so far use at most
+    /// `MAX_KEYSPACE_UTILIZATION` of the tokens of that length.
+    fn grown_token_config(&self) -> TokenConfig {
+        let mut config = self.token_config.clone();
+        let keyspace = |length: usize| (config.charset.size() as f64).powi(length as i32);
+        while config.length < Token::MAX_TOKEN_LENGTH
+            && self.items.len() as f64 >= keyspace(config.length) * MAX_KEYSPACE_UTILIZATION
+        {
+            config.length += 1;
+        }
+        config
+    }
+
+    /// Accepts both generated tokens and aliases.
+    fn parse_token(&self, token: &str) -> Result<Token> {
+        Token::parse_any(token, &self.token_config).map(|token| self.canonical(token))
+    }
+
+    // A panic can't leave a click half-recorded, so poisoning is ignored
+    fn click_log(&self) -> MutexGuard<'_, HashMap<Token, VecDeque<ClickInfo>>> {
+        self.click_log
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Form a token is stored and looked up under.
+    fn canonical(&self, token: Token) -> Token {
+        if self.case_insensitive {
+            token.to_ascii_lowercase()
+        } else {
+            token
+        }
+    }
 }
 
-pub trait StoreAccess: Send {
-    fn register_url(&mut self, url: Url) -> Result<Token>;
+pub trait StoreAccess: Send + Sync {
+    fn register_url(&mut self, url: Url) -> Result<Token> {
+        self.register_with_options(url, None, None, LinkOptions::default())
+    }
+    fn register_url_with_alias(&mut self, url: Url, alias: &str) -> Result<Token> {
+        self.register_with_options(url, Some(alias), None, LinkOptions::default())
+    }
+    // Handlers pass a TTL through `register_with_options`, only tests use this
+    #[allow(dead_code)]
+    fn register_url_with_ttl(&mut self, url: Url, ttl: Duration) -> Result<Token> {
+        self.register_with_options(url, None, Some(ttl), LinkOptions::default())
+    }
+    /// Registers `url` under `alias`, or a fresh token without one, expiring after `ttl` if
+    /// given. `options` are written in the same insert, so the link is never live without them.
+    fn register_with_options(
+        &mut self,
+        url: Url,
+        alias: Option<&str>,
+        ttl: Option<Duration>,
+        options: LinkOptions,
+    ) -> Result<Token>;
+    /// The token of a shareable link to `url`, see `LinkRecord::is_shareable`, or a newly
+    /// registered one, along with whether it is new. Checking and registering happen in this
+    /// one call, so nothing can register the same URL in between. Walks every link, so stores
+    /// may want something cheaper.
+    fn register_or_get(&mut self, url: Url) -> Result<(Token, bool)> {
+        let existing = self
+            .list(0, usize::MAX)?
+            .into_iter()
+            .find(|(_, record)| record.url == url && record.is_shareable());
+        match existing {
+            Some((token, _)) => Ok((token, false)),
+            None => Ok((self.register_url(url)?, true)),
+        }
+    }
+    /// Registers each URL independently with the same `options`, so one failure doesn't
+    /// sink the rest.
+    fn register_batch(&mut self, urls: Vec<Url>, options: LinkOptions) -> Vec<Result<Token>> {
+        urls.into_iter()
+            .map(|url| self.register_with_options(url, None, None, options.clone()))
+            .collect()
+    }
     fn resolve_token(&self, token: &str) -> Result<Url>;
+    /// Looks up a link's full record without counting it as a hit; fails
+    /// exactly when `resolve_token` would.
+    fn link_info(&self, token: &str) -> Result<LinkRecord>;
+    /// The record behind `resolve_token`, for handlers that need more than the URL.
+    fn get_record(&self, token: &str) -> Result<LinkRecord> {
+        self.link_info(token)
+    }
+    /// The API key the link belongs to, deleted or not, so only its owner can restore it.
+    /// Walks every link, so stores may want something cheaper.
+    fn owner(&self, token: &str) -> Result<Option<String>> {
+        self.list(0, usize::MAX)?
+            .into_iter()
+            .find(|(candidate, _)| candidate.as_str() == token)
+            .map(|(_, record)| record.owner)
+            .ok_or_else(|| eyre!("Token not found"))
+    }
+    /// Hits of the link so far; fails exactly when `link_info` would.
+    // Only tests read a bare counter since `link_stats` needs the whole record
+    #[allow(dead_code)]
+    fn hit_count(&self, token: &str) -> Result<u64> {
+        Ok(self.link_info(token)?.hits.into_inner())
+    }
+    /// Tombstones the link: lookups fail with `StoreError::Deleted` until
+    /// `restore_token`, and the token is never handed out again.
+    fn delete_token(&mut self, token: &str) -> Result<()>;
+    /// Lifts the tombstone left by `delete_token`; restoring a live link is a no-op.
+    fn restore_token(&mut self, token: &str) -> Result<()>;
+    /// Points an existing token at `new_url`, keeping its hits and expiry.
+    fn update_url(&mut self, token: &str, new_url: Url) -> Result<()>;
+    /// Requires a password to follow the link; `None` lifts the requirement.
+    // Registration takes these settings as `LinkOptions`, only tests call the setters below
+    #[allow(dead_code)]
+    fn set_password_hash(&mut self, token: &str, password_hash: Option<String>) -> Result<()>;
+    /// Records which API key the link belongs to.
+    #[allow(dead_code)]
+    fn set_owner(&mut self, token: &str, owner: Option<String>) -> Result<()>;
+    /// Lets the link be resolved `max_hits` times in total, after which lookups fail
+    /// with `StoreError::Exhausted`; `None` lifts the limit.
+    #[allow(dead_code)]
+    fn set_max_hits(&mut self, token: &str, max_hits: Option<u64>) -> Result<()>;
+    /// Splits resolves between weighted targets, see `LinkRecord::destinations`;
+    /// empty sends them all to the link's URL again.
+    #[allow(dead_code)]
+    fn set_destinations(&mut self, token: &str, destinations: Vec<(Url, u32)>) -> Result<()>;
+    /// Tags every resolve with these query parameters, see `LinkRecord::utm_params`;
+    /// empty stops tagging.
+    #[allow(dead_code)]
+    fn set_utm_params(&mut self, token: &str, utm_params: Vec<(String, String)>) -> Result<()>;
+    /// Removes links that expired by `now` or used up their `max_hits`, with their
+    /// clicks, returning how many went.
+    /// Their tokens may be handed out again afterwards.
+    fn purge_expired(&mut self, now: SystemTime) -> Result<usize>;
+    /// Removes every link, deleted or not, and resets counters, returning how many links went.
+    fn clear(&mut self) -> Result<usize>;
+    /// Tombstones every link `matches` accepts, like `delete_token`, returning how many went.
+    /// Links deleted already are skipped.
+    fn delete_matching(&mut self, matches: &LinkPredicate<'_>) -> Result<usize> {
+        let tokens: Vec<Token> = self
+            .list(0, usize::MAX)?
+            .into_iter()
+            .filter(|(token, record)| !record.deleted && matches(token, record))
+            .map(|(token, _)| token)
+            .collect();
+        for token in &tokens {
+            self.delete_token(token.as_str())?;
+        }
+        Ok(tokens.len())
+    }
+    /// Page of stored links, oldest first, with ties broken by token. Deleted links are included.
+    fn list(&self, offset: usize, limit: usize) -> Result<Vec<(Token, LinkRecord)>>;
+    /// Appends to the token's click log, keeping only the last `MAX_CLICKS`.
+    fn record_click(&self, token: &str, click: ClickInfo) -> Result<()>;
+    /// Logged clicks for a token, newest first.
+    fn clicks(&self, token: &str) -> Result<Vec<ClickInfo>>;
+    /// Every link that isn't deleted, in `list` order.
+    fn export(&self) -> Result<Vec<(Token, LinkRecord)>> {
+        let mut links = self.list(0, usize::MAX)?;
+        links.retain(|(_, record)| !record.deleted);
+        Ok(links)
+    }
+    /// Every link as `snapshot::to_json` writes it, deleted ones included so their tokens
+    /// stay taken. Click logs are not part of it.
+    fn to_json(&self) -> Result<Vec<u8>> {
+        crate::snapshot::to_json(&self.list(0, usize::MAX)?)
+    }
+    /// Links registered by `owner` that aren't deleted, in `list` order.
+    fn list_by_owner(&self, owner: &str) -> Result<Vec<(Token, LinkRecord)>> {
+        let mut links = self.list(0, usize::MAX)?;
+        links.retain(|(_, record)| !record.deleted && record.owner.as_deref() == Some(owner));
+        Ok(links)
+    }
+    /// The `n` links with the most hits that aren't deleted, most hit first. Ties go to the
+    /// older link, as in `list` order.
+    fn top_by_hits(&self, n: usize) -> Result<Vec<(Token, LinkRecord)>> {
+        let mut links = self.export()?;
+        // Stable, so ties keep their `list` order
+        links.sort_by_key(|(_, record)| std::cmp::Reverse(record.hits.load(Ordering::Relaxed)));
+        links.truncate(n);
+        Ok(links)
+    }
+    /// Fails like `register_url_with_alias` would for `alias`, without registering anything.
+    /// Any link `link_info` knows of, even a deleted or expired one, holds its alias; stores
+    /// with reserved words check those too.
+    fn check_alias(&self, alias: &str) -> Result<()> {
+        Token::from_alias(alias)?;
+        match self.link_info(alias) {
+            Err(e) if e.downcast_ref::<StoreError>().is_none() => Ok(()),
+            _ => Err(StoreError::AliasTaken.into()),
+        }
+    }
+    /// Number of live links per owner as of `now`; links without an owner are left out.
+    /// Walks every link, so stores may want something cheaper.
+    fn usage(&self, now: SystemTime) -> Result<HashMap<String, usize>> {
+        let mut usage = HashMap::new();
+        for (_, record) in self.list(0, usize::MAX)? {
+            if record.deleted || record.is_expired(now) || record.is_exhausted() {
+                continue;
+            }
+            if let Some(owner) = record.owner {
+                *usage.entry(owner).or_default() += 1;
+            }
+        }
+        Ok(usage)
+    }
+    /// Link and hit totals as of `now`.
+    /// Walks every link, so stores may want something cheaper.
+    fn stats(&self, now: SystemTime) -> Result<StoreStats> {
+        let mut stats = StoreStats::default();
+        for (_, record) in self.list(0, usize::MAX)? {
+            stats.add(&record, now);
+        }
+        Ok(stats)
+    }
+    /// Registers each link under its original token. Tokens already in use fail
+    /// with `StoreError::AliasTaken` and keep their current target.
+    fn import(&mut self, links: Vec<(String, Url)>) -> Vec<Result<Token>> {
+        links
+            .into_iter()
+            .map(|(token, url)| self.register_url_with_alias(url, &token))
+            .collect()
+    }
 }
 
 impl StoreAccess for Store {
-    fn register_url(&mut self, url: Url) -> Result<Token> {
-        let token = Token::default();
-        self.items.insert(token.clone(), url);
+    fn register_with_options(
+        &mut self,
+        url: Url,
+        alias: Option<&str>,
+        ttl: Option<Duration>,
+        options: LinkOptions,
+    ) -> Result<Token> {
+        let token = match alias {
+            Some(alias) => {
+                self.check_alias(alias)?;
+                self.check_unique(&url)?;
+                self.canonical(Token::from_alias(alias)?)
+            }
+            None => {
+                self.check_unique(&url)?;
+                self.fresh_token()?
+            }
+        };
+        let expires_at = ttl.map(|ttl| self.clock.now() + ttl);
+        self.insert(token.clone(), url, expires_at, options);
 
-        tracing::info!("Registered a new token: {token}");
+        log_registration(&token, alias, ttl);
         Ok(token)
     }
 
+    fn register_or_get(&mut self, url: Url) -> Result<(Token, bool)> {
+        let existing = if self.dedup {
+            self.tokens_by_url.get(&url)
+        } else {
+            self.items
+                .iter()
+                .find(|(_, record)| record.url == url && record.is_shareable())
+                .map(|(token, _)| token)
+        };
+        if let Some(token) = existing {
+            return Ok((token.clone(), false));
+        }
+        let token = self.register_url(url.clone())?;
+        if self.dedup {
+            self.tokens_by_url.insert(url, token.clone());
+        }
+        Ok((token, true))
+    }
+
+    fn check_alias(&self, alias: &str) -> Result<()> {
+        let token = self.canonical(Token::from_alias(alias)?);
+        if self.token_config.is_reserved(token.as_str()) {
+            return Err(StoreError::AliasReserved.into());
+        }
+        if self.items.contains_key(&token) {
+            return Err(StoreError::AliasTaken.into());
+        }
+        Ok(())
+    }
+
     fn resolve_token(&self, token: &str) -> Result<Url> {
-        let token = Token::try_from(token)?;
-        self.items
+        let record = self.live_record(token)?;
+        record.record_hit()?;
+        Ok(record.redirect_url(&mut rand::rng()))
+    }
+
+    fn link_info(&self, token: &str) -> Result<LinkRecord> {
+        self.live_record(token).cloned()
+    }
+
+    fn owner(&self, token: &str) -> Result<Option<String>> {
+        let token = self.parse_token(token)?;
+        let record = self
+            .items
             .get(&token)
-            .cloned()
-            .ok_or_else(|| eyre!("Token not found"))
+            .ok_or_else(|| eyre!("Token not found"))?;
+        Ok(record.owner.clone())
+    }
+
+    fn delete_token(&mut self, token: &str) -> Result<()> {
+        let token = self.parse_token(token)?;
+        let record = self
+            .items
+            .get_mut(&token)
+            .ok_or_else(|| eyre!("Token not found"))?;
+        if record.deleted {
+            return Err(StoreError::Deleted.into());
+        }
+        record.deleted = true;
+        // Registering the URL again should not hand out the tombstoned token
+        if self.tokens_by_url.get(&record.url) == Some(&token) {
+            self.tokens_by_url.remove(&record.url);
+        }
+
+        tracing::info!("Deleted token: {token}");
+        Ok(())
+    }
+
+    fn restore_token(&mut self, token: &str) -> Result<()> {
+        let token = self.parse_token(token)?;
+        let record = self
+            .items
+            .get_mut(&token)
+            .ok_or_else(|| eyre!("Token not found"))?;
+        record.deleted = false;
+        // Registering the URL again may hand it out, unless a newer link took its place
+        if self.dedup && record.is_shareable() {
+            self.tokens_by_url
+                .entry(record.url.clone())
+                .or_insert(token.clone());
+        }
+
+        tracing::info!("Restored token: {token}");
+        Ok(())
+    }
+
+    fn update_url(&mut self, token: &str, new_url: Url) -> Result<()> {
+        let old_url =
+            self.update_live(token, |record| std::mem::replace(&mut record.url, new_url))?;
+        let token = self.parse_token(token)?;
+        // The token no longer stands for the old URL; dedup mints a fresh one for it
+        if self.tokens_by_url.get(&old_url) == Some(&token) {
+            self.tokens_by_url.remove(&old_url);
+        }
+
+        tracing::info!("Updated token: {token}");
+        Ok(())
+    }
+
+    fn set_password_hash(&mut self, token: &str, password_hash: Option<String>) -> Result<()> {
+        self.update_live(token, |record| record.password_hash = password_hash)
+    }
+
+    fn set_owner(&mut self, token: &str, owner: Option<String>) -> Result<()> {
+        self.update_live(token, |record| record.owner = owner)
+    }
+
+    fn set_max_hits(&mut self, token: &str, max_hits: Option<u64>) -> Result<()> {
+        self.update_live(token, |record| record.max_hits = max_hits)
+    }
+
+    fn set_destinations(&mut self, token: &str, destinations: Vec<(Url, u32)>) -> Result<()> {
+        self.update_live(token, |record| record.destinations = destinations)
+    }
+
+    fn set_utm_params(&mut self, token: &str, utm_params: Vec<(String, String)>) -> Result<()> {
+        self.update_live(token, |record| record.utm_params = utm_params)
+    }
+
+    fn purge_expired(&mut self, now: SystemTime) -> Result<usize> {
+        let expired: Vec<Token> = self
+            .items
+            .iter()
+            .filter(|(_, record)| record.is_expired(now) || record.is_exhausted())
+            .map(|(token, _)| token.clone())
+            .collect();
+        for token in &expired {
+            self.items.remove(token);
+        }
+        let mut click_log = self.click_log();
+        for token in &expired {
+            click_log.remove(token);
+        }
+        Ok(expired.len())
+    }
+
+    fn clear(&mut self) -> Result<usize> {
+        let cleared = self.items.len();
+        self.items.clear();
+        self.tokens_by_url.clear();
+        self.click_log().clear();
+        self.next_sequence = 0;
+
+        tracing::info!("Cleared {cleared} links");
+        Ok(cleared)
+    }
+
+    fn list(&self, offset: usize, limit: usize) -> Result<Vec<(Token, LinkRecord)>> {
+        let mut links: Vec<_> = self.items.iter().collect();
+        links.sort_by(|(a_token, a), (b_token, b)| {
+            (a.created_at, a_token.as_str()).cmp(&(b.created_at, b_token.as_str()))
+        });
+        Ok(links
+            .into_iter()
+            .skip(offset)
+            .take(limit)
+            .map(|(token, record)| (token.clone(), record.clone()))
+            .collect())
+    }
+
+    fn top_by_hits(&self, n: usize) -> Result<Vec<(Token, LinkRecord)>> {
+        let mut links: Vec<_> = self
+            .items
+            .iter()
+            .filter(|(_, record)| !record.deleted)
+            .collect();
+        links.sort_by(|(a_token, a), (b_token, b)| {
+            let (a_hits, b_hits) = (
+                a.hits.load(Ordering::Relaxed),
+                b.hits.load(Ordering::Relaxed),
+            );
+            (b_hits, a.created_at, a_token.as_str()).cmp(&(a_hits, b.created_at, b_token.as_str()))
+        });
+        Ok(links
+            .into_iter()
+            .take(n)
+            .map(|(token, record)| (token.clone(), record.clone()))
+            .collect())
+    }
+
+    fn record_click(&self, token: &str, click: ClickInfo) -> Result<()> {
+        let token = self.parse_token(token)?;
+        let mut click_log = self.click_log();
+        let clicks = click_log.entry(token).or_default();
+        if clicks.len() == MAX_CLICKS {
+            clicks.pop_front();
+        }
+        clicks.push_back(click);
+        Ok(())
+    }
+
+    fn clicks(&self, token: &str) -> Result<Vec<ClickInfo>> {
+        self.live_record(token)?;
+        let token = self.parse_token(token)?;
+        let click_log = self.click_log();
+        Ok(click_log
+            .get(&token)
+            .map(|clicks| clicks.iter().rev().cloned().collect())
+            .unwrap_or_default())
+    }
+
+    /// Like the default, but without cloning every record.
+    fn stats(&self, now: SystemTime) -> Result<StoreStats> {
+        let mut stats = StoreStats::default();
+        for record in self.items.values() {
+            stats.add(record, now);
+        }
+        Ok(stats)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::clock::MockClock;
+    use rand::SeedableRng;
+    use std::collections::HashSet;
 
     #[test]
     fn test_register_url() -> Result<()> {
@@ -42,7 +915,7 @@ mod tests {
         let token = store.register_url(url.clone())?;
 
         assert_eq!(store.items.len(), 1);
-        assert_eq!(store.items.get(&token), Some(&url));
+        assert_eq!(store.items.get(&token).map(|r| &r.url), Some(&url));
         Ok(())
     }
 
@@ -72,17 +945,1178 @@ mod tests {
     }
 
     #[test]
-    fn test_multiple_urls() -> Result<()> {
+    fn test_delete_token() -> Result<()> {
         let mut store = Store::default();
-        let url1 = Url::parse("https://example1.com")?;
-        let url2 = Url::parse("https://example2.com")?;
+        let url = Url::parse("https://example.com")?;
+        let token = store.register_url(url)?;
 
-        let token1 = store.register_url(url1.clone())?;
-        let token2 = store.register_url(url2.clone())?;
+        store.delete_token(token.as_str())?;
+        assert!(store.items[&token].deleted);
+        let err = store.resolve_token(token.as_str()).unwrap_err();
+        assert_eq!(err.downcast_ref::<StoreError>(), Some(&StoreError::Deleted));
+        let err = store.delete_token(token.as_str()).unwrap_err();
+        assert_eq!(err.downcast_ref::<StoreError>(), Some(&StoreError::Deleted));
+        Ok(())
+    }
 
-        assert_ne!(token1, token2);
-        assert_eq!(store.resolve_token(token1.as_str())?, url1);
-        assert_eq!(store.resolve_token(token2.as_str())?, url2);
+    #[test]
+    fn test_restore_token() -> Result<()> {
+        let mut store = Store::default();
+        let url = Url::parse("https://example.com")?;
+        let token = store.register_url(url.clone())?;
+
+        store.delete_token(token.as_str())?;
+        store.restore_token(token.as_str())?;
+        assert_eq!(store.resolve_token(token.as_str())?, url);
+        // Restoring a live link changes nothing
+        store.restore_token(token.as_str())?;
+        assert_eq!(store.resolve_token(token.as_str())?, url);
+        assert!(store.restore_token("abc123").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_deleted_tokens_are_not_reissued() -> Result<()> {
+        let mut store = Store {
+            token_generator: Box::new(|_| Token::try_from("abc123")),
+            ..Store::default()
+        };
+        store.register_url(Url::parse("https://example.com")?)?;
+        store.delete_token("abc123")?;
+
+        assert!(store
+            .register_url(Url::parse("https://other.com")?)
+            .is_err());
+        assert!(store
+            .register_url_with_alias(Url::parse("https://other.com")?, "abc123")
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_delete_nonexistent_token() {
+        let mut store = Store::default();
+        let result = store.delete_token("123456");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_register_url_with_alias() -> Result<()> {
+        let mut store = Store::default();
+        let url = Url::parse("https://example.com")?;
+        let token = store.register_url_with_alias(url.clone(), "my-launch")?;
+
+        assert_eq!(token.as_str(), "my-launch");
+        assert_eq!(store.resolve_token("my-launch")?, url);
+        Ok(())
+    }
+
+    #[test]
+    fn test_register_url_with_taken_alias() -> Result<()> {
+        let mut store = Store::default();
+        store.register_url_with_alias(Url::parse("https://example1.com")?, "my-launch")?;
+
+        let result =
+            store.register_url_with_alias(Url::parse("https://example2.com")?, "my-launch");
+        let err = result.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<StoreError>(),
+            Some(&StoreError::AliasTaken)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_register_url_with_invalid_alias() -> Result<()> {
+        let mut store = Store::default();
+        let result = store.register_url_with_alias(Url::parse("https://example.com")?, "my launch");
+        assert!(result.is_err());
+        assert!(store.items.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_unexpired_token() -> Result<()> {
+        let clock = Arc::new(MockClock::new(SystemTime::UNIX_EPOCH));
+        let mut store = Store {
+            clock: clock.clone(),
+            ..Store::default()
+        };
+        let url = Url::parse("https://example.com")?;
+        let token = store.register_url_with_ttl(url.clone(), Duration::from_secs(60))?;
+
+        clock.advance(Duration::from_secs(59));
+        assert_eq!(store.resolve_token(token.as_str())?, url);
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_expired_token() -> Result<()> {
+        let clock = Arc::new(MockClock::new(SystemTime::UNIX_EPOCH));
+        let mut store = Store {
+            clock: clock.clone(),
+            ..Store::default()
+        };
+        let url = Url::parse("https://example.com")?;
+        let token = store.register_url_with_ttl(url, Duration::from_secs(60))?;
+
+        clock.advance(Duration::from_secs(60));
+        let err = store.resolve_token(token.as_str()).unwrap_err();
+        assert_eq!(err.downcast_ref::<StoreError>(), Some(&StoreError::Expired));
+        Ok(())
+    }
+
+    #[test]
+    fn test_created_at_defaults_to_now() -> Result<()> {
+        let mut store = Store::default();
+        let before = SystemTime::now();
+        let token = store.register_url(Url::parse("https://example.com")?)?;
+
+        let created_at = store.link_info(token.as_str())?.created_at;
+        assert!(created_at >= before);
+        assert!(created_at.duration_since(before)? < Duration::from_secs(5));
+        Ok(())
+    }
+
+    #[test]
+    fn test_link_info() -> Result<()> {
+        let clock = Arc::new(MockClock::new(SystemTime::UNIX_EPOCH));
+        let mut store = Store {
+            clock: clock.clone(),
+            ..Store::default()
+        };
+        let url = Url::parse("https://example.com")?;
+        let token = store.register_url(url.clone())?;
+        clock.advance(Duration::from_secs(5));
+
+        let record = store.link_info(token.as_str())?;
+        assert_eq!(record.url, url);
+        assert_eq!(record.created_at, SystemTime::UNIX_EPOCH);
+        assert_eq!(record.hits.load(Ordering::Relaxed), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_record_agrees_with_resolve_token() -> Result<()> {
+        let clock = Arc::new(MockClock::new(SystemTime::UNIX_EPOCH));
+        let mut store = Store {
+            clock: clock.clone(),
+            ..Store::default()
+        };
+        let permanent = store.register_url(Url::parse("https://example.com/a")?)?;
+        let expiring = store.register_url_with_ttl(
+            Url::parse("https://example.com/b")?,
+            Duration::from_secs(10),
+        )?;
+        let deleted = store.register_url(Url::parse("https://example.com/c")?)?;
+        store.delete_token(deleted.as_str())?;
+
+        for token in [&permanent, &expiring] {
+            let record = store.get_record(token.as_str())?;
+            assert_eq!(store.resolve_token(token.as_str())?, record.url);
+            assert_eq!(store.get_record(token.as_str())?.hits.into_inner(), 1);
+        }
+        assert_eq!(
+            store.get_record(expiring.as_str())?.expires_at,
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(10))
+        );
+
+        clock.advance(Duration::from_secs(10));
+        for token in [&expiring, &deleted] {
+            assert!(store.get_record(token.as_str()).is_err());
+            assert!(store.resolve_token(token.as_str()).is_err());
+        }
+        assert!(store.get_record("zzzzzz").is_err());
+        assert!(store.resolve_token("zzzzzz").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_counts_hits() -> Result<()> {
+        let mut store = Store::default();
+        let token = store.register_url(Url::parse("https://example.com")?)?;
+
+        store.resolve_token(token.as_str())?;
+        store.link_info(token.as_str())?;
+        let record = store.link_info(token.as_str())?;
+        assert_eq!(record.hits.load(Ordering::Relaxed), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_hit_count() -> Result<()> {
+        let mut store = Store::default();
+        let token = store.register_url(Url::parse("https://example.com")?)?;
+
+        for _ in 0..3 {
+            store.resolve_token(token.as_str())?;
+        }
+        assert_eq!(store.hit_count(token.as_str())?, 3);
+        assert!(store.hit_count("123456").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_dedup_returns_existing_token() -> Result<()> {
+        let mut store = Store {
+            dedup: true,
+            ..Store::default()
+        };
+        let url = Url::parse("https://example.com")?;
+
+        let (token, created) = store.register_or_get(url.clone())?;
+        assert!(created);
+        assert_eq!(store.register_or_get(url.clone())?, (token.clone(), false));
+        assert_eq!(store.items.len(), 1);
+        // Plain registrations still mint a token of their own
+        assert_ne!(store.register_url(url)?, token);
+        Ok(())
+    }
+
+    #[test]
+    fn test_register_or_get() -> Result<()> {
+        let mut store = Store::default();
+        let url = Url::parse("https://example.com")?;
+
+        let (token, created) = store.register_or_get(url.clone())?;
+        assert!(created);
+        assert_eq!(store.register_or_get(url.clone())?, (token.clone(), false));
+        let (_, created) = store.register_or_get(Url::parse("https://other.com")?)?;
+        assert!(created);
+
+        // Expiring and deleted links don't count
+        store.register_url_with_ttl(Url::parse("https://brief.com")?, Duration::from_secs(60))?;
+        assert!(store.register_or_get(Url::parse("https://brief.com")?)?.1);
+        store.delete_token(token.as_str())?;
+        let (fresh, created) = store.register_or_get(url)?;
+        assert!(created);
+        assert_ne!(fresh, token);
+        Ok(())
+    }
+
+    #[test]
+    fn test_top_by_hits() -> Result<()> {
+        let clock = Arc::new(MockClock::new(SystemTime::UNIX_EPOCH));
+        let mut store = Store {
+            clock: clock.clone(),
+            ..Store::default()
+        };
+        let mut tokens = Vec::new();
+        for (url, hits) in [
+            ("https://quiet.com", 0),
+            ("https://popular.com", 3),
+            ("https://older.com", 1),
+            ("https://newer.com", 1),
+            ("https://gone.com", 5),
+        ] {
+            let token = store.register_url(Url::parse(url)?)?;
+            for _ in 0..hits {
+                store.resolve_token(token.as_str())?;
+            }
+            tokens.push(token);
+            clock.advance(Duration::from_secs(1));
+        }
+        store.delete_token(tokens[4].as_str())?;
+
+        let top: Vec<_> = store
+            .top_by_hits(3)?
+            .into_iter()
+            .map(|(token, record)| (token, record.hits.load(Ordering::Relaxed)))
+            .collect();
+        assert_eq!(
+            top,
+            [
+                (tokens[1].clone(), 3),
+                (tokens[2].clone(), 1),
+                (tokens[3].clone(), 1)
+            ]
+        );
+        assert_eq!(store.top_by_hits(10)?.len(), 4);
+        assert!(store.top_by_hits(0)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_unique_urls_rejects_duplicates() -> Result<()> {
+        let mut store = Store::default().with_unique_urls(true);
+        let url = Url::parse("https://example.com")?;
+        let token = store.register_url(url.clone())?;
+
+        for result in [
+            store.register_url(url.clone()),
+            store.register_url_with_alias(url.clone(), "launch"),
+            store.register_url_with_ttl(url.clone(), Duration::from_secs(60)),
+        ] {
+            assert_eq!(
+                result.unwrap_err().downcast_ref::<StoreError>(),
+                Some(&StoreError::DuplicateUrl)
+            );
+        }
+
+        // A deleted link no longer claims its URL
+        store.delete_token(token.as_str())?;
+        assert!(store.register_url(url).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_register_or_get_skips_owned_and_protected_links() -> Result<()> {
+        for dedup in [false, true] {
+            let mut store = Store::default().with_dedup(dedup);
+            let url = Url::parse("https://example.com")?;
+
+            let (owned, _) = store.register_or_get(url.clone())?;
+            store.set_owner(owned.as_str(), Some("alpha".into()))?;
+            let (protected, created) = store.register_or_get(url.clone())?;
+            assert!(created);
+            store.set_password_hash(protected.as_str(), Some("hash".into()))?;
+            let (plain, created) = store.register_or_get(url.clone())?;
+            assert!(created);
+            assert_eq!(store.register_or_get(url)?, (plain, false));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_with_dedup_indexes_existing_links() -> Result<()> {
+        let mut source = Store::default();
+        let url = Url::parse("https://example.com")?;
+        let token = source.register_url(url.clone())?;
+
+        let mut store = Store::default()
+            .with_links(source.list(0, 10)?)
+            .with_dedup(true);
+        assert_eq!(store.tokens_by_url.get(&url), Some(&token));
+        assert_eq!(store.register_or_get(url)?, (token, false));
+        Ok(())
+    }
+
+    #[test]
+    fn test_no_dedup_mints_new_tokens() -> Result<()> {
+        let mut store = Store::default();
+        let url = Url::parse("https://example.com")?;
+
+        let token1 = store.register_url(url.clone())?;
+        let token2 = store.register_url(url)?;
+        assert_ne!(token1, token2);
+        assert!(store.tokens_by_url.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_dedup_forgets_deleted_tokens() -> Result<()> {
+        let mut store = Store {
+            dedup: true,
+            ..Store::default()
+        };
+        let url = Url::parse("https://example.com")?;
+
+        let (token1, _) = store.register_or_get(url.clone())?;
+        store.delete_token(token1.as_str())?;
+        let (token2, _) = store.register_or_get(url)?;
+        assert_ne!(token1, token2);
+        assert!(store.resolve_token(token2.as_str()).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_dedup_indexes_restored_tokens() -> Result<()> {
+        let mut store = Store::default().with_dedup(true);
+        let url = Url::parse("https://example.com")?;
+
+        let (token, _) = store.register_or_get(url.clone())?;
+        store.delete_token(token.as_str())?;
+        store.restore_token(token.as_str())?;
+        assert_eq!(store.register_or_get(url)?, (token, false));
+        Ok(())
+    }
+
+    #[test]
+    fn test_register_batch() -> Result<()> {
+        let mut store = Store::default();
+        let urls = vec![
+            Url::parse("https://example1.com")?,
+            Url::parse("https://example2.com")?,
+        ];
+
+        let tokens = store.register_batch(urls.clone(), LinkOptions::default());
+        assert_eq!(tokens.len(), 2);
+        for (token, url) in tokens.into_iter().zip(urls) {
+            assert_eq!(store.resolve_token(token?.as_str())?, url);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_register_batch_with_owner() -> Result<()> {
+        let mut store = Store::default();
+        let urls = vec![
+            Url::parse("https://example1.com")?,
+            Url::parse("https://example2.com")?,
+        ];
+        let options = LinkOptions {
+            owner: Some("alpha".to_string()),
+            ..LinkOptions::default()
+        };
+
+        for token in store.register_batch(urls, options) {
+            assert_eq!(store.owner(token?.as_str())?.as_deref(), Some("alpha"));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_register_url_with_reserved_alias() -> Result<()> {
+        let mut store = Store::default();
+
+        let result = store.register_url_with_alias(Url::parse("https://example.com")?, "Healthz");
+        assert_eq!(
+            result.unwrap_err().downcast_ref::<StoreError>(),
+            Some(&StoreError::AliasReserved)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_alias() -> Result<()> {
+        let mut store = Store::default();
+        store.register_url_with_alias(Url::parse("https://example.com")?, "launch")?;
+        store.register_url_with_alias(Url::parse("https://deleted.com")?, "gone")?;
+        store.delete_token("gone")?;
+
+        assert!(store.check_alias("promo").is_ok());
+        for (alias, expected) in [
+            ("launch", StoreError::AliasTaken),
+            ("gone", StoreError::AliasTaken),
+            ("Healthz", StoreError::AliasReserved),
+        ] {
+            let result = store.check_alias(alias);
+            assert_eq!(result.unwrap_err().downcast_ref(), Some(&expected));
+        }
+        assert!(store.check_alias("my launch!").is_err());
+        // Nothing was registered along the way
+        assert!(store.link_info("promo").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_register_url_skips_reserved_tokens() -> Result<()> {
+        let mut candidates = vec![Token::try_from("abc123")?, Token::from_alias("metrics")?];
+        let mut store = Store {
+            token_generator: Box::new(move |_| Ok(candidates.pop().unwrap())),
+            ..Store::default()
+        };
+
+        let token = store.register_url(Url::parse("https://example.com")?)?;
+        assert_eq!(token.as_str(), "abc123");
+        Ok(())
+    }
+
+    #[test]
+    fn test_register_url_retries_on_collision() -> Result<()> {
+        // Popped from the back: the second registration collides once
+        let mut candidates = vec![
+            Token::try_from("def456")?,
+            Token::try_from("abc123")?,
+            Token::try_from("abc123")?,
+        ];
+        let mut store = Store {
+            token_generator: Box::new(move |_| Ok(candidates.pop().unwrap())),
+            ..Store::default()
+        };
+        let url1 = Url::parse("https://example1.com")?;
+        let url2 = Url::parse("https://example2.com")?;
+
+        let token1 = store.register_url(url1.clone())?;
+        let token2 = store.register_url(url2.clone())?;
+        assert_eq!(token1.as_str(), "abc123");
+        assert_eq!(token2.as_str(), "def456");
+        assert_eq!(store.resolve_token("abc123")?, url1);
+        assert_eq!(store.resolve_token("def456")?, url2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_list_by_owner() -> Result<()> {
+        let mut store = Store::default();
+        let first = store.register_url(Url::parse("https://first.com")?)?;
+        let second = store.register_url(Url::parse("https://second.com")?)?;
+        let deleted = store.register_url(Url::parse("https://deleted.com")?)?;
+        let other = store.register_url(Url::parse("https://other.com")?)?;
+        store.register_url(Url::parse("https://unowned.com")?)?;
+        for token in [&first, &second, &deleted] {
+            store.set_owner(token.as_str(), Some("alpha".to_string()))?;
+        }
+        store.set_owner(other.as_str(), Some("beta".to_string()))?;
+        store.delete_token(deleted.as_str())?;
+
+        let tokens = |owner| -> Result<HashSet<Token>> {
+            Ok(store
+                .list_by_owner(owner)?
+                .into_iter()
+                .map(|(token, _)| token)
+                .collect())
+        };
+        assert_eq!(tokens("alpha")?, HashSet::from([first, second]));
+        assert_eq!(tokens("beta")?, HashSet::from([other]));
+        assert!(tokens("gamma")?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_json_round_trip() -> Result<()> {
+        let mut store = Store::default();
+        let token = store.register_url(Url::parse("https://example.com")?)?;
+        store
+            .register_url_with_ttl(Url::parse("https://expiring.com")?, Duration::from_secs(60))?;
+        store.register_url_with_alias(Url::parse("https://alias.com")?, "launch")?;
+        store.resolve_token(token.as_str())?;
+        store.set_owner(token.as_str(), Some("alpha".to_string()))?;
+        store.set_max_hits(token.as_str(), Some(10))?;
+        store.set_destinations(token.as_str(), vec![(Url::parse("https://b.com")?, 2)])?;
+        store.set_utm_params(token.as_str(), vec![("utm_source".into(), "news".into())])?;
+        store.delete_token("launch")?;
+
+        let restored = Store::from_json(&store.to_json()?, TokenConfig::default())?;
+        assert_eq!(restored.items.len(), 3);
+        for (token, record) in &store.items {
+            let restored = &restored.items[token];
+            assert_eq!(restored.url, record.url);
+            assert_eq!(restored.created_at, record.created_at);
+            assert_eq!(restored.expires_at, record.expires_at);
+            assert_eq!(
+                restored.hits.load(Ordering::Relaxed),
+                record.hits.load(Ordering::Relaxed)
+            );
+            assert_eq!(restored.deleted, record.deleted);
+            assert_eq!(restored.owner, record.owner);
+            assert_eq!(restored.max_hits, record.max_hits);
+            assert_eq!(restored.destinations, record.destinations);
+            assert_eq!(restored.utm_params, record.utm_params);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_export_import_round_trip() -> Result<()> {
+        let mut store = Store::default();
+        let mut tokens =
+            vec![store.register_url_with_alias(Url::parse("https://alias.com")?, "launch")?];
+        for i in 0..3 {
+            tokens.push(store.register_url(Url::parse(&format!("https://example{i}.com"))?)?);
+        }
+
+        let exported: Vec<(String, Url)> = store
+            .export()?
+            .into_iter()
+            .map(|(token, record)| (token.to_string(), record.url))
+            .collect();
+        assert_eq!(exported.len(), tokens.len());
+
+        let mut restored = Store::default();
+        let results = restored.import(exported.clone());
+        assert!(results.iter().all(Result::is_ok));
+        for token in &tokens {
+            assert_eq!(
+                restored.resolve_token(token.as_str())?,
+                store.resolve_token(token.as_str())?
+            );
+        }
+
+        // A second import leaves every existing link in place
+        let results = restored.import(vec![(
+            "launch".to_string(),
+            Url::parse("https://other.com")?,
+        )]);
+        assert_eq!(
+            results[0]
+                .as_ref()
+                .unwrap_err()
+                .downcast_ref::<StoreError>(),
+            Some(&StoreError::AliasTaken)
+        );
+        assert_eq!(
+            restored.resolve_token("launch")?.as_str(),
+            "https://alias.com/"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_sequential_tokens() -> Result<()> {
+        let mut store = Store::new(TokenConfig {
+            strategy: TokenStrategy::Sequential,
+            ..Default::default()
+        });
+        store.register_url_with_alias(Url::parse("https://alias.com")?, "c")?;
+
+        let tokens = (0..200)
+            .map(|i| store.register_url(Url::parse(&format!("https://example{i}.com"))?))
+            .collect::<Result<Vec<_>>>()?;
+        let codes: Vec<&str> = tokens.iter().map(Token::as_str).collect();
+        assert_eq!(codes[..4], ["a", "b", "d", "e"]);
+        assert_eq!(codes[60], "9");
+        assert_eq!(codes[61], "aa");
+        // Shorter codes come first, so (length, position) never goes backwards
+        assert!(codes.windows(2).all(|w| w[0].len() <= w[1].len()));
+        let unique: std::collections::HashSet<&str> = codes.iter().copied().collect();
+        assert_eq!(unique.len(), codes.len());
+        assert_eq!(store.resolve_token("a")?.as_str(), "https://example0.com/");
+        assert_eq!(store.resolve_token("c")?.as_str(), "https://alias.com/");
+        Ok(())
+    }
+
+    #[test]
+    fn test_sequential_tokens_skip_reserved() -> Result<()> {
+        let mut store = Store::new(TokenConfig {
+            strategy: TokenStrategy::Sequential,
+            reserved: HashSet::from(["b".to_string()]),
+            ..Default::default()
+        });
+
+        let token1 = store.register_url(Url::parse("https://example1.com")?)?;
+        let token2 = store.register_url(Url::parse("https://example2.com")?)?;
+        assert_eq!(token1.as_str(), "a");
+        assert_eq!(token2.as_str(), "c");
+        Ok(())
+    }
+
+    #[test]
+    fn test_sequential_tokens_skip_blocked() -> Result<()> {
+        let mut store = Store::new(TokenConfig {
+            strategy: TokenStrategy::Sequential,
+            blocklist: vec!["b".to_string()],
+            ..Default::default()
+        });
+
+        let token1 = store.register_url(Url::parse("https://example1.com")?)?;
+        let token2 = store.register_url(Url::parse("https://example2.com")?)?;
+        assert_eq!(token1.as_str(), "a");
+        assert_eq!(token2.as_str(), "c");
+        Ok(())
+    }
+
+    #[test]
+    fn test_register_url_gives_up_after_repeated_collisions() -> Result<()> {
+        let mut store = Store {
+            token_generator: Box::new(|_| Token::try_from("abc123")),
+            ..Store::default()
+        };
+        let url = Url::parse("https://example.com")?;
+        store.register_url(url.clone())?;
+
+        assert!(store
+            .register_url(Url::parse("https://other.com")?)
+            .is_err());
+        assert_eq!(store.resolve_token("abc123")?, url);
+        Ok(())
+    }
+
+    #[test]
+    fn test_multiple_urls() -> Result<()> {
+        let mut store = Store::default();
+        let url1 = Url::parse("https://example1.com")?;
+        let url2 = Url::parse("https://example2.com")?;
+
+        let token1 = store.register_url(url1.clone())?;
+        let token2 = store.register_url(url2.clone())?;
+
+        assert_ne!(token1, token2);
+        assert_eq!(store.resolve_token(token1.as_str())?, url1);
+        assert_eq!(store.resolve_token(token2.as_str())?, url2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_custom_token_length() -> Result<()> {
+        let mut store = Store::new(TokenConfig {
+            length: 10,
+            ..Default::default()
+        });
+        let url = Url::parse("https://example.com")?;
+        let token = store.register_url(url.clone())?;
+
+        assert_eq!(token.as_str().len(), 10);
+        assert_eq!(store.resolve_token(token.as_str())?, url);
+        assert!(store.resolve_token("abc123").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_list_pages_in_creation_order() -> Result<()> {
+        let clock = Arc::new(MockClock::new(SystemTime::UNIX_EPOCH));
+        let mut store = Store {
+            clock: clock.clone(),
+            ..Store::default()
+        };
+        let mut tokens = Vec::new();
+        for i in 0..5 {
+            tokens.push(store.register_url(Url::parse(&format!("https://example{i}.com"))?)?);
+            clock.advance(Duration::from_secs(1));
+        }
+
+        let listed = |offset, limit| -> Result<Vec<Token>> {
+            Ok(store
+                .list(offset, limit)?
+                .into_iter()
+                .map(|(token, _)| token)
+                .collect())
+        };
+        assert_eq!(listed(0, 2)?, tokens[0..2]);
+        assert_eq!(listed(2, 2)?, tokens[2..4]);
+        assert_eq!(listed(4, 2)?, tokens[4..]);
+        assert!(listed(5, 2)?.is_empty());
+        assert_eq!(listed(0, 10)?, tokens);
+        assert!(listed(0, 0)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_list_empty_store() -> Result<()> {
+        let store = Store::default();
+        assert!(store.list(0, 10)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_click_log_is_bounded() -> Result<()> {
+        let mut store = Store::default();
+        let token = store.register_url(Url::parse("https://example.com")?)?;
+        let click = |i: usize| ClickInfo {
+            at: SystemTime::UNIX_EPOCH + Duration::from_secs(i as u64),
+            referer: None,
+            user_agent: Some(format!("agent-{i}")),
+        };
+
+        for i in 0..MAX_CLICKS + 5 {
+            store.record_click(token.as_str(), click(i))?;
+        }
+
+        let clicks = store.clicks(token.as_str())?;
+        assert_eq!(clicks.len(), MAX_CLICKS);
+        assert_eq!(clicks.first(), Some(&click(MAX_CLICKS + 4)));
+        assert_eq!(clicks.last(), Some(&click(5)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_click_log_survives_poisoning() -> Result<()> {
+        let mut store = Store::default();
+        let token = store.register_url(Url::parse("https://example.com")?)?;
+        let poisoned = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _click_log = store.click_log.lock().unwrap();
+            panic!("panicked while holding the click log");
+        }));
+        assert!(poisoned.is_err() && store.click_log.is_poisoned());
+
+        let click = ClickInfo {
+            at: SystemTime::UNIX_EPOCH,
+            referer: None,
+            user_agent: None,
+        };
+        store.record_click(token.as_str(), click.clone())?;
+        assert_eq!(store.clicks(token.as_str())?, vec![click]);
+        store.delete_token(token.as_str())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_clicks_of_unknown_token() {
+        let store = Store::default();
+        assert!(store.clicks("abc123").is_err());
+    }
+
+    #[test]
+    fn test_tokens_are_case_sensitive_by_default() -> Result<()> {
+        let mut store = Store::default();
+        store.register_url_with_alias(Url::parse("https://example.com")?, "my-launch")?;
+
+        assert!(store.resolve_token("My-Launch").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_case_insensitive_tokens() -> Result<()> {
+        let mut store = Store {
+            case_insensitive: true,
+            ..Store::default()
+        };
+        let url = Url::parse("https://example.com")?;
+        let alias = store.register_url_with_alias(url.clone(), "My-Launch")?;
+        let token = store.register_url(url.clone())?;
+
+        assert_eq!(alias.as_str(), "my-launch");
+        assert_eq!(token, token.to_ascii_lowercase());
+        assert_eq!(store.resolve_token("MY-LAUNCH")?, url);
+        assert_eq!(
+            store.resolve_token(&token.as_str().to_ascii_uppercase())?,
+            url
+        );
+        assert!(store.register_url_with_alias(url, "my-LAUNCH").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_with_case_insensitive_tokens() -> Result<()> {
+        let url = Url::parse("https://example.com")?;
+        let mut loaded = Store::default();
+        loaded.register_url_with_alias(url.clone(), "Loaded-Launch")?;
+
+        let mut store = Store::default()
+            .with_links(loaded.list(0, usize::MAX)?)
+            .with_case_insensitive_tokens(true);
+        let token = store.register_url_with_alias(url.clone(), "Fresh-Launch")?;
+
+        assert_eq!(token.as_str(), "fresh-launch");
+        assert_eq!(store.resolve_token("FRESH-launch")?, url);
+        assert_eq!(store.resolve_token("loaded-LAUNCH")?, url);
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_url() -> Result<()> {
+        let mut store = Store::default();
+        let token = store.register_url(Url::parse("https://example1.com")?)?;
+        store.resolve_token(token.as_str())?;
+
+        let new_url = Url::parse("https://example2.com")?;
+        store.update_url(token.as_str(), new_url.clone())?;
+        assert_eq!(store.resolve_token(token.as_str())?, new_url);
+        assert_eq!(store.hit_count(token.as_str())?, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_password_hash() -> Result<()> {
+        let mut store = Store::default();
+        let token = store.register_url(Url::parse("https://example.com")?)?;
+
+        store.set_password_hash(token.as_str(), Some("$argon2id$hash".to_string()))?;
+        assert_eq!(
+            store.link_info(token.as_str())?.password_hash.as_deref(),
+            Some("$argon2id$hash")
+        );
+        store.set_password_hash(token.as_str(), None)?;
+        assert_eq!(store.link_info(token.as_str())?.password_hash, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_register_with_options() -> Result<()> {
+        let mut store = Store::default();
+        let options = LinkOptions {
+            password_hash: Some("$argon2id$hash".to_string()),
+            ..LinkOptions::default()
+        };
+        let token = store.register_with_options(
+            Url::parse("https://example.com")?,
+            Some("protected"),
+            None,
+            options,
+        )?;
+
+        assert_eq!(token.as_str(), "protected");
+        assert_eq!(
+            store.link_info(token.as_str())?.password_hash.as_deref(),
+            Some("$argon2id$hash")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_purge_expired() -> Result<()> {
+        let clock = Arc::new(MockClock::new(SystemTime::UNIX_EPOCH));
+        let mut store = Store {
+            clock: clock.clone(),
+            ..Store::default()
+        };
+        let expiring = store
+            .register_url_with_ttl(Url::parse("https://example1.com")?, Duration::from_secs(60))?;
+        let permanent = store.register_url(Url::parse("https://example2.com")?)?;
+
+        assert_eq!(store.purge_expired(clock.now())?, 0);
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(store.purge_expired(clock.now())?, 1);
+        assert!(!store.items.contains_key(&expiring));
+        assert!(store.items.contains_key(&permanent));
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_hits() -> Result<()> {
+        let mut store = Store {
+            dedup: true,
+            ..Store::default()
+        };
+        let url = Url::parse("https://example.com")?;
+        let (token, _) = store.register_or_get(url.clone())?;
+        store.set_max_hits(token.as_str(), Some(2))?;
+
+        store.resolve_token(token.as_str())?;
+        store.resolve_token(token.as_str())?;
+        let err = store.resolve_token(token.as_str()).unwrap_err();
+        assert_eq!(err.downcast_ref(), Some(&StoreError::Exhausted));
+        let err = store.link_info(token.as_str()).unwrap_err();
+        assert_eq!(err.downcast_ref(), Some(&StoreError::Exhausted));
+        assert_eq!(store.items[&token].hits.load(Ordering::Relaxed), 2);
+
+        // Dedup no longer hands out the used up link
+        assert!(store.register_or_get(url)?.1);
+        assert_eq!(store.purge_expired(SystemTime::now())?, 1);
+        assert!(!store.items.contains_key(&token));
+        Ok(())
+    }
+
+    #[test]
+    fn test_concurrent_resolves_count_every_hit() -> Result<()> {
+        let mut store = Store::default();
+        let unlimited = store.register_url(Url::parse("https://example.com")?)?;
+        let limited = store.register_url(Url::parse("https://limited.com")?)?;
+        store.set_max_hits(limited.as_str(), Some(1_000))?;
+
+        // Hits are atomics, so resolving through a shared `&Store` is enough
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    for _ in 0..500 {
+                        store.resolve_token(unlimited.as_str()).unwrap();
+                        let _ = store.resolve_token(limited.as_str());
+                    }
+                });
+            }
+        });
+
+        assert_eq!(store.hit_count(unlimited.as_str())?, 4_000);
+        assert_eq!(store.items[&limited].hits.load(Ordering::Relaxed), 1_000);
+        Ok(())
+    }
+
+    #[test]
+    fn test_destinations_split_by_weight() -> Result<()> {
+        let mut store = Store {
+            dedup: true,
+            ..Store::default()
+        };
+        let url = Url::parse("https://example.com")?;
+        let (token, _) = store.register_or_get(url.clone())?;
+        let (a, b) = (Url::parse("https://a.com")?, Url::parse("https://b.com")?);
+        store.set_destinations(token.as_str(), vec![(a.clone(), 3), (b.clone(), 1)])?;
+
+        let record = store.link_info(token.as_str())?;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+        let picks_of_a = (0..10_000)
+            .filter(|_| record.pick_url(&mut rng) == &a)
+            .count();
+        assert!((7_200..7_800).contains(&picks_of_a), "{picks_of_a}");
+
+        let resolved = store.resolve_token(token.as_str())?;
+        assert!(resolved == a || resolved == b);
+        assert!(store.register_or_get(url.clone())?.1);
+
+        store.set_destinations(token.as_str(), Vec::new())?;
+        assert_eq!(store.resolve_token(token.as_str())?, url);
+        Ok(())
+    }
+
+    #[test]
+    fn test_utm_params_merge_into_query() -> Result<()> {
+        let mut store = Store {
+            dedup: true,
+            ..Store::default()
+        };
+        let url = Url::parse("https://example.com/page?ref=home&utm_medium=site")?;
+        let (token, _) = store.register_or_get(url.clone())?;
+        store.set_utm_params(
+            token.as_str(),
+            vec![
+                ("utm_medium".into(), "email".into()),
+                ("utm_source".into(), "foo".into()),
+            ],
+        )?;
+
+        // The target's own `utm_medium` wins
+        assert_eq!(
+            store.resolve_token(token.as_str())?.as_str(),
+            "https://example.com/page?ref=home&utm_medium=site&utm_source=foo"
+        );
+        assert_eq!(store.link_info(token.as_str())?.url, url);
+        assert!(store.register_or_get(url.clone())?.1);
+
+        store.set_utm_params(token.as_str(), Vec::new())?;
+        assert_eq!(store.resolve_token(token.as_str())?, url);
+        Ok(())
+    }
+
+    #[test]
+    fn test_delete_matching() -> Result<()> {
+        let clock = Arc::new(MockClock::new(SystemTime::UNIX_EPOCH));
+        let mut store = Store {
+            clock: clock.clone(),
+            ..Store::default()
+        };
+        let mut tokens = Vec::new();
+        for day in 0..4 {
+            let url = Url::parse(&format!("https://example.com/{day}"))?;
+            tokens.push(store.register_url(url)?);
+            clock.advance(Duration::from_secs(24 * 60 * 60));
+        }
+        store.delete_token(tokens[0].as_str())?;
+
+        // Created on days 0 to 2, of which day 0 is deleted already
+        let cutoff = SystemTime::UNIX_EPOCH + Duration::from_secs(3 * 24 * 60 * 60);
+        assert_eq!(
+            store.delete_matching(&|_, record| record.created_at < cutoff)?,
+            2
+        );
+        for token in &tokens[..3] {
+            let err = store.resolve_token(token.as_str()).unwrap_err();
+            assert_eq!(err.downcast_ref(), Some(&StoreError::Deleted));
+        }
+        store.resolve_token(tokens[3].as_str())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_stats() -> Result<()> {
+        let clock = Arc::new(MockClock::new(SystemTime::UNIX_EPOCH));
+        let mut store = Store {
+            clock: clock.clone(),
+            ..Store::default()
+        };
+        let permanent = store.register_url(Url::parse("https://example.com")?)?;
+        store
+            .register_url_with_ttl(Url::parse("https://expiring.com")?, Duration::from_secs(60))?;
+        let deleted = store.register_url(Url::parse("https://deleted.com")?)?;
+        store.resolve_token(permanent.as_str())?;
+        store.resolve_token(permanent.as_str())?;
+        store.resolve_token(deleted.as_str())?;
+        store.delete_token(deleted.as_str())?;
+        clock.advance(Duration::from_secs(60));
+
+        assert_eq!(
+            store.stats(clock.now())?,
+            StoreStats {
+                links: 2,
+                hits: 3,
+                expired: 1,
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_with_links() -> Result<()> {
+        let mut source = Store::default();
+        let token = source.register_url(Url::parse("https://example.com")?)?;
+        source.resolve_token(token.as_str())?;
+
+        let mut store = Store::default().with_links(source.list(0, 10)?);
+        assert_eq!(store.hit_count(token.as_str())?, 1);
+        assert_ne!(store.register_url(Url::parse("https://other.com")?)?, token);
+        Ok(())
+    }
+
+    #[test]
+    fn test_clear() -> Result<()> {
+        let mut store = Store {
+            dedup: true,
+            ..Store::default()
+        };
+        let url = Url::parse("https://example.com")?;
+        let (token, _) = store.register_or_get(url.clone())?;
+        store.register_url_with_alias(Url::parse("https://alias.com")?, "launch")?;
+        store.delete_token("launch")?;
+
+        assert_eq!(store.clear()?, 2);
+        assert!(store.list(0, 10)?.is_empty());
+        assert!(store.resolve_token(token.as_str()).is_err());
+        assert!(store.click_log().is_empty());
+        assert!(store.register_or_get(url)?.1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_usage_counts_live_owned_links() -> Result<()> {
+        let clock = Arc::new(MockClock::new(SystemTime::UNIX_EPOCH));
+        let mut store = Store {
+            clock: clock.clone(),
+            ..Store::default()
+        };
+        let mut register = |url: &str, owner: Option<&str>| -> Result<Token> {
+            let token = store.register_url_with_ttl(Url::parse(url)?, Duration::from_secs(60))?;
+            store.set_owner(token.as_str(), owner.map(String::from))?;
+            Ok(token)
+        };
+        register("https://example1.com", Some("alpha"))?;
+        let deleted = register("https://example2.com", Some("alpha"))?;
+        register("https://example3.com", Some("beta"))?;
+        register("https://example4.com", None)?;
+        store.delete_token(deleted.as_str())?;
+
+        let usage = store.usage(clock.now())?;
+        assert_eq!(
+            usage,
+            HashMap::from([("alpha".to_string(), 1), ("beta".to_string(), 1)])
+        );
+        assert!(store
+            .usage(clock.now() + Duration::from_secs(60))?
+            .is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_nonexistent_token() -> Result<()> {
+        let mut store = Store::default();
+        assert!(store
+            .update_url("abc123", Url::parse("https://example.com")?)
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_url_drops_dedup_entry() -> Result<()> {
+        let mut store = Store {
+            dedup: true,
+            ..Store::default()
+        };
+        let url = Url::parse("https://example1.com")?;
+        let (token, _) = store.register_or_get(url.clone())?;
+
+        store.update_url(token.as_str(), Url::parse("https://example2.com")?)?;
+        assert!(store.register_or_get(url)?.1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_token_length_grows_with_utilization() -> Result<()> {
+        // Never collides: one distinct character per token, repeated to the requested length
+        let alphabet: Vec<char> = ('a'..='z').chain('A'..='Z').chain('0'..='9').collect();
+        let keyspace = alphabet.len();
+        let mut next = 0;
+        let mut store = Store {
+            token_generator: Box::new(move |config| {
+                let token = alphabet[next].to_string().repeat(config.length);
+                next += 1;
+                Token::parse(&token, config)
+            }),
+            ..Store::new(TokenConfig {
+                length: 1,
+                ..Default::default()
+            })
+        };
+
+        let mut tokens = Vec::new();
+        for i in 0..keyspace {
+            let url = Url::parse(&format!("https://example{i}.com"))?;
+            tokens.push((store.register_url(url.clone())?, url));
+        }
+
+        let lengths: Vec<usize> = tokens.iter().map(|(t, _)| t.as_str().len()).collect();
+        assert!(lengths[..keyspace / 2].iter().all(|&len| len == 1));
+        assert!(lengths[keyspace / 2..].iter().all(|&len| len == 2));
+        for (token, url) in &tokens {
+            assert_eq!(&store.resolve_token(token.as_str())?, url);
+        }
         Ok(())
     }
 }
@@ -1,18 +1,183 @@
 use color_eyre::eyre::{self, eyre, Result};
 use rand::Rng;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::HashSet;
 use std::fmt::{self, Display};
+use std::ops::RangeInclusive;
+use std::str::FromStr;
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+/// Serialized as its string; deserializing accepts anything `from_alias` does, since
+/// tokens were valid when saved but the configured shape may have changed since.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+#[serde(into = "String")]
 pub struct Token(String);
 
+/// Set of characters tokens are generated from and validated against.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Charset {
+    #[default]
+    Alphanumeric,
+    /// Alphanumeric minus glyphs that are easy to confuse when typed by hand: `0`, `O`, `1`, `l`, `I`.
+    Unambiguous,
+}
+
+impl Charset {
+    fn chars(&self) -> &'static [u8] {
+        match self {
+            Charset::Alphanumeric => {
+                b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789"
+            }
+            Charset::Unambiguous => b"abcdefghijkmnopqrstuvwxyzABCDEFGHJKLMNPQRSTUVWXYZ23456789",
+        }
+    }
+
+    /// Number of distinct characters tokens are drawn from.
+    pub fn size(&self) -> usize {
+        self.chars().len()
+    }
+
+    pub fn contains(&self, c: char) -> bool {
+        c.is_ascii() && self.chars().contains(&(c as u8))
+    }
+
+    fn sample(&self, rng: &mut impl Rng) -> char {
+        let chars = self.chars();
+        char::from(chars[rng.random_range(0..chars.len())])
+    }
+}
+
+/// How a store picks the next token.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TokenStrategy {
+    /// Random characters, see `Token::generate`.
+    #[default]
+    Random,
+    /// A counter encoded in the charset, see `Token::from_sequence`.
+    /// Never collides and starts at a single character, but reveals how many links exist.
+    Sequential,
+}
+
+impl FromStr for TokenStrategy {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "random" => Ok(TokenStrategy::Random),
+            "sequential" => Ok(TokenStrategy::Sequential),
+            _ => Err(eyre!("Unknown token strategy: {s}")),
+        }
+    }
+}
+
+impl FromStr for Charset {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "alphanumeric" => Ok(Charset::Alphanumeric),
+            "unambiguous" => Ok(Charset::Unambiguous),
+            _ => Err(eyre!("Unknown charset: {s}")),
+        }
+    }
+}
+
+/// Shape of the tokens handed out by a store.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenConfig {
+    /// Length of random tokens; sequential ones are as short as their counter allows.
+    pub length: usize,
+    pub charset: Charset,
+    pub strategy: TokenStrategy,
+    /// Lowercase words never handed out or accepted as aliases, see `is_reserved`.
+    pub reserved: HashSet<String>,
+    /// Lowercase substrings no generated token may contain, see `is_blocked`. Aliases
+    /// are chosen by people and not checked against it.
+    pub blocklist: Vec<String>,
+}
+
+impl TokenConfig {
+    /// Paths served by the router itself, which a token of the same name would shadow.
+    pub const DEFAULT_RESERVED: [&str; 9] = [
+        "admin",
+        "available",
+        "batch",
+        "favicon",
+        "favicon.ico",
+        "healthz",
+        "links",
+        "metrics",
+        "shorten",
+    ];
+
+    /// Whether `token` is one of the reserved words, ignoring case.
+    pub fn is_reserved(&self, token: &str) -> bool {
+        self.reserved.contains(&token.to_ascii_lowercase())
+    }
+
+    /// Whether `token` contains one of the `blocklist` substrings, ignoring case.
+    pub fn is_blocked(&self, token: &str) -> bool {
+        let token = token.to_ascii_lowercase();
+        self.blocklist
+            .iter()
+            .any(|word| token.contains(word.as_str()))
+    }
+}
+
+impl Default for TokenConfig {
+    fn default() -> Self {
+        Self {
+            length: Token::TOKEN_LENGTH,
+            charset: Charset::default(),
+            strategy: TokenStrategy::default(),
+            reserved: Self::DEFAULT_RESERVED
+                .iter()
+                .map(|word| word.to_string())
+                .collect(),
+            blocklist: Vec::new(),
+        }
+    }
+}
+
+/// Why a string is not a valid token, see `Token::parse`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TokenError {
+    /// `expected` is the inclusive range of accepted lengths.
+    WrongLength {
+        expected: RangeInclusive<usize>,
+        got: usize,
+    },
+    /// The first character outside the configured charset.
+    InvalidCharacter(char),
+    /// `Token::generate` drew nothing but blocklisted candidates.
+    Blocked,
+}
+
+impl Display for TokenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenError::WrongLength { expected, got } => write!(
+                f,
+                "Token must be between {} and {} characters long, got {got}",
+                expected.start(),
+                expected.end()
+            ),
+            TokenError::InvalidCharacter(c) => {
+                write!(f, "Token contains {c:?}, which is outside its charset")
+            }
+            TokenError::Blocked => write!(
+                f,
+                "Every token generated in {} attempts was blocklisted",
+                Token::MAX_GENERATE_ATTEMPTS
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TokenError {}
+
 impl Default for Token {
     fn default() -> Self {
-        let str = rand::rng()
-            .sample_iter(&rand::distr::Alphanumeric)
-            .take(Self::TOKEN_LENGTH)
-            .map(char::from)
-            .collect();
-        Self(str)
+        Self::generate_with(&mut rand::rng(), Self::TOKEN_LENGTH)
     }
 }
 
@@ -23,30 +188,165 @@ impl Display for Token {
 }
 
 impl Token {
+    /// Length used when no explicit `TokenConfig` is given.
     const TOKEN_LENGTH: usize = 6;
+    /// Longest a generated token grows to as the keyspace fills up.
+    pub const MAX_TOKEN_LENGTH: usize = 16;
+    pub const ALIAS_MAX_LENGTH: usize = 32;
+    /// Candidates `generate` draws before giving up on the blocklist, as many as the
+    /// stores' `MAX_TOKEN_ATTEMPTS` for an unused token.
+    const MAX_GENERATE_ATTEMPTS: usize = 10;
+
+    /// A random token shaped by `config`. Candidates containing a `config.blocklist`
+    /// substring are drawn again, failing with `TokenError::Blocked` if none is clean.
+    pub fn generate(config: &TokenConfig) -> Result<Self, TokenError> {
+        Self::generate_from(config, &mut rand::rng())
+    }
+
+    /// `generate` drawing from `rng`.
+    fn generate_from<R: Rng>(config: &TokenConfig, rng: &mut R) -> Result<Self, TokenError> {
+        (0..Self::MAX_GENERATE_ATTEMPTS)
+            .map(|_| Self::sample(rng, config.length, config.charset))
+            .find(|token| !config.is_blocked(token.as_str()))
+            .ok_or(TokenError::Blocked)
+    }
+
+    /// `len` characters of the default charset drawn from `rng`, e.g. a seeded `StdRng`
+    /// for reproducible tokens. No blocklist applies.
+    pub fn generate_with<R: Rng>(rng: &mut R, len: usize) -> Self {
+        Self::sample(rng, len, Charset::default())
+    }
+
+    fn sample<R: Rng>(rng: &mut R, len: usize, charset: Charset) -> Self {
+        Self((0..len).map(|_| charset.sample(rng)).collect())
+    }
+
+    /// Encodes `n` in bijective base `charset.size()`, so `0, 1, ..` become
+    /// `a, b, .., 9, aa, ab, ..` and every length is used before growing.
+    pub fn from_sequence(mut n: u64, charset: Charset) -> Self {
+        let chars = charset.chars();
+        let base = chars.len() as u64;
+        let mut encoded = Vec::new();
+        loop {
+            encoded.push(chars[(n % base) as usize]);
+            n /= base;
+            if n == 0 {
+                break;
+            }
+            n -= 1;
+        }
+        encoded.reverse();
+        Self(String::from_utf8(encoded).expect("Charsets are ASCII"))
+    }
+
+    /// Validates a user-chosen alias such as `my-launch`.
+    pub fn from_alias(value: &str) -> Result<Self> {
+        if value.is_empty() || value.len() > Self::ALIAS_MAX_LENGTH {
+            return Err(eyre!(
+                "Alias must be between 1 and {} characters long",
+                Self::ALIAS_MAX_LENGTH
+            ));
+        }
+        if !value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(eyre!("Alias may only contain letters, digits, '-' and '_'"));
+        }
+        Ok(Self(value.to_string()))
+    }
+
+    /// Validates `value` against the charset of `config`. Any length from
+    /// `config.length` up to `MAX_TOKEN_LENGTH` is accepted, since stores
+    /// lengthen new tokens over time while older, shorter ones stay valid.
+    /// Sequential tokens may be as short as a single character.
+    pub fn parse(value: &str, config: &TokenConfig) -> Result<Self, TokenError> {
+        let min_length = match config.strategy {
+            TokenStrategy::Random => config.length,
+            TokenStrategy::Sequential => 1,
+        };
+        let expected = min_length..=config.length.max(Self::MAX_TOKEN_LENGTH);
+        if !expected.contains(&value.len()) {
+            return Err(TokenError::WrongLength {
+                expected,
+                got: value.len(),
+            });
+        }
+        if let Some(c) = value.chars().find(|&c| !config.charset.contains(c)) {
+            return Err(TokenError::InvalidCharacter(c));
+        }
+        Ok(Self(value.to_string()))
+    }
+
+    /// Accepts either a token generated under `config` or an alias.
+    pub fn parse_any(value: &str, config: &TokenConfig) -> Result<Self> {
+        Self::parse(value, config).or_else(|_| Self::from_alias(value))
+    }
+
+    /// Lowercased copy, the canonical form for case-insensitive stores.
+    pub fn to_ascii_lowercase(&self) -> Self {
+        Self(self.0.to_ascii_lowercase())
+    }
 
     pub fn as_str(&self) -> &str {
         &self.0
     }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        self.0.as_bytes()
+    }
+
+    /// The token's bytes as lowercase hex, two digits per byte.
+    pub fn to_hex(&self) -> String {
+        self.as_bytes().iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    /// The token read as a base62 number with `Charset::Alphanumeric` digits, so `a` is 0
+    /// and `9` is 61. `None` for aliases with other characters.
+    pub fn to_base62(&self) -> Option<u128> {
+        let digits = Charset::Alphanumeric.chars();
+        self.as_bytes().iter().try_fold(0u128, |n, b| {
+            let digit = digits.iter().position(|d| d == b)?;
+            n.checked_mul(digits.len() as u128)?
+                .checked_add(digit as u128)
+        })
+    }
 }
 
 impl TryFrom<&str> for Token {
-    type Error = eyre::Error;
+    type Error = TokenError;
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
-        if value.len() != Self::TOKEN_LENGTH {
-            return Err(eyre!(
-                "Token must be {} characters long",
-                Self::TOKEN_LENGTH
-            ));
-        }
-        Ok(Self(value.to_string()))
+        Self::parse(value, &TokenConfig::default())
+    }
+}
+
+impl<'de> Deserialize<'de> for Token {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let token = String::deserialize(deserializer)?;
+        Self::from_alias(&token).map_err(serde::de::Error::custom)
+    }
+}
+
+impl From<Token> for String {
+    fn from(token: Token) -> Self {
+        token.0
+    }
+}
+
+impl FromStr for Token {
+    type Err = TokenError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::try_from(s)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
 
     #[test]
     fn test_token_length() {
@@ -59,6 +359,74 @@ mod tests {
         assert_eq!(token.as_str().len(), Token::TOKEN_LENGTH);
     }
 
+    #[test]
+    fn test_generate_with_seeded_rng() {
+        let token = Token::generate_with(&mut StdRng::seed_from_u64(42), 6);
+        assert_eq!(token.as_str(), "iGpH1N");
+        assert_eq!(
+            Token::generate_with(&mut StdRng::seed_from_u64(42), 6),
+            token
+        );
+        let longer = Token::generate_with(&mut StdRng::seed_from_u64(42), 10);
+        assert_eq!(longer.as_str().len(), 10);
+        assert!(longer.as_str().starts_with("iGpH1N"));
+    }
+
+    #[test]
+    fn test_token_serializes_as_string() -> Result<()> {
+        let token = Token::from_alias("launch")?;
+        assert_eq!(serde_json::to_string(&token)?, r#""launch""#);
+        assert_eq!(serde_json::from_str::<Token>(r#""launch""#)?, token);
+        assert!(serde_json::from_str::<Token>(r#""not a token""#).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_encodings() {
+        let token = Token::from_alias("Z9").unwrap();
+        assert_eq!(token.as_bytes(), b"Z9");
+        assert_eq!(token.to_hex(), "5a39");
+        assert_eq!(token.to_base62(), Some(51 * 62 + 61));
+
+        assert_eq!(Token::from_alias("abc").unwrap().to_base62(), Some(64));
+        assert_eq!(Token::from_alias("my-launch").unwrap().to_base62(), None);
+        // 32 characters overflow even a u128
+        let longest = Token::from_alias(&"9".repeat(Token::ALIAS_MAX_LENGTH)).unwrap();
+        assert_eq!(longest.to_base62(), None);
+    }
+
+    #[test]
+    fn test_generate_skips_blocked_tokens() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let blocked = Token::generate_with(&mut rng, 6);
+        let clean = Token::generate_with(&mut rng, 6);
+        assert_eq!(blocked.as_str(), "iGpH1N");
+
+        let config = TokenConfig {
+            blocklist: vec!["gph".to_string()],
+            ..TokenConfig::default()
+        };
+        assert!(config.is_blocked(blocked.as_str()));
+        assert!(!config.is_blocked(clean.as_str()));
+        let token = Token::generate_from(&config, &mut StdRng::seed_from_u64(42));
+        assert_eq!(token, Ok(clean));
+    }
+
+    #[test]
+    fn test_generate_gives_up_on_blocklist() {
+        // Every single-character token is blocked
+        let config = TokenConfig {
+            length: 1,
+            blocklist: Charset::Alphanumeric
+                .chars()
+                .iter()
+                .map(|&c| char::from(c).to_ascii_lowercase().to_string())
+                .collect(),
+            ..TokenConfig::default()
+        };
+        assert_eq!(Token::generate(&config), Err(TokenError::Blocked));
+    }
+
     #[test]
     fn test_token_generation_is_random() {
         let token1 = Token::default();
@@ -73,8 +441,161 @@ mod tests {
     }
 
     #[test]
-    fn test_try_from_fails_for_longer_strings() {
-        let result = Token::try_from("1234567");
-        assert!(result.is_err());
+    fn test_try_from_accepts_grown_lengths() {
+        assert!(Token::try_from("1234567").is_ok());
+        assert!(Token::try_from("12345").is_err());
+        assert!(Token::try_from("1".repeat(Token::MAX_TOKEN_LENGTH + 1).as_str()).is_err());
+    }
+
+    #[test]
+    fn test_generate_with_custom_length() {
+        let config = TokenConfig {
+            length: 10,
+            ..Default::default()
+        };
+        let token = Token::generate(&config).unwrap();
+        assert_eq!(token.as_str().len(), 10);
+
+        let parsed = Token::parse(token.as_str(), &config).unwrap();
+        assert_eq!(parsed, token);
+    }
+
+    #[test]
+    fn test_parse_validates_against_config_length() {
+        let config = TokenConfig {
+            length: 10,
+            ..Default::default()
+        };
+        assert!(Token::parse("abc123", &config).is_err());
+        assert!(Token::parse("abc123", &TokenConfig::default()).is_ok());
+    }
+
+    #[test]
+    fn test_from_alias() {
+        let token = Token::from_alias("my-launch").unwrap();
+        assert_eq!(token.as_str(), "my-launch");
+    }
+
+    #[test]
+    fn test_from_alias_rejects_invalid_characters() {
+        assert!(Token::from_alias("my launch").is_err());
+        assert!(Token::from_alias("my/launch").is_err());
+    }
+
+    #[test]
+    fn test_from_alias_rejects_wrong_length() {
+        assert!(Token::from_alias("").is_err());
+        assert!(Token::from_alias(&"a".repeat(33)).is_err());
+    }
+
+    #[test]
+    fn test_unambiguous_tokens_skip_confusable_characters() {
+        let config = TokenConfig {
+            length: 64,
+            charset: Charset::Unambiguous,
+            ..Default::default()
+        };
+        for _ in 0..100 {
+            let token = Token::generate(&config).unwrap();
+            assert!(!token.as_str().contains(['0', 'O', '1', 'l', 'I']));
+        }
+    }
+
+    #[test]
+    fn test_parse_rejects_characters_outside_charset() {
+        let config = TokenConfig {
+            charset: Charset::Unambiguous,
+            ..Default::default()
+        };
+        assert!(Token::parse("abc234", &config).is_ok());
+        assert!(Token::parse("abc0OI", &config).is_err());
+        assert!(Token::parse("abc-12", &TokenConfig::default()).is_err());
+    }
+
+    #[test]
+    fn test_from_sequence() {
+        let encode = |n| Token::from_sequence(n, Charset::Alphanumeric).to_string();
+        assert_eq!(encode(0), "a");
+        assert_eq!(encode(1), "b");
+        assert_eq!(encode(26), "A");
+        assert_eq!(encode(61), "9");
+        assert_eq!(encode(62), "aa");
+        assert_eq!(encode(62 + 62 * 62), "aaa");
+        assert_eq!(encode(u64::MAX).len(), 11);
+    }
+
+    #[test]
+    fn test_parse_accepts_short_sequential_tokens() {
+        let config = TokenConfig {
+            strategy: TokenStrategy::Sequential,
+            ..Default::default()
+        };
+        assert!(Token::parse("a", &config).is_ok());
+        assert!(Token::parse("a-", &config).is_err());
+        assert!(Token::parse("a", &TokenConfig::default()).is_err());
+    }
+
+    #[test]
+    fn test_token_strategy_from_str() {
+        assert_eq!(
+            "Sequential".parse::<TokenStrategy>().unwrap(),
+            TokenStrategy::Sequential
+        );
+        assert_eq!(
+            "random".parse::<TokenStrategy>().unwrap(),
+            TokenStrategy::Random
+        );
+        assert!("counter".parse::<TokenStrategy>().is_err());
+    }
+
+    #[test]
+    fn test_charset_from_str() {
+        assert_eq!(
+            "unambiguous".parse::<Charset>().unwrap(),
+            Charset::Unambiguous
+        );
+        assert_eq!(
+            "Alphanumeric".parse::<Charset>().unwrap(),
+            Charset::Alphanumeric
+        );
+        assert!("emoji".parse::<Charset>().is_err());
+    }
+
+    #[test]
+    fn test_parse_reports_wrong_length() {
+        assert_eq!(
+            Token::try_from("abc"),
+            Err(TokenError::WrongLength {
+                expected: 6..=16,
+                got: 3
+            })
+        );
+        assert_eq!(
+            "a".repeat(17).parse::<Token>(),
+            Err(TokenError::WrongLength {
+                expected: 6..=16,
+                got: 17
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_reports_invalid_character() {
+        assert_eq!(
+            Token::try_from("abc-12"),
+            Err(TokenError::InvalidCharacter('-'))
+        );
+        assert_eq!(
+            "abcdé1".parse::<Token>(),
+            Err(TokenError::InvalidCharacter('é'))
+        );
+    }
+
+    #[test]
+    fn test_is_reserved() {
+        let config = TokenConfig::default();
+        assert!(config.is_reserved("healthz"));
+        assert!(config.is_reserved("Admin"));
+        assert!(!config.is_reserved("abc123"));
     }
 }